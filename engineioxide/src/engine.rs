@@ -21,10 +21,11 @@ use crate::{
     sid_generator::Sid,
 };
 use bytes::Buf;
-use futures::{stream::SplitStream, SinkExt, StreamExt, TryStreamExt};
+use futures::{stream::SplitStream, Sink, SinkExt, StreamExt, TryStreamExt};
 use http::{Request, Response, StatusCode};
 use hyper::upgrade::Upgraded;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use tokio::sync::mpsc::Receiver;
 use tokio_tungstenite::{
     tungstenite::{protocol::Role, Message},
     WebSocketStream,
@@ -36,7 +37,7 @@ type SocketMap<T> = RwLock<HashMap<Sid, Arc<T>>>;
 /// It handle all the connection logic and dispatch the packets to the socket
 pub struct EngineIo<H: EngineIoHandler> {
     sockets: SocketMap<Socket<H>>,
-    handler: H,
+    pub(crate) handler: H,
     pub config: EngineIoConfig,
 }
 
@@ -132,9 +133,22 @@ impl<H: EngineIoHandler> EngineIo<H> {
 
         debug!("[sid={sid}] polling request");
         let mut data = String::new();
+        // The last flush marker reached, reported once the response is built
+        let mut flushed = None;
 
         // Send all packets in the buffer
         while let Ok(packet) = rx.try_recv() {
+            let packet = match packet.into_fresh() {
+                Some(Packet::Flush(id)) => {
+                    flushed = Some(id);
+                    continue;
+                }
+                Some(packet) => packet,
+                None => {
+                    debug!("[sid={sid}] dropping expired packet");
+                    continue;
+                }
+            };
             debug!("sending packet: {:?}", packet);
             let packet: String = packet.try_into().unwrap();
             if !data.is_empty() {
@@ -154,7 +168,15 @@ impl<H: EngineIoHandler> EngineIo<H> {
 
         // If there is no packet in the buffer, wait for the next packet
         if data.is_empty() {
-            let packet = rx.recv().await.ok_or(Error::Aborted)?;
+            let packet = loop {
+                let packet = rx.recv().await.ok_or(Error::Aborted)?;
+                match packet.into_fresh() {
+                    // The packets queued before the marker were already written
+                    Some(Packet::Flush(id)) => socket.flushed(id),
+                    Some(packet) => break packet,
+                    None => debug!("[sid={sid}] dropping expired packet"),
+                }
+            };
             let packet: String = packet.try_into().unwrap();
             #[cfg(feature = "v3")]
             {
@@ -165,7 +187,11 @@ impl<H: EngineIoHandler> EngineIo<H> {
             }
             data.push_str(&packet);
         }
-        Ok(http_response(StatusCode::OK, data)?)
+        let res = http_response(StatusCode::OK, data)?;
+        if let Some(id) = flushed {
+            socket.flushed(id);
+        }
+        Ok(res)
     }
 
     /// Handle http polling post request
@@ -196,40 +222,39 @@ impl<H: EngineIoHandler> EngineIo<H> {
         let packets = Payload::new(protocol, body.reader());
 
         for p in packets {
-            let raw_packet = p.map_err(|e| {
-                debug!("error parsing packets: {:?}", e);
-                self.close_session(sid);
-                Error::HttpErrorResponse(StatusCode::BAD_REQUEST)
-            })?;
-
-            match Packet::try_from(raw_packet) {
-                Ok(Packet::Close) => {
+            let packet = match decode_payload_packet(sid, p) {
+                Ok(Some(packet)) => packet,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("error parsing packets: {:?}", e);
+                    self.close_session(sid);
+                    return Err(Error::HttpErrorResponse(StatusCode::BAD_REQUEST));
+                }
+            };
+
+            match packet {
+                Packet::Close => {
                     debug!("[sid={sid}] closing session");
                     socket.send(Packet::Noop)?;
                     self.close_session(sid);
                     break;
                 }
-                Ok(Packet::Pong) | Ok(Packet::Ping) => socket
+                Packet::Pong | Packet::Ping => socket
                     .heartbeat_tx
                     .try_send(())
                     .map_err(|_| Error::HeartbeatTimeout),
-                Ok(Packet::Message(msg)) => {
+                Packet::Message(msg) => {
                     self.handler.on_message(msg, &socket);
                     Ok(())
                 }
-                Ok(Packet::Binary(bin)) | Ok(Packet::BinaryV3(bin)) => {
+                Packet::Binary(bin) | Packet::BinaryV3(bin) => {
                     self.handler.on_binary(bin, &socket);
                     Ok(())
                 }
-                Ok(p) => {
+                p => {
                     debug!("[sid={sid}] bad packet received: {:?}", &p);
                     Err(Error::BadPacket(p))
                 }
-                Err(e) => {
-                    debug!("[sid={sid}] error parsing packet: {:?}", e);
-                    self.close_session(sid);
-                    return Err(e);
-                }
             }?;
         }
         Ok(http_response(StatusCode::OK, "ok")?)
@@ -321,23 +346,7 @@ impl<H: EngineIoHandler> EngineIo<H> {
         let rx_socket = socket.clone();
         let rx_handle = tokio::spawn(async move {
             let mut socket_rx = rx_socket.internal_rx.try_lock().unwrap();
-            while let Some(item) = socket_rx.recv().await {
-                let res = match item {
-                    Packet::Binary(bin) | Packet::BinaryV3(bin) => {
-                        tx.send(Message::Binary(bin)).await
-                    }
-                    Packet::Close => tx.send(Message::Close(None)).await,
-                    _ => {
-                        let packet: String = item.try_into().unwrap();
-                        tx.send(Message::Text(packet)).await
-                    }
-                };
-                debug!("[sid={}] sent packet", rx_socket.sid);
-                if let Err(e) = res {
-                    debug!("[sid={}] error sending packet: {}", rx_socket.sid, e);
-                    break;
-                }
-            }
+            ws_forward_to_client(&rx_socket, &mut socket_rx, &mut tx).await;
         });
 
         self.handler.on_connect(&socket);
@@ -489,6 +498,7 @@ impl<H: EngineIoHandler> EngineIo<H> {
         if let Some(socket) = socket {
             self.handler.on_disconnect(&socket);
             socket.abort_heartbeat();
+            socket.flush_handle().close();
             debug!(
                 "remaining sockets: {:?}",
                 self.sockets.read().unwrap().len()
@@ -505,11 +515,94 @@ impl<H: EngineIoHandler> EngineIo<H> {
     }
 }
 
+/// Convert a [`Packet`] to a websocket [`Message`]
+///
+/// Binary packets are sent as binary frames, they are only base64 encoded with the polling transport
+fn ws_message(packet: Packet) -> Message {
+    match packet {
+        Packet::Binary(bin) | Packet::BinaryV3(bin) => Message::Binary(bin),
+        Packet::Close => Message::Close(None),
+        _ => Message::Text(packet.try_into().unwrap()),
+    }
+}
+
+/// Write the packets queued for a socket to its websocket,
+/// until the socket channel is closed or the websocket cannot be written.
+///
+/// The packets already queued are written together and flushed only once
+async fn ws_forward_to_client<H, S>(
+    socket: &Socket<H>,
+    socket_rx: &mut Receiver<Packet>,
+    tx: &mut S,
+) where
+    H: EngineIoHandler,
+    S: Sink<Message> + Unpin,
+    S::Error: Display,
+{
+    while let Some(item) = socket_rx.recv().await {
+        let mut res = Ok(());
+        let mut flushed = None;
+        let mut next = Some(item);
+        while let Some(item) = next {
+            match item.into_fresh() {
+                Some(Packet::Flush(id)) => flushed = Some(id),
+                Some(item) => res = tx.feed(ws_message(item)).await,
+                None => debug!("[sid={}] dropping expired packet", socket.sid),
+            }
+            next = match res {
+                Ok(()) => socket_rx.try_recv().ok(),
+                Err(_) => None,
+            };
+        }
+        if res.is_ok() {
+            res = tx.flush().await;
+        }
+        debug!("[sid={}] sent packets", socket.sid);
+        if let Err(e) = res {
+            debug!("[sid={}] error sending packet: {}", socket.sid, e);
+            break;
+        }
+        if let Some(id) = flushed {
+            socket.flushed(id);
+        }
+    }
+}
+
+/// Decode a raw packet extracted from a polling payload.
+///
+/// A malformed packet is skipped so that the other packets of the batch can still be dispatched.
+/// Only framing errors, which make the rest of the payload unreadable, are returned.
+fn decode_payload_packet(
+    sid: Sid,
+    raw_packet: Result<String, Error>,
+) -> Result<Option<Packet>, Error> {
+    let raw_packet = match raw_packet {
+        Ok(raw_packet) => raw_packet,
+        Err(Error::Utf8(e)) => {
+            debug!("[sid={sid}] skipping malformed packet: {:?}", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    match Packet::try_from(raw_packet) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(e) => {
+            debug!("[sid={sid}] skipping malformed packet: {:?}", e);
+            Ok(None)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+
     use async_trait::async_trait;
 
     use super::*;
+    use crate::SendPacket;
 
     #[derive(Debug, Clone)]
     struct MockHandler;
@@ -536,4 +629,152 @@ mod tests {
             socket.emit_binary(data).ok();
         }
     }
+
+    #[tokio::test]
+    async fn test_polling_binary_base64() {
+        let engine = Arc::new(EngineIo::new(MockHandler, EngineIoConfig::default()));
+        let sid: Sid = 1i64.into();
+        let socket = Socket::new(
+            sid,
+            ProtocolVersion::V4,
+            ConnectionType::Http,
+            &engine.config,
+            SocketReq {
+                uri: http::Uri::default(),
+                headers: http::HeaderMap::new(),
+            },
+            Box::new(|_sid: Sid| {}),
+        );
+        let socket = Arc::new(socket);
+        engine.sockets.write().unwrap().insert(sid, socket.clone());
+        assert_eq!(socket.transport(), TransportType::Polling);
+        let transport = socket.transport_handle();
+        assert_eq!(transport.transport(), TransportType::Polling);
+
+        socket.emit_binary(vec![1, 2, 3]).unwrap();
+        let res = engine
+            .on_polling_http_req::<http_body::Full<bytes::Bytes>>(ProtocolVersion::V4, sid)
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"bAQID");
+
+        // The handle follows the upgrade of the socket
+        socket.upgrade_to_websocket();
+        assert_eq!(transport.transport(), TransportType::Websocket);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_binary_frame() {
+        let socket: Socket<MockHandler> = Socket::new_dummy(1i64.into(), Box::new(|_sid: Sid| {}));
+        assert_eq!(socket.transport(), TransportType::Websocket);
+
+        assert_eq!(
+            ws_message(Packet::Binary(vec![1, 2, 3])),
+            Message::Binary(vec![1, 2, 3])
+        );
+        assert_eq!(
+            ws_message(Packet::Message("foo".to_string())),
+            Message::Text("4foo".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_waits_for_write() {
+        let socket: Socket<MockHandler> = Socket::new_dummy(1i64.into(), Box::new(|_sid: Sid| {}));
+        socket
+            .tx
+            .try_send(SendPacket::Message("foo".to_string()))
+            .unwrap();
+        let handle = socket.flush_handle();
+        let mut flush = Box::pin(handle.flush());
+        assert!(futures::poll!(&mut flush).is_pending());
+
+        // The transport takes the packets out of the queue, a marker follows the pending ones
+        let mut rx = socket.internal_rx.try_lock().unwrap();
+        assert_eq!(rx.recv().await, Some(Packet::Message("foo".to_string())));
+        assert_eq!(rx.recv().await, Some(Packet::Flush(1)));
+        // They are not written yet
+        assert!(futures::poll!(&mut flush).is_pending());
+
+        socket.flushed(1);
+        flush.await;
+    }
+
+    /// A sink recording the messages written at each flush
+    #[derive(Default)]
+    struct RecordingSink {
+        pending: Vec<Message>,
+        flushed: Vec<Vec<Message>>,
+    }
+
+    impl Sink<Message> for RecordingSink {
+        type Error = std::convert::Infallible;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+            self.pending.push(item);
+            Ok(())
+        }
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            let pending = std::mem::take(&mut self.pending);
+            self.flushed.push(pending);
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_flushes_queued_packets_once() {
+        let socket: Socket<MockHandler> = Socket::new_dummy(1i64.into(), Box::new(|_sid: Sid| {}));
+        let handle = socket.flush_handle();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        tx.try_send(Packet::Message("foo".to_string())).unwrap();
+        tx.try_send(Packet::Binary(vec![1, 2, 3])).unwrap();
+        let expired = Packet::Message("bar".to_string());
+        tx.try_send(Packet::Expiring(Box::new(expired), Instant::now()))
+            .unwrap();
+        tx.try_send(Packet::Flush(1)).unwrap();
+        drop(tx);
+
+        let mut sink = RecordingSink::default();
+        ws_forward_to_client(&socket, &mut rx, &mut sink).await;
+        // The queued packets are written together and flushed once, the expired one is dropped
+        assert_eq!(
+            sink.flushed,
+            [vec![
+                Message::Text("4foo".to_string()),
+                Message::Binary(vec![1, 2, 3])
+            ]]
+        );
+        // The marker queued after them is reported once they are flushed
+        assert!(futures::poll!(Box::pin(handle.flush())).is_ready());
+    }
+
+    #[test]
+    fn test_decode_payload_skips_malformed_packet() {
+        let data = "4foo\x1ezzz\x1e4bar".as_bytes();
+        let packets: Vec<Packet> = Payload::new(ProtocolVersion::V4, data)
+            .map(|p| decode_payload_packet(1i64.into(), p))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        assert_eq!(
+            packets,
+            [
+                Packet::Message("foo".to_string()),
+                Packet::Message("bar".to_string())
+            ]
+        );
+    }
 }
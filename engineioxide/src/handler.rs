@@ -15,6 +15,8 @@ pub trait EngineIoHandler: std::fmt::Debug + Send + Sync + Clone + 'static {
     fn on_disconnect(&self, socket: &Socket<Self>);
 
     /// Called when a message is received from the client.
+    ///
+    /// The `4` message prefix of the engine.io packet is already stripped.
     fn on_message(&self, msg: String, socket: &Socket<Self>);
 
     /// Called when a binary message is received from the client.
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+use std::time::Instant;
+
 use base64::{engine::general_purpose, Engine};
 use bytes::Bytes;
 use serde::{de::Error, Deserialize, Serialize};
@@ -11,7 +14,11 @@ use crate::{config::EngineIoConfig, service::TransportType};
 #[derive(Debug)]
 pub enum SendPacket {
     Message(String),
+    /// A message shared between several sockets, like a broadcast, which is only copied when it is written
+    SharedMessage(Arc<str>),
     Binary(Vec<u8>),
+    /// A packet that is dropped if it is not handed to the transport before the given deadline
+    Expiring(Box<SendPacket>, Instant),
 }
 
 /// A Packet type to use when receiving and sending data from the client
@@ -35,6 +42,8 @@ pub enum Packet {
 
     /// Message packet used to send a message to the client
     Message(String),
+    /// Message packet shared between several sockets, it is serialized as a [`Packet::Message`]
+    SharedMessage(Arc<str>), // Not part of the protocol, used internally
     /// Upgrade packet to upgrade the connection from polling to websocket
     Upgrade,
 
@@ -56,6 +65,28 @@ pub enum Packet {
     ///
     /// This is a special packet, excepionally specific to the V3 protocol.
     BinaryV3(Vec<u8>), // Not part of the protocol, used internally
+
+    /// A packet that is dropped if it is not handed to the transport before the given deadline
+    ///
+    /// It is serialized as the wrapped packet
+    Expiring(Box<Packet>, Instant), // Not part of the protocol, used internally
+
+    /// A marker queued by [`FlushHandle::flush`](crate::socket::FlushHandle::flush)
+    ///
+    /// It is never sent, the transport reports it once the packets queued before it are written
+    Flush(u64), // Not part of the protocol, used internally
+}
+
+impl Packet {
+    /// Unwraps an [`Packet::Expiring`] packet.
+    /// Returns `None` if its deadline is passed and it should be dropped
+    pub(crate) fn into_fresh(self) -> Option<Packet> {
+        match self {
+            Packet::Expiring(packet, deadline) if Instant::now() < deadline => packet.into_fresh(),
+            Packet::Expiring(..) => None,
+            packet => Some(packet),
+        }
+    }
 }
 
 /// Serialize a [Packet] to a [String] according to the Engine.IO protocol
@@ -72,10 +103,15 @@ impl TryInto<String> for Packet {
             Packet::PingUpgrade => "2probe".to_string(),
             Packet::PongUpgrade => "3probe".to_string(),
             Packet::Message(msg) => "4".to_string() + &msg,
+            Packet::SharedMessage(msg) => "4".to_string() + &msg,
             Packet::Upgrade => "5".to_string(),
             Packet::Noop => "6".to_string(),
             Packet::Binary(data) => "b".to_string() + &general_purpose::STANDARD.encode(data),
             Packet::BinaryV3(data) => "b4".to_string() + &general_purpose::STANDARD.encode(data),
+            Packet::Expiring(packet, _) => (*packet).try_into()?,
+            Packet::Flush(_) => Err(serde_json::Error::custom(
+                "Flush markers are not serialized",
+            ))?,
         };
         Ok(res)
     }
@@ -147,7 +183,11 @@ impl From<SendPacket> for Packet {
     fn from(value: SendPacket) -> Packet {
         match value {
             SendPacket::Message(msg) => Packet::Message(msg),
+            SendPacket::SharedMessage(msg) => Packet::SharedMessage(msg),
             SendPacket::Binary(data) => Packet::Binary(data),
+            SendPacket::Expiring(packet, deadline) => {
+                Packet::Expiring(Box::new((*packet).into()), deadline)
+            }
         }
     }
 }
@@ -223,6 +263,16 @@ mod tests {
         assert_eq!(packet_str, "4hello");
     }
 
+    #[test]
+    fn test_shared_message_packet() {
+        let msg: Arc<str> = "hello".into();
+        let packet: Packet = SendPacket::SharedMessage(msg.clone()).into();
+        let packet_str: String = packet.try_into().unwrap();
+        assert_eq!(packet_str, "4hello");
+        // The serialized packet is a copy, the message is still shared
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
+
     #[test]
     fn test_message_packet_deserialize() {
         let packet_str = "4hello".to_string();
@@ -230,6 +280,14 @@ mod tests {
         assert_eq!(packet, Packet::Message("hello".to_string()));
     }
 
+    #[test]
+    fn test_message_packet_socketio_payload() {
+        // The `4` message prefix is stripped, the socket.io packet is handed over as is
+        let packet_str = r#"42["event",1]"#.to_string();
+        let packet: Packet = packet_str.try_into().unwrap();
+        assert_eq!(packet, Packet::Message(r#"2["event",1]"#.to_string()));
+    }
+
     #[test]
     fn test_binary_packet() {
         let packet = Packet::Binary(vec![1, 2, 3]);
@@ -268,4 +326,18 @@ mod tests {
         let packet: Packet = packet.into();
         assert_eq!(packet, Packet::Binary(vec![1, 2, 3]));
     }
+
+    #[test]
+    fn test_expiring_packet_into_fresh() {
+        let deadline = Instant::now() + std::time::Duration::from_secs(10);
+        let packet = SendPacket::Expiring(Box::new(SendPacket::Message("hello".into())), deadline);
+        let packet: Packet = packet.into();
+        assert_eq!(
+            packet.into_fresh(),
+            Some(Packet::Message("hello".to_string()))
+        );
+
+        let packet = Packet::Expiring(Box::new(Packet::Message("hello".into())), Instant::now());
+        assert_eq!(packet.into_fresh(), None);
+    }
 }
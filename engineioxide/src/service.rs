@@ -55,6 +55,11 @@ impl<S: Clone, H: EngineIoHandler> EngineIoService<H, S> {
         }
     }
 
+    /// Get a reference to the [`EngineIoHandler`] of this service.
+    pub fn handler(&self) -> &H {
+        &self.engine.handler
+    }
+
     /// Convert this [`EngineIoService`] into a [`MakeEngineIoService`].
     /// This is useful when using [`EngineIoService`] without layers.
     pub fn into_make_service(self) -> MakeEngineIoService<H, S> {
@@ -194,7 +199,7 @@ where
 }
 
 /// The type of the transport used by the client.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TransportType {
     Websocket,
     Polling,
@@ -1,6 +1,6 @@
 use std::{
     sync::{
-        atomic::{AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
         Arc,
     },
     time::Duration,
@@ -8,15 +8,20 @@ use std::{
 
 use http::{request::Parts, Uri};
 use tokio::{
-    sync::{mpsc, mpsc::Receiver, Mutex},
+    sync::{mpsc, mpsc::Receiver, Mutex, Notify},
     task::JoinHandle,
 };
 use tracing::debug;
 
 use crate::sid_generator::Sid;
 use crate::{
-    config::EngineIoConfig, errors::Error, handler::EngineIoHandler, packet::Packet,
-    service::ProtocolVersion, utils::forward_map_chan, SendPacket,
+    config::EngineIoConfig,
+    errors::Error,
+    handler::EngineIoHandler,
+    packet::Packet,
+    service::{ProtocolVersion, TransportType},
+    utils::forward_packets,
+    SendPacket,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -54,6 +59,119 @@ impl From<Parts> for SocketReq {
     }
 }
 
+/// A handle to wait for the packets queued on a [`Socket`] to be written by the transport.
+///
+/// It can be cloned and kept independently from the socket it was created from.
+/// A default handle is not bound to any socket and is always considered flushed.
+#[derive(Debug, Clone, Default)]
+pub struct FlushHandle {
+    inner: Option<Arc<FlushInner>>,
+}
+
+#[derive(Debug)]
+struct FlushInner {
+    /// The id of the last flush request and the channel to hand the requests to the forwarding task,
+    /// locked together so that the markers are queued in the order of their ids
+    requests: std::sync::Mutex<(u64, mpsc::UnboundedSender<u64>)>,
+    /// The id of the last [`Packet::Flush`] marker reported by the transport
+    flushed: AtomicU64,
+    /// Set once the socket is closed, the packets still queued will never be written
+    closed: AtomicBool,
+    /// Notified each time the transport reports a marker or the socket is closed
+    notify: Notify,
+}
+
+impl FlushHandle {
+    fn new(requests: mpsc::UnboundedSender<u64>) -> Self {
+        Self {
+            inner: Some(Arc::new(FlushInner {
+                requests: std::sync::Mutex::new((0, requests)),
+                flushed: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+                notify: Notify::new(),
+            })),
+        }
+    }
+
+    /// Waits until all the packets queued when calling this method have been written by the transport.
+    ///
+    /// A marker is queued behind the pending packets, it takes a slot in the socket buffer until
+    /// the transport reaches it. The method also returns if the socket is closed in the meantime.
+    ///
+    /// ⚠️ It only guarantees that the packets were written to the OS socket buffer (websocket)
+    /// or to a polling response, **not** that the client received them.
+    pub async fn flush(&self) {
+        let Some(inner) = &self.inner else { return };
+        let id = {
+            let mut requests = inner.requests.lock().unwrap();
+            let id = requests.0 + 1;
+            if requests.1.send(id).is_err() {
+                return;
+            }
+            requests.0 = id;
+            id
+        };
+        loop {
+            // The future must be created before checking the state to not miss any notification
+            let notified = inner.notify.notified();
+            if inner.flushed.load(Ordering::SeqCst) >= id || inner.closed.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Report that the packets queued before the given marker were written by the transport
+    pub(crate) fn flushed(&self, id: u64) {
+        if let Some(inner) = &self.inner {
+            inner.flushed.fetch_max(id, Ordering::SeqCst);
+            inner.notify.notify_waiters();
+        }
+    }
+
+    /// Release the waiting tasks, the packets still queued will never be written
+    pub(crate) fn close(&self) {
+        if let Some(inner) = &self.inner {
+            inner.closed.store(true, Ordering::SeqCst);
+            inner.notify.notify_waiters();
+        }
+    }
+}
+
+/// A handle to read the [`TransportType`] currently used by a [`Socket`].
+///
+/// It can be cloned and kept independently from the socket it was created from,
+/// and it follows the socket if the client upgrades the connection.
+/// A default handle is not bound to any socket and always reports [`TransportType::Polling`].
+#[derive(Debug, Clone, Default)]
+pub struct TransportHandle {
+    conn: Arc<AtomicU8>,
+}
+
+impl TransportHandle {
+    /// Returns the [`TransportType`] currently used by the socket
+    pub fn transport(&self) -> TransportType {
+        if self.conn.load(Ordering::Relaxed) == ConnectionType::WebSocket as u8 {
+            TransportType::Websocket
+        } else {
+            TransportType::Polling
+        }
+    }
+}
+
+/// Create a handle that is not bound to any socket and always reports the given transport
+impl From<TransportType> for TransportHandle {
+    fn from(transport: TransportType) -> Self {
+        let conn = match transport {
+            TransportType::Websocket => ConnectionType::WebSocket,
+            TransportType::Polling => ConnectionType::Http,
+        };
+        Self {
+            conn: Arc::new(AtomicU8::new(conn as u8)),
+        }
+    }
+}
+
 /// A [`Socket`] represents a connection to the server.
 /// It is agnostic to the [`TransportType`](crate::service::TransportType).
 /// It handles :
@@ -73,8 +191,8 @@ where
 
     /// The connection type represented as a bitfield
     /// It is represented as a bitfield to allow the use of an [`AtomicU8`] so it can be shared between threads
-    /// without any mutex
-    conn: AtomicU8,
+    /// without any mutex. It is shared with the [`TransportHandle`]s of the socket.
+    conn: Arc<AtomicU8>,
 
     /// Channel to receive [`Packet`] from the connection
     ///
@@ -98,6 +216,9 @@ where
     /// Handle to the heartbeat job so that it can be aborted when the socket is closed
     heartbeat_handle: Mutex<Option<JoinHandle<()>>>,
 
+    /// Handle notified each time the transport writes the packets queued before a flush marker
+    flush: FlushHandle,
+
     /// Function to call when the socket is closed
     close_fn: Box<dyn Fn(Sid) + Send + Sync>,
     /// User data bound to the socket
@@ -123,12 +244,15 @@ where
         let (tx, rx) = mpsc::channel(config.max_buffer_size);
         let (heartbeat_tx, heartbeat_rx) = mpsc::channel(1);
 
-        tokio::spawn(forward_map_chan(rx, internal_tx.clone(), SendPacket::into));
+        let (flush_tx, flush_rx) = mpsc::unbounded_channel();
+        let flush = FlushHandle::new(flush_tx);
+
+        tokio::spawn(forward_packets(rx, flush_rx, internal_tx.clone()));
 
         Self {
             sid,
             protocol,
-            conn: AtomicU8::new(conn as u8),
+            conn: Arc::new(AtomicU8::new(conn as u8)),
 
             internal_rx: Mutex::new(internal_rx),
             internal_tx,
@@ -137,6 +261,7 @@ where
             heartbeat_rx: Mutex::new(heartbeat_rx),
             heartbeat_tx,
             heartbeat_handle: Mutex::new(None),
+            flush,
             close_fn,
 
             data: H::Data::default(),
@@ -272,6 +397,24 @@ where
         self.conn.load(Ordering::Relaxed) == ConnectionType::Http as u8
     }
 
+    /// Returns the [`TransportType`] currently used by the [`Socket`]
+    ///
+    /// It may change from [`TransportType::Polling`] to [`TransportType::Websocket`] if the client upgrades the connection.
+    pub fn transport(&self) -> TransportType {
+        if self.is_ws() {
+            TransportType::Websocket
+        } else {
+            TransportType::Polling
+        }
+    }
+
+    /// Returns a [`TransportHandle`] to read the transport of the socket without keeping a reference to it.
+    pub fn transport_handle(&self) -> TransportHandle {
+        TransportHandle {
+            conn: self.conn.clone(),
+        }
+    }
+
     /// Sets the [`ConnectionType`] to WebSocket
     /// Used when the client upgrade the connection from HTTP to WebSocket
     pub(crate) fn upgrade_to_websocket(&self) {
@@ -290,6 +433,24 @@ where
         self.send(Packet::Message(msg))
     }
 
+    /// Returns a [`FlushHandle`] to wait for the queued packets to be written by the transport.
+    pub fn flush_handle(&self) -> FlushHandle {
+        self.flush.clone()
+    }
+
+    /// Waits until all the packets queued on this socket have been written by the transport.
+    ///
+    /// ⚠️ It only guarantees that the packets were written to the OS socket buffer (websocket)
+    /// or to a polling response, **not** that the client received them.
+    pub async fn flush(&self) {
+        self.flush.flush().await
+    }
+
+    /// Report to the [`FlushHandle`] that the packets queued before the given marker were written
+    pub(crate) fn flushed(&self, id: u64) {
+        self.flush.flushed(id);
+    }
+
     /// Immediately closes the socket and the underlying connection.
     /// The socket will be removed from the `Engine` and the [`Handler`](crate::handler::EngineIoHandler) will be notified.
     pub fn close(&self) {
@@ -303,6 +464,9 @@ where
     ///
     /// If the transport is in polling mode, the message is buffered and sent as a text frame **encoded in base64** to the next polling request.
     ///
+    /// The encoding is chosen when the message is handed to the transport rather than when it is queued,
+    /// so a message queued before an upgrade to websocket is still encoded correctly.
+    ///
     /// ⚠️ If the buffer is full or the socket is disconnected, an error will be returned
     pub fn emit_binary(&self, data: Vec<u8>) -> Result<(), Error> {
         if self.protocol == ProtocolVersion::V3 {
@@ -322,12 +486,15 @@ impl<H: EngineIoHandler> Socket<H> {
         let (tx, rx) = mpsc::channel(200);
         let (heartbeat_tx, heartbeat_rx) = mpsc::channel(1);
 
-        tokio::spawn(forward_map_chan(rx, internal_tx.clone(), SendPacket::into));
+        let (flush_tx, flush_rx) = mpsc::unbounded_channel();
+        let flush = FlushHandle::new(flush_tx);
+
+        tokio::spawn(forward_packets(rx, flush_rx, internal_tx.clone()));
 
         Self {
             sid,
             protocol: ProtocolVersion::V4,
-            conn: AtomicU8::new(ConnectionType::WebSocket as u8),
+            conn: Arc::new(AtomicU8::new(ConnectionType::WebSocket as u8)),
 
             internal_rx: Mutex::new(internal_rx),
             internal_tx,
@@ -336,6 +503,7 @@ impl<H: EngineIoHandler> Socket<H> {
             heartbeat_rx: Mutex::new(heartbeat_rx),
             heartbeat_tx,
             heartbeat_handle: Mutex::new(None),
+            flush,
             close_fn,
 
             data: H::Data::default(),
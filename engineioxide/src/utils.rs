@@ -1,16 +1,39 @@
+use std::task::Poll;
+
+use futures::future::{poll_fn, Either};
 use tokio::sync::mpsc::{self, error::SendError};
 
-/// Forwards items from a channel to another channel, applying a map function to each item
+use crate::{packet::Packet, SendPacket};
+
+/// Forwards the packets of the public channel to the internal channel of a socket
+///
+/// A flush request queues a [`Packet::Flush`] marker behind the packets already sent to the public channel.
 ///
-/// Returns an error if the sending channel is full
-pub async fn forward_map_chan<T, R>(
-    mut from: mpsc::Receiver<T>,
-    to: mpsc::Sender<R>,
-    map: impl Fn(T) -> R,
-) -> Result<(), SendError<R>> {
-    while let Some(item) = from.recv().await {
-        let item = map(item);
-        to.send(item).await?;
+/// Returns an error if the internal channel is closed
+pub async fn forward_packets(
+    mut from: mpsc::Receiver<SendPacket>,
+    mut flush_requests: mpsc::UnboundedReceiver<u64>,
+    to: mpsc::Sender<Packet>,
+) -> Result<(), SendError<Packet>> {
+    loop {
+        let next = poll_fn(|cx| match from.poll_recv(cx) {
+            Poll::Ready(packet) => Poll::Ready(packet.map(Either::Left)),
+            Poll::Pending => match flush_requests.poll_recv(cx) {
+                Poll::Ready(Some(id)) => Poll::Ready(Some(Either::Right(id))),
+                _ => Poll::Pending,
+            },
+        })
+        .await;
+        match next {
+            Some(Either::Left(packet)) => to.send(packet.into()).await?,
+            Some(Either::Right(id)) => {
+                // A packet may have been sent right before the flush request was received
+                while let Ok(packet) = from.try_recv() {
+                    to.send(packet.into()).await?;
+                }
+                to.send(Packet::Flush(id)).await?;
+            }
+            None => return Ok(()),
+        }
     }
-    Ok(())
 }
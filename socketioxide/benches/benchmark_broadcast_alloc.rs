@@ -0,0 +1,138 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::Value;
+use socketioxide::adapter::LocalAdapter;
+use socketioxide::testing::connect_socket;
+use socketioxide::Namespace;
+
+const BROADCASTS: usize = 10_000;
+
+/// A global allocator counting the allocations
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// A criterion measurement of the number of allocations
+pub struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+    fn end(&self, start: usize) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+    fn zero(&self) -> usize {
+        0
+    }
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+pub struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _: f64, _: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+    fn scale_throughputs(
+        &self,
+        _: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(elems) = throughput {
+            for value in values {
+                *value /= *elems as f64;
+            }
+        }
+        "allocs/elem"
+    }
+    fn scale_for_machines(&self, _: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+pub fn criterion_benchmark(c: &mut Criterion<Allocations>) {
+    // The connect handlers are spawned on the runtime
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let _guard = rt.enter();
+
+    let mut group = c.benchmark_group("10k broadcasts");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BROADCASTS as u64));
+    for socket_count in [1, 10, 100] {
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| Box::pin(async move {})));
+        let (sockets, mut receivers): (Vec<_>, Vec<_>) = (0..socket_count)
+            .map(|i| connect_socket(&ns, (i as i64).into(), Value::Null))
+            .unzip();
+        for socket in &sockets {
+            socket.join("live").unwrap();
+        }
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{socket_count} sockets")),
+            &sockets[0],
+            |b, socket| {
+                b.iter(|| {
+                    for _ in 0..BROADCASTS {
+                        socket.within("live").emit("news", "hello").unwrap();
+                        // The sent packets are dropped to keep the socket buffers from filling up
+                        for rx in &mut receivers {
+                            while rx.try_recv().is_ok() {}
+                        }
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn allocations() -> Criterion<Allocations> {
+    // The allocation counts do not vary, which the plots cannot draw
+    Criterion::default()
+        .with_measurement(Allocations)
+        .without_plots()
+}
+
+criterion_group! {
+    name = benches;
+    config = allocations();
+    targets = criterion_benchmark
+}
+criterion_main!(benches);
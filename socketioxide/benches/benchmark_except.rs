@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::FutureExt;
+use socketioxide::adapter::{Adapter, BroadcastOptions, LocalAdapter};
+use socketioxide::Namespace;
+
+const EXCEPT_ROOMS: usize = 100;
+const SOCKETS_PER_ROOM: usize = 100;
+
+/// Create an adapter where every socket is in the "target" room and in one of the except rooms
+fn create_adapter() -> (Arc<Namespace<LocalAdapter>>, LocalAdapter) {
+    let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+    let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+    for i in 0..EXCEPT_ROOMS * SOCKETS_PER_ROOM {
+        let rooms = vec![
+            "target".to_string(),
+            format!("except-{}", i / SOCKETS_PER_ROOM),
+        ];
+        adapter.add_all((i as i64).into(), rooms).unwrap();
+    }
+    (ns, adapter)
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    c.bench_function("broadcast with 100 except rooms", |b| {
+        let (_ns, adapter) = create_adapter();
+        let except: Vec<String> = (0..EXCEPT_ROOMS).map(|i| format!("except-{i}")).collect();
+        b.iter_batched(
+            || {
+                BroadcastOptions::new(0i64.into())
+                    .within("target")
+                    .except(except.clone())
+            },
+            |opts| adapter.fetch_sockets(black_box(opts)).unwrap(),
+            criterion::BatchSize::SmallInput,
+        );
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
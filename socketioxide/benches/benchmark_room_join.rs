@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use socketioxide::adapter::{Adapter, LocalAdapter};
+use socketioxide::testing::build_ns;
+use socketioxide::Namespace;
+
+const ROOM_SIZE: usize = 10_000;
+
+/// Create a namespace whose rooms are created with the given capacity
+fn create_ns(room_capacity: usize) -> Arc<Namespace<LocalAdapter>> {
+    let builder = Namespace::ns_builder("/")
+        .room_capacity(room_capacity)
+        .connect(|_| async move {});
+    build_ns(builder).unwrap()
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("10k sockets joining a room");
+    for room_capacity in [0, ROOM_SIZE] {
+        let ns = create_ns(room_capacity);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("capacity {room_capacity}")),
+            &ns,
+            |b, ns| {
+                b.iter_batched(
+                    || LocalAdapter::new(Arc::downgrade(ns)),
+                    |adapter| {
+                        for i in 0..ROOM_SIZE {
+                            adapter.add_all((i as i64).into(), "live").unwrap();
+                        }
+                        black_box(adapter)
+                    },
+                    criterion::BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
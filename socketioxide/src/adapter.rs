@@ -2,54 +2,164 @@
 //! When a socket joins or leaves a room, the adapter is responsible for updating the state.
 //! The default adapter is the [`LocalAdapter`], which stores the state in memory.
 //! Other adapters can be made to share the state between multiple servers.
+//! The [`CompositeAdapter`] writes to two adapters at once, for example to migrate from one to the other.
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     convert::Infallible,
-    sync::{Arc, RwLock, Weak},
-    time::Duration,
+    ops::Deref,
+    sync::{Arc, Mutex, RwLock, Weak},
+    task::Poll,
+    time::{Duration, Instant},
 };
 
-use engineioxide::sid_generator::Sid;
+use engineioxide::{service::TransportType, sid_generator::Sid};
 use futures::{
     stream::{self, BoxStream},
     StreamExt,
 };
 use itertools::Itertools;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::{
-    errors::{AckError, AdapterError, BroadcastError},
+    buffer_pool,
+    errors::{AckError, AdapterError, BroadcastError, Error},
     handler::AckResponse,
     ns::Namespace,
     operators::RoomParam,
     packet::Packet,
-    socket::Socket,
+    socket::{DisconnectReason, Socket, SocketState},
 };
 
 /// A room identifier
 pub type Room = String;
 
 /// Flags that can be used to modify the behavior of the broadcast methods.
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+///
+/// The durations are serialized as a number of milliseconds.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BroadcastFlags {
     /// Broadcast only to the current server
     Local,
     /// Broadcast to all servers
     Broadcast,
+    /// Select all the sockets, without a sender to exclude
+    All,
     /// Add a custom timeout to the ack callback
-    Timeout(Duration),
+    Timeout(#[serde(with = "duration_ms")] Duration),
+    /// Skip the sockets that are disconnecting, the disconnected sockets are always skipped
+    SkipDisconnecting,
+    /// Drop the message if it is not handed to the transport before this duration
+    Ttl(#[serde(with = "duration_ms")] Duration),
+    /// Only select the sockets currently using this transport
+    Transport(#[serde(with = "transport_name")] TransportType),
+    /// Limit the number of sockets waited for at the same time when broadcasting with acks
+    MaxConcurrentAcks(usize),
+    /// Only fetch a page of the selected sockets, ordered by id
+    Page { offset: usize, limit: usize },
+}
+
+/// Match a room name against a glob pattern,
+/// where `*` matches any sequence of characters and `?` matches a single character.
+pub(crate) fn glob_match(pattern: &str, room: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let room: Vec<char> = room.chars().collect();
+    let (mut p, mut r) = (0, 0);
+    // The position of the last `*` in the pattern and the room position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while r < room.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, r));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == room[r] => {
+                p += 1;
+                r += 1;
+            }
+            // Backtrack: the last `*` matches one more character
+            _ => match star {
+                Some((star_p, star_r)) => {
+                    star = Some((star_p, star_r + 1));
+                    p = star_p + 1;
+                    r = star_r + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Keep the given page of the sockets, if any. The sockets are sorted by id so the pages are stable,
+/// in the same order as the pages of the [`LocalAdapter`].
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn paginate<A: Adapter>(
+    mut sockets: Vec<Arc<Socket<A>>>,
+    page: Option<(usize, usize)>,
+) -> Vec<Arc<Socket<A>>> {
+    let Some((offset, limit)) = page else {
+        return sockets;
+    };
+    sockets.sort_unstable_by_key(|socket| socket.sid);
+    sockets.into_iter().skip(offset).take(limit).collect()
+}
+
+/// (De)serialize a [`Duration`] as a number of milliseconds,
+/// so it is readable in structured logs and can be sent to other nodes.
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// (De)serialize a [`TransportType`] with its engine.io name, `websocket` or `polling`.
+mod transport_name {
+    use engineioxide::service::TransportType;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        transport: &TransportType,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match transport {
+            TransportType::Websocket => "websocket",
+            TransportType::Polling => "polling",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<TransportType, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|_| D::Error::custom(format!("unknown transport: {name}")))
+    }
 }
 
 /// Options that can be used to modify the behavior of the broadcast methods.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BroadcastOptions {
     /// The flags to apply to the broadcast.
     pub flags: HashSet<BroadcastFlags>,
     /// The rooms to broadcast to.
     pub rooms: Vec<Room>,
+    /// The glob patterns of the rooms to broadcast to, in addition to the `rooms`.
+    #[serde(default)]
+    pub room_patterns: Vec<String>,
     /// The rooms to exclude from the broadcast.
     pub except: Vec<Room>,
+    /// The glob patterns of the rooms to exclude from the broadcast, in addition to the `except` rooms.
+    #[serde(default)]
+    pub except_patterns: Vec<String>,
     /// The socket id of the sender.
     pub sid: Sid,
 }
@@ -58,10 +168,189 @@ impl BroadcastOptions {
         Self {
             flags: HashSet::new(),
             rooms: Vec::new(),
+            room_patterns: Vec::new(),
             except: Vec::new(),
+            except_patterns: Vec::new(),
             sid,
         }
     }
+
+    /// Select the sockets in the given rooms, except the sender.
+    ///
+    /// The sender is excluded through its `sid` with the [`BroadcastFlags::Broadcast`] flag,
+    /// so it is never excluded twice if it is also in an `except` room.
+    pub fn to(mut self, rooms: impl RoomParam) -> Self {
+        self.rooms.extend(rooms.into_room_iter().unique());
+        self.flags.insert(BroadcastFlags::Broadcast);
+        self
+    }
+
+    /// Select the sockets in the given rooms, including the sender.
+    pub fn within(mut self, rooms: impl RoomParam) -> Self {
+        self.rooms.extend(rooms.into_room_iter().unique());
+        self
+    }
+
+    /// Select the sockets in the rooms matching the given glob pattern, including the sender.
+    ///
+    /// In the pattern, `*` matches any sequence of characters and `?` matches a single character.
+    pub fn within_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.room_patterns.push(pattern.into());
+        self
+    }
+
+    /// Exclude the sockets in the given rooms.
+    ///
+    /// It also sets the [`BroadcastFlags::Broadcast`] flag so the sender is excluded.
+    pub fn except(mut self, rooms: impl RoomParam) -> Self {
+        self.except.extend(rooms.into_room_iter().unique());
+        self.flags.insert(BroadcastFlags::Broadcast);
+        self
+    }
+
+    /// Exclude the sockets in the rooms matching the given glob pattern, like [`BroadcastOptions::within_pattern`].
+    ///
+    /// It also sets the [`BroadcastFlags::Broadcast`] flag so the sender is excluded.
+    pub fn except_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.except_patterns.push(pattern.into());
+        self.flags.insert(BroadcastFlags::Broadcast);
+        self
+    }
+
+    /// Only select the sockets connected to this node.
+    pub fn local(mut self) -> Self {
+        self.flags.insert(BroadcastFlags::Local);
+        self
+    }
+
+    /// Whether only the sockets connected to this node are selected, with the [`BroadcastFlags::Local`] flag.
+    ///
+    /// An adapter spanning several nodes must not forward these broadcasts to the other nodes.
+    pub fn is_local(&self) -> bool {
+        self.flags.contains(&BroadcastFlags::Local)
+    }
+
+    /// Select all the sockets except the sender.
+    pub fn broadcast(mut self) -> Self {
+        self.flags.insert(BroadcastFlags::Broadcast);
+        self
+    }
+
+    /// Select all the sockets, with the [`BroadcastFlags::All`] flag.
+    ///
+    /// There is no sender, so no socket is excluded through the `sid` of the options.
+    pub fn all() -> Self {
+        let mut opts = Self::new(0i64.into());
+        opts.flags.insert(BroadcastFlags::All);
+        opts
+    }
+
+    /// Whether all the sockets are selected when no room is given,
+    /// with the [`BroadcastFlags::Broadcast`] or [`BroadcastFlags::All`] flags.
+    pub fn selects_all(&self) -> bool {
+        self.flags.contains(&BroadcastFlags::Broadcast) || self.flags.contains(&BroadcastFlags::All)
+    }
+
+    /// Whether the socket `sid` is the sender, excluded with the [`BroadcastFlags::Broadcast`] flag.
+    ///
+    /// No socket is excluded with the [`BroadcastFlags::All`] flag.
+    pub fn excludes_sender(&self, sid: Sid) -> bool {
+        self.flags.contains(&BroadcastFlags::Broadcast)
+            && !self.flags.contains(&BroadcastFlags::All)
+            && sid == self.sid
+    }
+
+    /// Set a custom timeout for the ack callbacks.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.flags.insert(BroadcastFlags::Timeout(timeout));
+        self
+    }
+
+    /// Skip the sockets that are disconnecting.
+    pub fn skip_disconnecting(mut self) -> Self {
+        self.flags.insert(BroadcastFlags::SkipDisconnecting);
+        self
+    }
+
+    /// Drop the message if it is not handed to the transport before the given `ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.flags.insert(BroadcastFlags::Ttl(ttl));
+        self
+    }
+
+    /// Only select the sockets currently using the given transport.
+    pub fn transport(mut self, transport: TransportType) -> Self {
+        self.flags
+            .retain(|flag| !matches!(flag, BroadcastFlags::Transport(_)));
+        self.flags.insert(BroadcastFlags::Transport(transport));
+        self
+    }
+
+    /// Only fetch the `limit` sockets following the first `offset` ones with [`Adapter::fetch_sockets`].
+    ///
+    /// The sockets are ordered by id so the successive pages don't overlap,
+    /// as long as no socket connects or disconnects in the meantime.
+    pub fn page(mut self, offset: usize, limit: usize) -> Self {
+        self.flags
+            .retain(|flag| !matches!(flag, BroadcastFlags::Page { .. }));
+        self.flags.insert(BroadcastFlags::Page { offset, limit });
+        self
+    }
+
+    /// Return the `(offset, limit)` of the page set with [`BroadcastOptions::page`], if any.
+    pub fn page_bounds(&self) -> Option<(usize, usize)> {
+        self.flags.iter().find_map(|flag| match flag {
+            BroadcastFlags::Page { offset, limit } => Some((*offset, *limit)),
+            _ => None,
+        })
+    }
+
+    /// Limit the number of sockets waited for at the same time when broadcasting with acks,
+    /// the packet is sent to the next socket once an ack is received or timed out.
+    ///
+    /// By default all the selected sockets are waited for at the same time.
+    pub fn max_concurrent_acks(mut self, max: usize) -> Self {
+        self.flags
+            .retain(|flag| !matches!(flag, BroadcastFlags::MaxConcurrentAcks(_)));
+        self.flags.insert(BroadcastFlags::MaxConcurrentAcks(max));
+        self
+    }
+}
+
+/// A snapshot of the rooms of an adapter and of their sockets.
+///
+/// It can be persisted to restore the rooms in another adapter, for example on a warm restart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterState {
+    /// The sockets of each room
+    pub rooms: HashMap<Room, HashSet<Sid>>,
+}
+
+/// A serializable summary of a socket, which may be connected to another server of the cluster,
+/// see [`Adapter::fetch_remote_sockets`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSocket {
+    /// The id of the socket
+    pub sid: Sid,
+    /// The path of the namespace of the socket
+    pub ns: String,
+    /// The rooms of the socket
+    pub rooms: Vec<Room>,
+    /// The auth payload sent by the client when connecting, if the server shares it
+    #[serde(default)]
+    pub handshake: Option<serde_json::Value>,
+}
+
+/// The state of a disconnected socket, kept so it can be recovered when its client reconnects,
+/// see [`Adapter::persist_session`].
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The id of the disconnected socket
+    pub sid: Sid,
+    /// The rooms of the socket when it disconnected
+    pub rooms: Vec<Room>,
+    /// The packets broadcast to the socket while it was disconnected, oldest first
+    pub missed_packets: Vec<Packet>,
 }
 
 //TODO: Make an AsyncAdapter trait
@@ -73,25 +362,51 @@ pub trait Adapter: std::fmt::Debug + Send + Sync + 'static {
     where
         Self: Sized;
 
+    /// Create an adapter that is not bound to any namespace, to be wrapped in a [`CompositeAdapter`].
+    ///
+    /// It keeps the state of the rooms, but the operations selecting the sockets don't reach
+    /// any socket of this server, only those of the other servers if the adapter spans several of them.
+    /// The default implementation creates the adapter with a namespace ref that never upgrades.
+    fn detached() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new(Weak::new())
+    }
+
     /// Initialize the adapter.
     fn init(&self) -> Result<(), Self::Error>;
     /// Close the adapter.
     fn close(&self) -> Result<(), Self::Error>;
 
-    /// Return the number of servers.
+    /// Return the number of servers participating in the broadcasts, including this one.
+    ///
+    /// It is at least 1 and it can change over time, as the servers of a cluster join and leave.
+    /// A [`Adapter::broadcast_with_ack`] that is not [local](BroadcastOptions::is_local) expects
+    /// the responses of the `server_count() - 1` other servers in addition to the local acks.
     fn server_count(&self) -> Result<u16, Self::Error>;
 
     /// Add the socket to all the rooms.
     fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Self::Error>;
     /// Remove the socket from the rooms.
+    /// A room that has no sockets left is deleted.
     fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Self::Error>;
     /// Remove the socket from all the rooms.
+    /// A room that has no sockets left is deleted.
     fn del_all(&self, sid: Sid) -> Result<(), Self::Error>;
 
-    /// Broadcast the packet to the sockets that match the [`BroadcastOptions`].
-    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<(), BroadcastError>;
+    /// Broadcast the packet to the sockets that match the [`BroadcastOptions`]
+    /// and return the number of sockets it was sent to.
+    ///
+    /// A socket in several of the selected rooms receives the packet once.
+    ///
+    /// If the options are [local](BroadcastOptions::is_local), only the sockets of this node are selected.
+    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<usize, BroadcastError>;
 
     /// Broadcast the packet to the sockets that match the [`BroadcastOptions`] and return a stream of ack responses.
+    ///
+    /// The stream completes once the [timeout](BroadcastOptions::timeout), or the `ack_timeout` of the config,
+    /// has elapsed since the broadcast, the sockets that didn't respond yet yield an [`AckError::AckTimeoutError`].
     fn broadcast_with_ack<V: DeserializeOwned>(
         &self,
         packet: Packet,
@@ -101,36 +416,273 @@ pub trait Adapter: std::fmt::Debug + Send + Sync + 'static {
     /// Return the sockets ids that match the [`BroadcastOptions`].
     fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Self::Error>;
 
+    /// Return the sockets ids in the given rooms.
+    ///
+    /// Unlike [`Adapter::sockets`], it returns an [`Error::RoomNotFound`] if one of the rooms doesn't exist.
+    fn sockets_strict(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Error> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        self.check_rooms(&rooms)?;
+        self.sockets(rooms).map_err(|e| Error::Adapter(e.into()))
+    }
+
+    /// Return true if the room exists.
+    fn has_room(&self, room: &str) -> Result<bool, Self::Error>;
+
+    /// Return all the rooms with the number of sockets in each of them, in no particular order.
+    fn rooms(&self) -> Result<Vec<(Room, usize)>, Self::Error>;
+
+    /// Return an [`Error::RoomNotFound`] for the first room that doesn't exist.
+    fn check_rooms(&self, rooms: &[Room]) -> Result<(), Error> {
+        for room in rooms {
+            if !self.has_room(room).map_err(|e| Error::Adapter(e.into()))? {
+                return Err(Error::RoomNotFound(room.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all the sockets from the room and delete it.
+    /// It is a no-op if the room doesn't exist.
+    fn clear_room(&self, room: Room) -> Result<(), Self::Error>;
+
+    /// Remove all the sockets from the room and delete it.
+    ///
+    /// Unlike [`Adapter::clear_room`], it returns an [`Error::RoomNotFound`] if the room doesn't exist.
+    fn clear_room_strict(&self, room: Room) -> Result<(), Error> {
+        self.check_rooms(std::slice::from_ref(&room))?;
+        self.clear_room(room).map_err(|e| Error::Adapter(e.into()))
+    }
+
+    /// Move all the sockets of the room `from` to the room `to` and delete `from`, atomically,
+    /// so a socket joining `from` concurrently is either moved or stays in `from`.
+    ///
+    /// If `to` already exists, the sockets are merged in it. It is a no-op if `from` doesn't exist.
+    ///
+    /// The default implementation is not atomic: a socket joining `from` while the sockets are moved
+    /// leaves it without joining `to`. An adapter should override it to move the sockets at once.
+    fn rename_room(&self, from: Room, to: Room) -> Result<(), Self::Error> {
+        if from == to {
+            return Ok(());
+        }
+        for sid in self.sockets(from.clone())? {
+            self.add_all(sid, to.clone())?;
+        }
+        self.clear_room(from)
+    }
+
+    /// Return a snapshot of all the rooms and of their sockets.
+    fn export_state(&self) -> Result<AdapterState, Self::Error>;
+    /// Replace all the rooms and their sockets with the given snapshot.
+    fn import_state(&self, state: AdapterState) -> Result<(), Self::Error>;
+
     /// Return the rooms of the socket.
     fn socket_rooms(&self, sid: Sid) -> Result<Vec<Room>, Self::Error>;
 
     /// Return the sockets that match the [`BroadcastOptions`].
+    ///
+    /// If a [page](BroadcastOptions::page) is set, only the sockets of this page are returned.
     fn fetch_sockets(&self, opts: BroadcastOptions) -> Result<Vec<Arc<Socket<Self>>>, Self::Error>
     where
         Self: Sized;
 
+    /// Return a [`RemoteSocket`] summary of the sockets that match the [`BroadcastOptions`].
+    ///
+    /// Unlike [`Adapter::fetch_sockets`], an adapter spanning several servers can return
+    /// the sockets connected to the other servers. The default implementation only returns
+    /// the sockets of this server.
+    fn fetch_remote_sockets(&self, opts: BroadcastOptions) -> Result<Vec<RemoteSocket>, Self::Error>
+    where
+        Self: Sized,
+    {
+        self.fetch_sockets(opts)?
+            .into_iter()
+            .map(|socket| {
+                Ok(RemoteSocket {
+                    sid: socket.sid,
+                    ns: socket.ns().clone(),
+                    rooms: self.socket_rooms(socket.sid)?,
+                    handshake: Some(socket.handshake.auth().clone()),
+                })
+            })
+            .collect()
+    }
+
     /// Add the sockets that match the [`BroadcastOptions`] to the rooms.
     fn add_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam)
         -> Result<(), Self::Error>;
     /// Remove the sockets that match the [`BroadcastOptions`] from the rooms.
     fn del_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam)
         -> Result<(), Self::Error>;
-    /// Disconnect the sockets that match the [`BroadcastOptions`].
-    fn disconnect_socket(&self, opts: BroadcastOptions) -> Result<(), BroadcastError>;
-
-    //TODO: implement
-    // fn server_side_emit(&self, packet: Packet, opts: BroadcastOptions) -> Result<u64, Error>;
-    // fn persist_session(&self, sid: i64);
-    // fn restore_session(&self, sid: i64) -> Session;
+    /// Disconnect the sockets that match the [`BroadcastOptions`] with the given [`DisconnectReason`].
+    ///
+    /// The reason is passed to the disconnect handlers of the sockets. The socket.io disconnect packet
+    /// has no payload, so a reason that the clients can't infer from it, like [`DisconnectReason::Kicked`],
+    /// is sent to them in a `disconnect_reason` event right before the disconnect packet.
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+        reason: DisconnectReason,
+    ) -> Result<(), BroadcastError>;
+
+    /// Emit the packet to the other servers of the cluster, not to the client sockets,
+    /// and return the number of servers it was sent to.
+    ///
+    /// The packet is not delivered to the current server. The `opts` are forwarded to the other servers,
+    /// for example with the [`BroadcastFlags::Timeout`] of the acknowledgements they may send back.
+    /// An adapter that doesn't span several servers returns 0.
+    fn server_side_emit(&self, packet: Packet, opts: BroadcastOptions) -> Result<u64, Self::Error>;
+
+    /// Keep the rooms of a socket whose transport was closed, and buffer the packets broadcast to it,
+    /// so its session can be restored with [`Adapter::restore_session`] when the client reconnects.
+    ///
+    /// It is called before the socket leaves its rooms, if the namespace recovers the connection state.
+    /// The session is persisted under the private id `pid` of the socket, which only its client knows,
+    /// so a client that learned the public `sid` of another one cannot take its session over.
+    fn persist_session(&self, sid: Sid, pid: Sid) -> Result<(), Self::Error>;
+
+    /// Take the session persisted under the given private id, if it didn't expire.
+    fn restore_session(&self, pid: Sid) -> Result<Option<Session>, Self::Error>;
 }
 
 /// The default adapter. store the state in memory.
 #[derive(Debug)]
 pub struct LocalAdapter {
-    rooms: RwLock<HashMap<Room, HashSet<Sid>>>,
+    rooms: RwLock<RoomsIndex>,
+    /// The sessions of the disconnected sockets that can be recovered, by private id
+    sessions: Mutex<HashMap<Sid, PersistedSession>>,
     ns: Weak<Namespace<Self>>,
 }
 
+/// A session persisted by the [`LocalAdapter`] until it expires
+#[derive(Debug)]
+struct PersistedSession {
+    sid: Sid,
+    rooms: Vec<Room>,
+    missed_packets: VecDeque<Packet>,
+    expires_at: Instant,
+}
+
+impl PersistedSession {
+    /// Whether the socket would have been selected by the `opts` if it was still connected
+    fn is_selected(&self, opts: &BroadcastOptions) -> bool {
+        let selected = if opts.rooms.is_empty() && opts.room_patterns.is_empty() {
+            // Without rooms, only a broadcast selects other sockets than the sender
+            opts.selects_all()
+        } else {
+            self.rooms.iter().any(|room| {
+                opts.rooms.contains(room)
+                    || opts
+                        .room_patterns
+                        .iter()
+                        .any(|pattern| glob_match(pattern, room))
+            })
+        };
+        selected
+            && !self.rooms.iter().any(|room| {
+                opts.except.contains(room)
+                    || opts
+                        .except_patterns
+                        .iter()
+                        .any(|pattern| glob_match(pattern, room))
+            })
+    }
+}
+
+/// The sockets of each room, along with the reverse index of the rooms of each socket.
+///
+/// It derefs to the sockets of each room for the reads, the writes go through its methods
+/// so both maps are always updated together.
+#[derive(Debug, Default)]
+struct RoomsIndex {
+    rooms: HashMap<Room, HashSet<Sid>>,
+    socket_rooms: HashMap<Sid, HashSet<Room>>,
+}
+
+impl RoomsIndex {
+    fn from_rooms(rooms: HashMap<Room, HashSet<Sid>>) -> Self {
+        let mut socket_rooms: HashMap<Sid, HashSet<Room>> = HashMap::new();
+        for (room, sockets) in &rooms {
+            for sid in sockets {
+                socket_rooms.entry(*sid).or_default().insert(room.clone());
+            }
+        }
+        Self {
+            rooms,
+            socket_rooms,
+        }
+    }
+
+    /// Add a socket to a room, the room is created with the given capacity if it doesn't exist
+    fn insert(&mut self, room: Room, sid: Sid, capacity: usize) {
+        self.socket_rooms
+            .entry(sid)
+            .or_default()
+            .insert(room.clone());
+        self.rooms
+            .entry(room)
+            .or_insert_with(|| HashSet::with_capacity(capacity))
+            .insert(sid);
+    }
+
+    /// Remove a socket from a room, the room is deleted if it becomes empty
+    fn remove(&mut self, room: &str, sid: Sid) {
+        self.remove_room_socket(room, sid);
+        self.remove_socket_room(sid, room);
+    }
+
+    /// Remove a socket from all its rooms, the rooms that become empty are deleted
+    fn remove_socket(&mut self, sid: Sid) {
+        for room in self.socket_rooms.remove(&sid).into_iter().flatten() {
+            self.remove_room_socket(&room, sid);
+        }
+    }
+
+    /// Remove a room and return its sockets
+    fn remove_room(&mut self, room: &str) -> Option<HashSet<Sid>> {
+        let sockets = self.rooms.remove(room)?;
+        for sid in &sockets {
+            self.remove_socket_room(*sid, room);
+        }
+        Some(sockets)
+    }
+
+    /// Move the sockets of a room to another one, the target room is created if it doesn't exist
+    fn rename_room(&mut self, from: &str, to: Room) {
+        if let Some(sockets) = self.remove_room(from) {
+            self.rooms.entry(to.clone()).or_default();
+            for sid in sockets {
+                self.insert(to.clone(), sid, 0);
+            }
+        }
+    }
+
+    fn remove_room_socket(&mut self, room: &str, sid: Sid) {
+        if let Some(sockets) = self.rooms.get_mut(room) {
+            // Churny room names would otherwise leak empty entries
+            if sockets.remove(&sid) && sockets.is_empty() {
+                self.rooms.remove(room);
+            }
+        }
+    }
+
+    fn remove_socket_room(&mut self, sid: Sid, room: &str) {
+        if let Some(rooms) = self.socket_rooms.get_mut(&sid) {
+            rooms.remove(room);
+            if rooms.is_empty() {
+                self.socket_rooms.remove(&sid);
+            }
+        }
+    }
+}
+
+impl Deref for RoomsIndex {
+    type Target = HashMap<Room, HashSet<Sid>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rooms
+    }
+}
+
 impl From<Infallible> for AdapterError {
     fn from(_: Infallible) -> AdapterError {
         unreachable!()
@@ -142,7 +694,8 @@ impl Adapter for LocalAdapter {
 
     fn new(ns: Weak<Namespace<Self>>) -> Self {
         Self {
-            rooms: HashMap::new().into(),
+            rooms: RoomsIndex::default().into(),
+            sessions: HashMap::new().into(),
             ns,
         }
     }
@@ -155,17 +708,21 @@ impl Adapter for LocalAdapter {
         Ok(())
     }
 
+    /// All the sockets are on this server
     fn server_count(&self) -> Result<u16, Infallible> {
         Ok(1)
     }
 
+    /// There is no other server to emit to
+    fn server_side_emit(&self, _: Packet, _: BroadcastOptions) -> Result<u64, Infallible> {
+        Ok(0)
+    }
+
     fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
+        let capacity = self.ns.upgrade().map_or(0, |ns| ns.config.room_capacity);
         let mut rooms_map = self.rooms.write().unwrap();
         for room in rooms.into_room_iter() {
-            rooms_map
-                .entry(room)
-                .or_insert_with(HashSet::new)
-                .insert(sid);
+            rooms_map.insert(room, sid, capacity);
         }
         Ok(())
     }
@@ -173,34 +730,20 @@ impl Adapter for LocalAdapter {
     fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
         let mut rooms_map = self.rooms.write().unwrap();
         for room in rooms.into_room_iter() {
-            if let Some(room) = rooms_map.get_mut(&room) {
-                room.remove(&sid);
-            }
+            rooms_map.remove(&room, sid);
         }
         Ok(())
     }
 
     fn del_all(&self, sid: Sid) -> Result<(), Infallible> {
-        let mut rooms_map = self.rooms.write().unwrap();
-        for room in rooms_map.values_mut() {
-            room.remove(&sid);
-        }
+        self.rooms.write().unwrap().remove_socket(sid);
         Ok(())
     }
 
-    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<(), BroadcastError> {
-        let sockets = self.apply_opts(opts);
-
-        tracing::debug!("broadcasting packet to {} sockets", sockets.len());
-        let errors: Vec<_> = sockets
-            .into_iter()
-            .filter_map(|socket| socket.send(packet.clone()).err())
-            .collect();
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(errors.into())
-        }
+    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<usize, BroadcastError> {
+        let deadline = ttl_deadline(&opts);
+        self.buffer_missed_packet(&packet, &opts);
+        send_to_sockets(self.apply_opts(opts), packet, deadline)
     }
 
     fn broadcast_with_ack<V: DeserializeOwned>(
@@ -208,25 +751,34 @@ impl Adapter for LocalAdapter {
         packet: Packet,
         opts: BroadcastOptions,
     ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
-        let duration = opts.flags.iter().find_map(|flag| match flag {
-            BroadcastFlags::Timeout(duration) => Some(*duration),
-            _ => None,
-        });
-        let sockets = self.apply_opts(opts);
+        let flags = opts.flags.clone();
+        // The other servers would each respond with the acks of their sockets,
+        // with a single server only the local acks are expected
+        let servers = if opts.is_local() {
+            1
+        } else {
+            self.server_count().unwrap_or(1)
+        };
         tracing::debug!(
-            "broadcasting packet to {} sockets: {:?}",
-            sockets.len(),
-            sockets.iter().map(|s| s.sid).collect::<Vec<_>>()
+            "expecting {} other server responses",
+            servers.saturating_sub(1)
         );
-        let count = sockets.len();
-        let ack_futs = sockets.into_iter().map(move |socket| {
-            let packet = packet.clone();
-            async move { socket.clone().send_with_ack(packet, duration).await }
-        });
-        Ok(stream::iter(ack_futs).buffer_unordered(count).boxed())
+        let sockets = self.apply_opts(opts);
+        Ok(ack_stream(sockets, packet, &flags))
     }
 
     fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Infallible> {
+        // A detached adapter has no sockets to look up, the rooms are read as they are
+        if self.ns.strong_count() == 0 {
+            let rooms_map = self.rooms.read().unwrap();
+            return Ok(rooms
+                .into_room_iter()
+                .filter_map(|room| rooms_map.get(&room))
+                .flatten()
+                .unique()
+                .copied()
+                .collect());
+        }
         let mut opts = BroadcastOptions::new(0i64.into());
         opts.rooms.extend(rooms.into_room_iter());
         Ok(self
@@ -236,44 +788,90 @@ impl Adapter for LocalAdapter {
             .collect())
     }
 
-    //TODO: make this operation O(1)
+    fn has_room(&self, room: &str) -> Result<bool, Infallible> {
+        Ok(self.rooms.read().unwrap().contains_key(room))
+    }
+
+    fn rooms(&self) -> Result<Vec<(Room, usize)>, Infallible> {
+        let rooms = self.rooms.read().unwrap();
+        Ok(rooms
+            .iter()
+            .map(|(room, sockets)| (room.clone(), sockets.len()))
+            .collect())
+    }
+
+    fn clear_room(&self, room: Room) -> Result<(), Infallible> {
+        self.rooms.write().unwrap().remove_room(&room);
+        Ok(())
+    }
+
+    fn rename_room(&self, from: Room, to: Room) -> Result<(), Infallible> {
+        if from == to {
+            return Ok(());
+        }
+        self.rooms.write().unwrap().rename_room(&from, to);
+        Ok(())
+    }
+
+    fn export_state(&self) -> Result<AdapterState, Infallible> {
+        Ok(AdapterState {
+            rooms: self.rooms.read().unwrap().rooms.clone(),
+        })
+    }
+
+    fn import_state(&self, state: AdapterState) -> Result<(), Infallible> {
+        *self.rooms.write().unwrap() = RoomsIndex::from_rooms(state.rooms);
+        Ok(())
+    }
+
     fn socket_rooms(&self, sid: Sid) -> Result<Vec<String>, Infallible> {
         let rooms_map = self.rooms.read().unwrap();
         Ok(rooms_map
-            .iter()
-            .filter(|(_, sockets)| sockets.contains(&sid))
-            .map(|(room, _)| room.clone())
-            .collect())
+            .socket_rooms
+            .get(&sid)
+            .map(|rooms| rooms.iter().cloned().collect())
+            .unwrap_or_default())
     }
 
     fn fetch_sockets(
         &self,
         opts: BroadcastOptions,
     ) -> Result<Vec<Arc<Socket<LocalAdapter>>>, Infallible> {
-        Ok(self.apply_opts(opts))
+        let page = opts.page_bounds();
+        Ok(self.apply_opts_page(opts, page))
     }
 
     fn add_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
         let rooms: Vec<Room> = rooms.into_room_iter().collect();
-        for socket in self.apply_opts(opts) {
-            self.add_all(socket.sid, rooms.clone()).unwrap();
-        }
+        let entries = self
+            .apply_opts(opts)
+            .into_iter()
+            .map(|socket| (socket.sid, rooms.clone()))
+            .collect();
+        self.add_all_bulk(entries);
         Ok(())
     }
 
     fn del_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
         let rooms: Vec<Room> = rooms.into_room_iter().collect();
-        for socket in self.apply_opts(opts) {
-            self.del(socket.sid, rooms.clone()).unwrap();
-        }
+        let entries = self
+            .apply_opts(opts)
+            .into_iter()
+            .map(|socket| (socket.sid, rooms.clone()))
+            .collect();
+        self.del_bulk(entries);
         Ok(())
     }
 
-    fn disconnect_socket(&self, opts: BroadcastOptions) -> Result<(), BroadcastError> {
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+        reason: DisconnectReason,
+    ) -> Result<(), BroadcastError> {
         let errors: Vec<_> = self
             .apply_opts(opts)
             .into_iter()
-            .filter_map(|socket| socket.disconnect().err())
+            .filter_map(|socket| socket.disconnect_with_reason(reason).err())
             .collect();
         if errors.is_empty() {
             Ok(())
@@ -281,62 +879,775 @@ impl Adapter for LocalAdapter {
             Err(errors.into())
         }
     }
+
+    fn persist_session(&self, sid: Sid, pid: Sid) -> Result<(), Infallible> {
+        let Some(ttl) = self.ns.upgrade().and_then(|ns| ns.config.recovery_ttl) else {
+            return Ok(());
+        };
+        let session = PersistedSession {
+            sid,
+            rooms: self.socket_rooms(sid)?,
+            missed_packets: VecDeque::new(),
+            expires_at: Instant::now() + ttl,
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        // The expired sessions are dropped lazily, when a new one is persisted
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(pid, session);
+        Ok(())
+    }
+
+    fn restore_session(&self, pid: Sid) -> Result<Option<Session>, Infallible> {
+        let session = self.sessions.lock().unwrap().remove(&pid);
+        Ok(session
+            .filter(|session| session.expires_at > Instant::now())
+            .map(|session| Session {
+                sid: session.sid,
+                rooms: session.rooms,
+                missed_packets: session.missed_packets.into(),
+            }))
+    }
 }
 
 impl LocalAdapter {
+    /// Add each socket to its rooms, like [`Adapter::add_all`] but under a single write lock,
+    /// for example to move a large room of sockets into another room.
+    pub fn add_all_bulk(&self, entries: Vec<(Sid, Vec<Room>)>) {
+        let capacity = self.ns.upgrade().map_or(0, |ns| ns.config.room_capacity);
+        let mut rooms_map = self.rooms.write().unwrap();
+        for (sid, rooms) in entries {
+            for room in rooms {
+                rooms_map.insert(room, sid, capacity);
+            }
+        }
+    }
+
+    /// Remove each socket from its rooms, like [`Adapter::del`] but under a single write lock.
+    pub fn del_bulk(&self, entries: Vec<(Sid, Vec<Room>)>) {
+        let mut rooms_map = self.rooms.write().unwrap();
+        for (sid, rooms) in entries {
+            for room in rooms {
+                rooms_map.remove(&room, sid);
+            }
+        }
+    }
+
+    /// Return the connected sockets that are not in any room.
+    ///
+    /// It compares the sockets of the namespace with the union of all the room memberships.
+    /// A socket is only expected here if it never joined a room or left all of them.
+    #[cfg(feature = "diagnostics")]
+    pub fn orphan_sockets(&self) -> Vec<Sid> {
+        let ns = self.ns.upgrade().unwrap();
+        let in_rooms: HashSet<Sid> = self
+            .rooms
+            .read()
+            .unwrap()
+            .values()
+            .flatten()
+            .copied()
+            .collect();
+        ns.get_sockets()
+            .into_iter()
+            .map(|socket| socket.sid)
+            .filter(|sid| !in_rooms.contains(sid))
+            .collect()
+    }
+
+    /// Buffer a broadcast packet for the persisted sessions that it would have reached,
+    /// the oldest packets are dropped when a buffer is full.
+    fn buffer_missed_packet(&self, packet: &Packet, opts: &BroadcastOptions) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.is_empty() {
+            return;
+        }
+        let max = self
+            .ns
+            .upgrade()
+            .map_or(0, |ns| ns.config.recovery_max_packets);
+        let now = Instant::now();
+        let selected = sessions
+            .values_mut()
+            .filter(|session| session.expires_at > now && session.is_selected(opts));
+        for session in selected {
+            if max == 0 {
+                break;
+            }
+            if session.missed_packets.len() >= max {
+                session.missed_packets.pop_front();
+            }
+            session.missed_packets.push_back(packet.clone());
+        }
+    }
+
     /// Apply the given `opts` and return the sockets that match.
+    ///
+    /// Each socket is returned once, even if it is in several of the selected rooms,
+    /// so all the operations built on it reach a socket only once.
+    ///
+    /// The [`BroadcastFlags::Local`] flag doesn't change the selection: all the sockets of the namespace
+    /// are connected to this node. An adapter spanning several nodes honors it by not forwarding
+    /// the [local](BroadcastOptions::is_local) operations to the other nodes.
     fn apply_opts(&self, opts: BroadcastOptions) -> Vec<Arc<Socket<Self>>> {
-        let rooms = opts.rooms;
+        self.apply_opts_page(opts, None)
+    }
 
-        let except = self.get_except_sids(&opts.except);
-        let ns = self.ns.upgrade().unwrap();
-        if !rooms.is_empty() {
+    /// Apply the given `opts` like [`LocalAdapter::apply_opts`] and keep the given page of the sockets, if any.
+    ///
+    /// The selected ids are sorted so the pages are stable, and only the sockets
+    /// up to the end of the page are looked up in the namespace.
+    fn apply_opts_page(
+        &self,
+        opts: BroadcastOptions,
+        page: Option<(usize, usize)>,
+    ) -> Vec<Arc<Socket<Self>>> {
+        let rooms = &opts.rooms;
+
+        // A detached adapter doesn't reach any socket of this server
+        let Some(ns) = self.ns.upgrade() else {
+            return Vec::new();
+        };
+        // The except and target sids are read under the same rooms guard so they are consistent.
+        // The rooms lock is released before locking the sockets of the namespace
+        let (except, sids) = {
             let rooms_map = self.rooms.read().unwrap();
-            rooms
-                .iter()
-                .filter_map(|room| rooms_map.get(room))
-                .flatten()
-                .unique()
-                .filter(|sid| {
-                    !except.contains(*sid)
-                        && (!opts.flags.contains(&BroadcastFlags::Broadcast) || **sid != opts.sid)
-                })
-                .filter_map(|sid| ns.get_socket(*sid).ok())
-                .collect()
-        } else if opts.flags.contains(&BroadcastFlags::Broadcast) {
-            let sockets = ns.get_sockets();
-            sockets
+            let except = Self::get_except_sids(&rooms_map, &opts.except, &opts.except_patterns);
+            let selected = !rooms.is_empty() || !opts.room_patterns.is_empty();
+            let sids: Option<Vec<Sid>> = selected.then(|| {
+                // The patterns are matched against all the room names
+                let matched = rooms_map
+                    .iter()
+                    .filter(|(room, _)| {
+                        opts.room_patterns
+                            .iter()
+                            .any(|pattern| glob_match(pattern, room))
+                    })
+                    .map(|(_, sids)| sids);
+                rooms
+                    .iter()
+                    .filter_map(|room| rooms_map.get(room))
+                    .chain(matched)
+                    .flatten()
+                    .unique()
+                    // The room membership alone selects the sockets,
+                    // the sender is only excluded with the `Broadcast` flag
+                    .filter(|sid| !except.contains(*sid) && !opts.excludes_sender(**sid))
+                    .copied()
+                    .collect()
+            });
+            (except, sids)
+        };
+        let sids = if let Some(sids) = sids {
+            sids
+        } else if opts.selects_all() {
+            // All the sockets of the namespace except the sender
+            let mut sids = ns.sids();
+            sids.retain(|sid| !except.contains(sid) && !opts.excludes_sender(*sid));
+            sids
+        } else {
+            vec![opts.sid]
+        };
+        lookup_sockets(&ns, sids, &opts.flags, page)
+    }
+
+    /// Get the sids of the sockets in the `except` rooms and in the rooms matching the `patterns`.
+    ///
+    /// The set is allocated once with the summed size of the rooms.
+    fn get_except_sids(
+        rooms_map: &HashMap<Room, HashSet<Sid>>,
+        except: &[Room],
+        patterns: &[String],
+    ) -> HashSet<Sid> {
+        // The patterns are matched against all the room names, only if there are some
+        let matched = rooms_map
+            .iter()
+            .filter(|(room, _)| {
+                !patterns.is_empty() && patterns.iter().any(|pattern| glob_match(pattern, room))
+            })
+            .map(|(_, sids)| sids);
+        let except_rooms: Vec<&HashSet<Sid>> = except
+            .iter()
+            .filter_map(|room| rooms_map.get(room))
+            .chain(matched)
+            .collect();
+        let capacity = except_rooms.iter().map(|sids| sids.len()).sum();
+        let mut except_sids = HashSet::with_capacity(capacity);
+        for sids in except_rooms {
+            except_sids.extend(sids);
+        }
+        except_sids
+    }
+}
+
+/// An adapter writing to two adapters and reading from the first one, the `primary` adapter.
+///
+/// It keeps both adapters in sync during a migration from the `primary` adapter to the `secondary` one,
+/// for example from the [`LocalAdapter`] to an adapter spanning several servers, without downtime.
+///
+/// The inner adapters are [detached](Adapter::detached): they keep the state of the rooms
+/// and only reach the sockets of the other servers. The sockets of this server are selected
+/// by the composite adapter from the rooms of the `primary` adapter.
+/// * The rooms are written to both adapters: a socket joining or leaving a room,
+///   [`Adapter::clear_room`], [`Adapter::rename_room`] and [`Adapter::import_state`].
+/// * The broadcasts are sent to the sockets of this server, then forwarded to both adapters
+///   so they reach the servers of both backends.
+/// * The rooms, the sockets, the server count and the sessions are read from the `primary` adapter.
+///   [`Adapter::broadcast_with_ack`] only streams the acks of this server and of the `primary` adapter.
+///
+/// A write is applied to both adapters even if the `primary` one fails, then the first error is returned.
+///
+/// A detached [`LocalAdapter`] doesn't know the config of the namespace, so it doesn't persist the sessions
+/// and the connection state is not recovered with a local `primary` adapter.
+#[derive(Debug)]
+pub struct CompositeAdapter<P: Adapter, S: Adapter> {
+    primary: P,
+    secondary: S,
+    ns: Weak<Namespace<Self>>,
+}
+
+impl<P: Adapter, S: Adapter> CompositeAdapter<P, S> {
+    /// Get the adapter that the reads go to.
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Get the adapter that only receives the writes.
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Select the sockets of this server that match the `opts`, like [`LocalAdapter`],
+    /// from the rooms of the `primary` adapter.
+    fn apply_opts(
+        &self,
+        opts: &BroadcastOptions,
+        page: Option<(usize, usize)>,
+    ) -> Result<Vec<Arc<Socket<Self>>>, AdapterError> {
+        let Some(ns) = self.ns.upgrade() else {
+            return Ok(Vec::new());
+        };
+        let except = self.room_sids(&opts.except, &opts.except_patterns)?;
+        let sids = if !opts.rooms.is_empty() || !opts.room_patterns.is_empty() {
+            let mut sids: Vec<Sid> = self
+                .room_sids(&opts.rooms, &opts.room_patterns)?
                 .into_iter()
-                .filter(|socket| !except.contains(&socket.sid))
-                .collect()
-        } else if let Ok(sock) = ns.get_socket(opts.sid) {
-            vec![sock]
+                .collect();
+            sids.retain(|sid| !except.contains(sid) && !opts.excludes_sender(*sid));
+            sids
+        } else if opts.selects_all() {
+            let mut sids = ns.sids();
+            sids.retain(|sid| !except.contains(sid) && !opts.excludes_sender(*sid));
+            sids
         } else {
-            vec![]
+            vec![opts.sid]
+        };
+        Ok(lookup_sockets(&ns, sids, &opts.flags, page))
+    }
+
+    /// Get the ids of the sockets in the `rooms` and in the rooms matching the `patterns`,
+    /// from the `primary` adapter.
+    fn room_sids(&self, rooms: &[Room], patterns: &[String]) -> Result<HashSet<Sid>, AdapterError> {
+        let mut rooms = rooms.to_vec();
+        if !patterns.is_empty() {
+            let matched = self.primary.rooms().map_err(Into::into)?;
+            rooms.extend(
+                matched
+                    .into_iter()
+                    .map(|(room, _)| room)
+                    .filter(|room| patterns.iter().any(|pattern| glob_match(pattern, room))),
+            );
+        }
+        if rooms.is_empty() {
+            return Ok(HashSet::new());
         }
+        let sids = self.primary.sockets(rooms).map_err(Into::into)?;
+        Ok(sids.into_iter().collect())
     }
+}
 
-    fn get_except_sids(&self, except: &Vec<Room>) -> HashSet<Sid> {
-        let mut except_sids = HashSet::new();
-        let rooms_map = self.rooms.read().unwrap();
-        for room in except {
-            if let Some(sockets) = rooms_map.get(room) {
-                except_sids.extend(sockets);
-            }
+/// Combine the results of a write applied to both adapters, the error of the `primary` one comes first.
+fn dual_write<T, U>(
+    primary: Result<T, impl Into<AdapterError>>,
+    secondary: Result<U, impl Into<AdapterError>>,
+) -> Result<T, AdapterError> {
+    let res = primary.map_err(Into::into)?;
+    secondary.map_err(Into::into)?;
+    Ok(res)
+}
+
+impl<P: Adapter, S: Adapter> Adapter for CompositeAdapter<P, S> {
+    type Error = AdapterError;
+
+    fn new(ns: Weak<Namespace<Self>>) -> Self {
+        Self {
+            primary: P::detached(),
+            secondary: S::detached(),
+            ns,
         }
-        except_sids
     }
+
+    fn init(&self) -> Result<(), AdapterError> {
+        dual_write(self.primary.init(), self.secondary.init())
+    }
+
+    fn close(&self) -> Result<(), AdapterError> {
+        dual_write(self.primary.close(), self.secondary.close())
+    }
+
+    fn server_count(&self) -> Result<u16, AdapterError> {
+        self.primary.server_count().map_err(Into::into)
+    }
+
+    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), AdapterError> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        dual_write(
+            self.primary.add_all(sid, rooms.clone()),
+            self.secondary.add_all(sid, rooms),
+        )
+    }
+
+    fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), AdapterError> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        dual_write(
+            self.primary.del(sid, rooms.clone()),
+            self.secondary.del(sid, rooms),
+        )
+    }
+
+    fn del_all(&self, sid: Sid) -> Result<(), AdapterError> {
+        dual_write(self.primary.del_all(sid), self.secondary.del_all(sid))
+    }
+
+    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<usize, BroadcastError> {
+        let deadline = ttl_deadline(&opts);
+        let sockets = self.apply_opts(&opts, None)?;
+        // The detached adapters only forward the packet to the other servers
+        let primary = self.primary.broadcast(packet.clone(), opts.clone());
+        let secondary = self.secondary.broadcast(packet.clone(), opts);
+        let count = send_to_sockets(sockets, packet, deadline)?;
+        primary?;
+        secondary?;
+        Ok(count)
+    }
+
+    fn broadcast_with_ack<V: DeserializeOwned>(
+        &self,
+        packet: Packet,
+        opts: BroadcastOptions,
+    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
+        let sockets = self.apply_opts(&opts, None)?;
+        // The acks of both streams are merged as plain values, then deserialized like in a single stream
+        let remote = self
+            .primary
+            .broadcast_with_ack::<Value>(packet.clone(), opts.clone())?;
+        let local = ack_stream::<_, Value>(sockets, packet, &opts.flags);
+        Ok(stream::select(local, remote)
+            .map(|res| {
+                let ack = res?;
+                Ok(AckResponse {
+                    data: serde_json::from_value(ack.data)?,
+                    binary: ack.binary,
+                    sid: ack.sid,
+                    elapsed: ack.elapsed,
+                })
+            })
+            .boxed())
+    }
+
+    fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, AdapterError> {
+        let opts = BroadcastOptions::new(0i64.into()).within(rooms);
+        if opts.rooms.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .apply_opts(&opts, None)?
+            .into_iter()
+            .map(|socket| socket.sid)
+            .collect())
+    }
+
+    fn has_room(&self, room: &str) -> Result<bool, AdapterError> {
+        self.primary.has_room(room).map_err(Into::into)
+    }
+
+    fn rooms(&self) -> Result<Vec<(Room, usize)>, AdapterError> {
+        self.primary.rooms().map_err(Into::into)
+    }
+
+    fn clear_room(&self, room: Room) -> Result<(), AdapterError> {
+        dual_write(
+            self.primary.clear_room(room.clone()),
+            self.secondary.clear_room(room),
+        )
+    }
+
+    fn rename_room(&self, from: Room, to: Room) -> Result<(), AdapterError> {
+        dual_write(
+            self.primary.rename_room(from.clone(), to.clone()),
+            self.secondary.rename_room(from, to),
+        )
+    }
+
+    fn export_state(&self) -> Result<AdapterState, AdapterError> {
+        self.primary.export_state().map_err(Into::into)
+    }
+
+    fn import_state(&self, state: AdapterState) -> Result<(), AdapterError> {
+        dual_write(
+            self.primary.import_state(state.clone()),
+            self.secondary.import_state(state),
+        )
+    }
+
+    fn socket_rooms(&self, sid: Sid) -> Result<Vec<Room>, AdapterError> {
+        self.primary.socket_rooms(sid).map_err(Into::into)
+    }
+
+    fn fetch_sockets(
+        &self,
+        opts: BroadcastOptions,
+    ) -> Result<Vec<Arc<Socket<Self>>>, AdapterError> {
+        self.apply_opts(&opts, opts.page_bounds())
+    }
+
+    fn add_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> Result<(), AdapterError> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        for socket in self.apply_opts(&opts, None)? {
+            self.add_all(socket.sid, rooms.clone())?;
+        }
+        // The sockets of the other servers
+        dual_write(
+            self.primary.add_sockets(opts.clone(), rooms.clone()),
+            self.secondary.add_sockets(opts, rooms),
+        )
+    }
+
+    fn del_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> Result<(), AdapterError> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        for socket in self.apply_opts(&opts, None)? {
+            self.del(socket.sid, rooms.clone())?;
+        }
+        // The sockets of the other servers
+        dual_write(
+            self.primary.del_sockets(opts.clone(), rooms.clone()),
+            self.secondary.del_sockets(opts, rooms),
+        )
+    }
+
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+        reason: DisconnectReason,
+    ) -> Result<(), BroadcastError> {
+        let sockets = self.apply_opts(&opts, None)?;
+        let primary = self.primary.disconnect_socket(opts.clone(), reason);
+        let secondary = self.secondary.disconnect_socket(opts, reason);
+        let errors: Vec<_> = sockets
+            .into_iter()
+            .filter_map(|socket| socket.disconnect_with_reason(reason).err())
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors.into());
+        }
+        primary?;
+        secondary
+    }
+
+    /// Emit the packet through both adapters and return the number of servers of the `primary` one.
+    fn server_side_emit(
+        &self,
+        packet: Packet,
+        opts: BroadcastOptions,
+    ) -> Result<u64, AdapterError> {
+        dual_write(
+            self.primary.server_side_emit(packet.clone(), opts.clone()),
+            self.secondary.server_side_emit(packet, opts),
+        )
+    }
+
+    fn persist_session(&self, sid: Sid, pid: Sid) -> Result<(), AdapterError> {
+        dual_write(
+            self.primary.persist_session(sid, pid),
+            self.secondary.persist_session(sid, pid),
+        )
+    }
+
+    /// Take the session from both adapters and return the one of the `primary` adapter.
+    fn restore_session(&self, pid: Sid) -> Result<Option<Session>, AdapterError> {
+        dual_write(
+            self.primary.restore_session(pid),
+            self.secondary.restore_session(pid),
+        )
+    }
+}
+
+/// Get the deadline of the [`BroadcastFlags::Ttl`] of the broadcast, if any.
+fn ttl_deadline(opts: &BroadcastOptions) -> Option<Instant> {
+    opts.flags.iter().find_map(|flag| match flag {
+        BroadcastFlags::Ttl(ttl) => Some(Instant::now() + *ttl),
+        _ => None,
+    })
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Send the packet to the sockets and return their number.
+fn send_to_sockets<A: Adapter>(
+    sockets: Vec<Arc<Socket<A>>>,
+    mut packet: Packet,
+    deadline: Option<Instant>,
+) -> Result<usize, BroadcastError> {
+    let count = sockets.len();
+    tracing::debug!("broadcasting packet to {} sockets", count);
+    // The packet is rewritten for each socket, so it is serialized for each socket
+    if sockets
+        .first()
+        .map_or(false, |socket| socket.needs_own_packet())
+    {
+        let errors: Vec<_> = sockets
+            .into_iter()
+            .filter_map(|socket| socket.send_with_deadline(packet.clone(), deadline).err())
+            .collect();
+        return if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors.into())
+        };
+    }
+    // All the sockets share the server config, so the payload is transformed only once
+    let transform = sockets
+        .first()
+        .and_then(|socket| socket.config.payload_transform.clone());
+    if let Some(transform) = transform {
+        packet = packet.transform_payload(transform.as_ref())?;
+    }
+    if let Some(socket) = sockets.first() {
+        packet = socket.inline_binary(packet);
+    }
+    // The packet is serialized only once and the result is shared between the sockets
+    let payload = packet.inner.take_bin();
+    let msg: Arc<str> = buffer_pool::with_buffer(|buf| {
+        packet.encode_into(buf)?;
+        Ok::<_, serde_json::Error>(buf.as_str().into())
+    })?;
+    let errors: Vec<_> = sockets
+        .into_iter()
+        .filter_map(|socket| socket.send_shared(&msg, payload.clone(), deadline).err())
+        .collect();
+    if errors.is_empty() {
+        Ok(count)
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// Send the packet to the sockets and return the stream of their acks,
+/// with the [`BroadcastFlags::Timeout`] and the [`BroadcastFlags::MaxConcurrentAcks`] of the broadcast.
+fn ack_stream<A: Adapter, V: DeserializeOwned>(
+    sockets: Vec<Arc<Socket<A>>>,
+    packet: Packet,
+    flags: &HashSet<BroadcastFlags>,
+) -> BoxStream<'static, Result<AckResponse<V>, AckError>> {
+    let duration = flags.iter().find_map(|flag| match flag {
+        BroadcastFlags::Timeout(duration) => Some(*duration),
+        _ => None,
+    });
+    let max_concurrent = flags.iter().find_map(|flag| match flag {
+        BroadcastFlags::MaxConcurrentAcks(max) => Some(*max),
+        _ => None,
+    });
+    tracing::debug!(
+        "broadcasting packet to {} sockets: {:?}",
+        sockets.len(),
+        sockets.iter().map(|s| s.sid).collect::<Vec<_>>()
+    );
+    let count = sockets.len();
+    // `buffer_unordered(0)` would never poll the acks, an empty target completes right away.
+    // `stream::empty` would carry `V`, which is not `Send`
+    if count == 0 {
+        return stream::poll_fn(|_| Poll::Ready(None)).boxed();
+    }
+    // All the acks share the same deadline, set before the first packet is sent,
+    // so the stream completes when it elapses however long the sending takes
+    let timeout = duration.unwrap_or(sockets[0].config.ack_timeout);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let ack_futs = sockets.into_iter().map(move |socket| {
+        let packet = packet.clone();
+        async move {
+            let (_, ack) = socket.send_with_ack_until(packet, deadline)?;
+            ack.await
+        }
+    });
+    // A zero limit would never poll the acks, so at least one socket is waited for
+    let concurrency = max_concurrent.map_or(count, |max| max.clamp(1, count));
+    stream::iter(ack_futs).buffer_unordered(concurrency).boxed()
+}
+
+/// Look the selected sockets up in the namespace and keep those matching the `flags`,
+/// then keep the given page of them, if any.
+///
+/// The ids are sorted when a page is set so the pages are stable,
+/// and only the sockets up to the end of the page are looked up.
+fn lookup_sockets<A: Adapter>(
+    ns: &Namespace<A>,
+    mut sids: Vec<Sid>,
+    flags: &HashSet<BroadcastFlags>,
+    page: Option<(usize, usize)>,
+) -> Vec<Arc<Socket<A>>> {
+    let skip_disconnecting = flags.contains(&BroadcastFlags::SkipDisconnecting);
+    let transport = flags.iter().find_map(|flag| match flag {
+        BroadcastFlags::Transport(transport) => Some(*transport),
+        _ => None,
+    });
+    if page.is_some() {
+        sids.sort_unstable();
+    }
+    let sockets = sids
+        .into_iter()
+        .filter_map(|sid| ns.get_socket(sid).ok())
+        .filter(|socket| match socket.state() {
+            SocketState::Connected => true,
+            SocketState::Disconnecting => !skip_disconnecting,
+            SocketState::Disconnected => false,
+        })
+        .filter(|socket| transport.map_or(true, |transport| socket.transport() == transport));
+    match page {
+        Some((offset, limit)) => sockets.skip(offset).take(limit).collect(),
+        None => sockets.collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::connect_socket;
+
+    #[test]
+    fn test_broadcast_options_serde() {
+        let flag = BroadcastFlags::Timeout(Duration::from_millis(12_500));
+        assert_eq!(
+            serde_json::to_value(&flag).unwrap(),
+            serde_json::json!({ "Timeout": 12500 })
+        );
+
+        let opts = BroadcastOptions::new(1i64.into())
+            .to("room1")
+            .ttl(Duration::from_secs(1));
+        let decoded: BroadcastOptions =
+            serde_json::from_str(&serde_json::to_string(&opts).unwrap()).unwrap();
+        assert_eq!(decoded.flags, opts.flags);
+        assert!(decoded
+            .flags
+            .contains(&BroadcastFlags::Ttl(Duration::from_secs(1))));
+        assert_eq!(decoded.rooms, opts.rooms);
+        assert_eq!(decoded.sid, opts.sid);
+
+        let flag = BroadcastFlags::Transport(TransportType::Websocket);
+        assert_eq!(
+            serde_json::to_value(&flag).unwrap(),
+            serde_json::json!({ "Transport": "websocket" })
+        );
+        let decoded: BroadcastFlags =
+            serde_json::from_value(serde_json::json!({ "Transport": "polling" })).unwrap();
+        assert_eq!(decoded, BroadcastFlags::Transport(TransportType::Polling));
+    }
+
+    #[tokio::test]
+    async fn test_server_count() {
+        let ns = Namespace::new_dummy([]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        assert_eq!(adapter.server_count().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_side_emit() {
+        let socket: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        let packet = Packet::event("/".to_string(), "test".to_string(), serde_json::json!(1));
+        let opts = BroadcastOptions::new(socket).broadcast();
+        assert_eq!(adapter.server_side_emit(packet, opts).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_persist_restore_session() {
+        use crate::{ns::NsConfig, packet::PacketData};
+        use futures::FutureExt;
+        let new_ns = |ttl| {
+            let config = NsConfig {
+                recovery_ttl: Some(ttl),
+                recovery_max_packets: 2,
+                ..Default::default()
+            };
+            Namespace::<LocalAdapter>::with_config("/", Arc::new(|_| async move {}.boxed()), config)
+        };
+        let event =
+            |i: i32| Packet::event("/".to_string(), "test".to_string(), serde_json::json!(i));
+        let socket: Sid = 1i64.into();
+        let pid: Sid = 10i64.into();
+        let ns = new_ns(Duration::from_secs(60));
+        ns.adapter.add_all(socket, ["room1", "room2"]).unwrap();
+        ns.adapter.persist_session(socket, pid).unwrap();
+        ns.adapter.del_all(socket).unwrap();
+
+        let opts = BroadcastOptions::new(2i64.into());
+        ns.adapter
+            .broadcast(event(1), opts.clone().to("room1"))
+            .unwrap();
+        ns.adapter
+            .broadcast(event(2), opts.clone().to("room3"))
+            .unwrap();
+        ns.adapter
+            .broadcast(event(3), opts.clone().to("room1").except("room2"))
+            .unwrap();
+        ns.adapter
+            .broadcast(event(4), opts.clone().broadcast())
+            .unwrap();
+        ns.adapter.broadcast(event(5), opts.to("room2")).unwrap();
+
+        // The session is only found with the private id
+        assert!(ns.adapter.restore_session(socket).unwrap().is_none());
+        let session = ns.adapter.restore_session(pid).unwrap().unwrap();
+        assert_eq!(session.sid, socket);
+        assert_eq!(session.rooms.len(), 2);
+        assert!(session.rooms.contains(&"room1".to_string()));
+        assert!(session.rooms.contains(&"room2".to_string()));
+        // The buffer keeps the last 2 packets that reached the rooms of the socket
+        assert_eq!(session.missed_packets.len(), 2);
+        assert!(
+            matches!(&session.missed_packets[0].inner, PacketData::Event(_, data, _) if data == &serde_json::json!(4))
+        );
+        assert!(
+            matches!(&session.missed_packets[1].inner, PacketData::Event(_, data, _) if data == &serde_json::json!(5))
+        );
+        // A session is restored only once
+        assert!(ns.adapter.restore_session(pid).unwrap().is_none());
+
+        let ns = new_ns(Duration::ZERO);
+        ns.adapter.add_all(socket, ["room1"]).unwrap();
+        ns.adapter.persist_session(socket, pid).unwrap();
+        assert!(ns.adapter.restore_session(pid).unwrap().is_none());
+    }
 
     #[tokio::test]
-    async fn test_server_count() {
+    async fn test_broadcast_with_ack_empty_ns() {
+        use futures::FutureExt;
         let ns = Namespace::new_dummy([]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        assert_eq!(adapter.server_count().unwrap(), 1);
+        let opts = BroadcastOptions::new(1i64.into()).broadcast();
+        let packet = Packet::event("/".to_string(), "test".to_string(), serde_json::json!(1));
+        let mut stream = adapter
+            .broadcast_with_ack::<serde_json::Value>(packet, opts)
+            .unwrap();
+        // The stream ends immediately, without any item
+        assert!(matches!(stream.next().now_or_never(), Some(None)));
     }
 
     #[tokio::test]
@@ -351,6 +1662,25 @@ mod test {
         assert_eq!(rooms_map.get("room2").unwrap().len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_room_capacity() {
+        use crate::ns::NsConfig;
+        use futures::FutureExt;
+        let config = NsConfig {
+            room_capacity: 64,
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(1i64.into(), ["room1"]).unwrap();
+        assert!(adapter.rooms.read().unwrap()["room1"].capacity() >= 64);
+
+        let ns = Namespace::new_dummy([]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(1i64.into(), ["room1"]).unwrap();
+        assert!(adapter.rooms.read().unwrap()["room1"].capacity() < 64);
+    }
+
     #[tokio::test]
     async fn test_del() {
         let socket: Sid = 1i64.into();
@@ -359,8 +1689,9 @@ mod test {
         adapter.add_all(socket, ["room1", "room2"]).unwrap();
         adapter.del(socket, "room1").unwrap();
         let rooms_map = adapter.rooms.read().unwrap();
-        assert_eq!(rooms_map.len(), 2);
-        assert_eq!(rooms_map.get("room1").unwrap().len(), 0);
+        // The empty room is deleted
+        assert_eq!(rooms_map.len(), 1);
+        assert!(rooms_map.get("room1").is_none());
         assert_eq!(rooms_map.get("room2").unwrap().len(), 1);
     }
 
@@ -372,9 +1703,8 @@ mod test {
         adapter.add_all(socket, ["room1", "room2"]).unwrap();
         adapter.del_all(socket).unwrap();
         let rooms_map = adapter.rooms.read().unwrap();
-        assert_eq!(rooms_map.len(), 2);
-        assert_eq!(rooms_map.get("room1").unwrap().len(), 0);
-        assert_eq!(rooms_map.get("room2").unwrap().len(), 0);
+        // The empty rooms are deleted
+        assert!(rooms_map.is_empty());
     }
 
     #[tokio::test]
@@ -396,6 +1726,52 @@ mod test {
         assert_eq!(adapter.socket_rooms(3i64.into()).unwrap(), ["room2"]);
     }
 
+    #[tokio::test]
+    async fn test_socket_rooms_index() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        let sorted_rooms = |sid| {
+            let mut rooms = adapter.socket_rooms(sid).unwrap();
+            rooms.sort();
+            rooms
+        };
+
+        adapter
+            .add_all(socket0, ["room1", "room2", "room3"])
+            .unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+        adapter.del(socket0, "room2").unwrap();
+        assert_eq!(sorted_rooms(socket0), ["room1", "room3"]);
+
+        // Joining again after leaving
+        adapter.add_all(socket0, "room2").unwrap();
+        assert_eq!(sorted_rooms(socket0), ["room1", "room2", "room3"]);
+
+        adapter.del_all(socket0).unwrap();
+        assert!(sorted_rooms(socket0).is_empty());
+        assert!(!adapter
+            .rooms
+            .read()
+            .unwrap()
+            .socket_rooms
+            .contains_key(&socket0));
+        assert_eq!(sorted_rooms(socket1), ["room1"]);
+
+        // Leaving a room after del_all is a no-op
+        adapter.del(socket0, "room1").unwrap();
+        assert_eq!(adapter.sockets("room1").unwrap(), [socket1]);
+
+        // The index is rebuilt from an imported state
+        adapter.add_all(socket0, ["room2"]).unwrap();
+        let state = adapter.export_state().unwrap();
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.import_state(state).unwrap();
+        assert_eq!(adapter.socket_rooms(socket0).unwrap(), ["room2"]);
+        assert_eq!(adapter.socket_rooms(socket1).unwrap(), ["room1"]);
+    }
+
     #[tokio::test]
     async fn test_add_socket() {
         let socket: Sid = 0i64.into();
@@ -413,6 +1789,82 @@ mod test {
         assert!(rooms_map.get("room2").unwrap().contains(&socket));
     }
 
+    #[tokio::test]
+    async fn test_fetch_sockets_page() {
+        let sids: [Sid; 5] = [0i64, 1, 2, 3, 4].map(Into::into);
+        let ns = Namespace::new_dummy(sids);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        let page = |offset, limit| -> Vec<Sid> {
+            let opts = BroadcastOptions::new(sids[0])
+                .broadcast()
+                .page(offset, limit);
+            adapter
+                .fetch_sockets(opts)
+                .unwrap()
+                .into_iter()
+                .map(|socket| socket.sid)
+                .collect()
+        };
+
+        // The sender is excluded, the pages cover the 4 other sockets once
+        let mut fetched = page(0, 3);
+        assert_eq!(fetched.len(), 3);
+        fetched.extend(page(3, 3));
+        assert_eq!(fetched.len(), 4);
+        assert_eq!(fetched.iter().collect::<HashSet<_>>().len(), 4);
+        assert!(!fetched.contains(&sids[0]));
+        assert!(page(4, 3).is_empty());
+        assert_eq!(page(1, 2), page(1, 2));
+        // The pages follow the order of the ids
+        assert_eq!(page(0, 4), sids[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_remote_sockets() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room3"]).unwrap();
+
+        let opts = BroadcastOptions::new(socket1).within("room1");
+        let mut sockets = adapter.fetch_remote_sockets(opts).unwrap();
+        assert_eq!(sockets.len(), 1);
+        let socket = sockets.pop().unwrap();
+        assert_eq!(socket.sid, socket0);
+        assert_eq!(socket.ns, "/");
+        assert_eq!(socket.rooms.len(), 2);
+        assert_eq!(socket.handshake, Some(serde_json::json!({})));
+
+        // The summary can be sent to another server
+        let decoded: RemoteSocket =
+            serde_json::from_str(&serde_json::to_string(&socket).unwrap()).unwrap();
+        assert_eq!(decoded, socket);
+    }
+
+    #[tokio::test]
+    async fn test_bulk() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all_bulk(vec![
+            (socket0, vec!["room1".to_string(), "room2".to_string()]),
+            (socket1, vec!["room2".to_string()]),
+        ]);
+        assert_eq!(adapter.sockets("room1").unwrap(), [socket0]);
+        assert_eq!(adapter.sockets("room2").unwrap().len(), 2);
+        assert_eq!(adapter.socket_rooms(socket1).unwrap(), ["room2"]);
+
+        adapter.del_bulk(vec![
+            (socket0, vec!["room2".to_string()]),
+            (socket1, vec!["room2".to_string()]),
+        ]);
+        assert!(!adapter.has_room("room2").unwrap());
+        assert_eq!(adapter.socket_rooms(socket0).unwrap(), ["room1"]);
+    }
+
     #[tokio::test]
     async fn test_del_socket() {
         let socket: Sid = 0i64.into();
@@ -439,12 +1891,30 @@ mod test {
         {
             let rooms_map = adapter.rooms.read().unwrap();
 
-            assert_eq!(rooms_map.len(), 2);
+            assert_eq!(rooms_map.len(), 1);
             assert!(rooms_map.get("room1").unwrap().contains(&socket));
-            assert!(rooms_map.get("room2").unwrap().is_empty());
+            assert!(rooms_map.get("room2").is_none());
         }
     }
 
+    #[tokio::test]
+    async fn test_rooms() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        assert!(adapter.rooms().unwrap().is_empty());
+
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+        let mut rooms = adapter.rooms().unwrap();
+        rooms.sort();
+        assert_eq!(rooms, [("room1".to_string(), 2), ("room2".to_string(), 1)]);
+
+        adapter.del_all(socket0).unwrap();
+        assert_eq!(adapter.rooms().unwrap(), [("room1".to_string(), 1)]);
+    }
+
     #[tokio::test]
     async fn test_sockets() {
         let socket0: Sid = 0i64.into();
@@ -472,6 +1942,205 @@ mod test {
         assert!(sockets.contains(&socket2));
     }
 
+    #[tokio::test]
+    async fn test_sockets_strict() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+        adapter.del(socket0, "room2").unwrap();
+
+        let sockets = adapter.sockets_strict("room1").unwrap();
+        assert_eq!(sockets.len(), 2);
+
+        // The room was deleted when its last socket left
+        assert!(adapter.sockets("room2").unwrap().is_empty());
+        match adapter.sockets_strict("room2") {
+            Err(Error::RoomNotFound(room)) => assert_eq!(room, "room2"),
+            e => panic!("should return a RoomNotFound error: {:?}", e),
+        }
+        assert!(adapter.sockets("ghost").unwrap().is_empty());
+        match adapter.sockets_strict(["room1", "ghost"]) {
+            Err(Error::RoomNotFound(room)) => assert_eq!(room, "ghost"),
+            e => panic!("should return a RoomNotFound error: {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clear_room() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+
+        adapter.clear_room("room1".into()).unwrap();
+        assert!(!adapter.has_room("room1").unwrap());
+        assert_eq!(adapter.socket_rooms(socket0).unwrap(), ["room2"]);
+        assert!(adapter.socket_rooms(socket1).unwrap().is_empty());
+
+        adapter.clear_room("ghost".into()).unwrap();
+        match adapter.clear_room_strict("ghost".into()) {
+            Err(Error::RoomNotFound(room)) => assert_eq!(room, "ghost"),
+            e => panic!("should return a RoomNotFound error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("game:*", "game:1"));
+        assert!(glob_match("game:*", "game:"));
+        assert!(glob_match("*:1", "game:1"));
+        assert!(glob_match("g?me:*1", "game:21"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("game:*", "lobby"));
+        assert!(!glob_match("game:?", "game:12"));
+        assert!(!glob_match("game", "game:1"));
+    }
+
+    #[tokio::test]
+    async fn test_within_pattern() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let socket2: Sid = 2i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1, socket2]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, "game:1").unwrap();
+        adapter.add_all(socket1, ["game:2", "game:1"]).unwrap();
+        adapter.add_all(socket2, "lobby").unwrap();
+
+        let opts = BroadcastOptions::new(socket0).within_pattern("game:*");
+        let mut sids: Vec<Sid> = adapter
+            .apply_opts(opts)
+            .into_iter()
+            .map(|socket| socket.sid)
+            .collect();
+        sids.sort_by_key(|sid| sid.to_string());
+        let mut expected = vec![socket0, socket1];
+        expected.sort_by_key(|sid| sid.to_string());
+        assert_eq!(sids, expected);
+
+        // Patterns and rooms are combined
+        let opts = BroadcastOptions::new(socket0)
+            .within("lobby")
+            .within_pattern("game:2");
+        assert_eq!(adapter.apply_opts(opts).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_except_pattern() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let socket2: Sid = 2i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1, socket2]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, "user:0:notifications").unwrap();
+        adapter.add_all(socket1, "user:1:notifications").unwrap();
+        adapter.add_all(socket2, "lobby").unwrap();
+        let sids = |opts| -> Vec<Sid> {
+            adapter
+                .apply_opts(opts)
+                .into_iter()
+                .map(|socket| socket.sid)
+                .collect()
+        };
+
+        // The sender is excluded as well
+        let opts = BroadcastOptions::new(socket0).except_pattern("user:*");
+        assert_eq!(sids(opts), [socket2]);
+
+        let opts = BroadcastOptions::new(socket2)
+            .within_pattern("user:*")
+            .except_pattern("user:1:*");
+        assert_eq!(sids(opts), [socket0]);
+
+        // Patterns and rooms are combined
+        let opts = BroadcastOptions::new(socket0)
+            .within_pattern("*")
+            .except("lobby")
+            .except_pattern("user:0:*");
+        assert_eq!(sids(opts), [socket1]);
+    }
+
+    #[tokio::test]
+    async fn test_rename_room() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room3"]).unwrap();
+
+        adapter.rename_room("room1".into(), "room3".into()).unwrap();
+        assert!(!adapter.has_room("room1").unwrap());
+        assert_eq!(adapter.sockets("room3").unwrap().len(), 2);
+        let mut rooms = adapter.socket_rooms(socket0).unwrap();
+        rooms.sort();
+        assert_eq!(rooms, ["room2", "room3"]);
+
+        // Renaming a room to itself or a missing room is a no-op
+        adapter.rename_room("room2".into(), "room2".into()).unwrap();
+        assert_eq!(adapter.sockets("room2").unwrap(), [socket0]);
+        adapter.rename_room("ghost".into(), "room2".into()).unwrap();
+        assert_eq!(adapter.sockets("room2").unwrap(), [socket0]);
+    }
+
+    #[tokio::test]
+    async fn test_rename_room_concurrent_join() {
+        // The sockets must be in the namespace to be returned by `sockets`
+        let sids: [Sid; 1000] = std::array::from_fn(|i| Sid::from(i as i64));
+        let ns = Namespace::<LocalAdapter>::new_dummy(sids);
+        let adapter = Arc::new(LocalAdapter::new(Arc::downgrade(&ns)));
+        let sids = sids.to_vec();
+
+        let joiner = {
+            let adapter = adapter.clone();
+            let sids = sids.clone();
+            std::thread::spawn(move || {
+                for sid in sids {
+                    adapter.add_all(sid, "lobby_a").unwrap();
+                }
+            })
+        };
+        for _ in 0..100 {
+            adapter
+                .rename_room("lobby_a".into(), "lobby_b".into())
+                .unwrap();
+        }
+        joiner.join().unwrap();
+
+        // Each socket is either moved or joined after the last rename, none is lost
+        let a: HashSet<Sid> = adapter.sockets("lobby_a").unwrap().into_iter().collect();
+        let b: HashSet<Sid> = adapter.sockets("lobby_b").unwrap().into_iter().collect();
+        assert!(a.is_disjoint(&b));
+        assert_eq!(a.len() + b.len(), sids.len());
+    }
+
+    #[tokio::test]
+    async fn test_export_import_state() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+
+        let state = adapter.export_state().unwrap();
+        assert_eq!(state.rooms.len(), 2);
+        let persisted = serde_json::to_string(&state).unwrap();
+        let state: AdapterState = serde_json::from_str(&persisted).unwrap();
+
+        let restored = LocalAdapter::new(Arc::downgrade(&ns));
+        restored.add_all(socket1, "room3").unwrap();
+        restored.import_state(state).unwrap();
+        assert_eq!(restored.export_state(), adapter.export_state());
+        assert!(!restored.has_room("room3").unwrap());
+        assert_eq!(restored.sockets("room1").unwrap().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_disconnect_socket() {
         let socket0: Sid = 0i64.into();
@@ -491,7 +2160,7 @@ mod test {
 
         let mut opts = BroadcastOptions::new(socket0);
         opts.rooms = vec!["room5".to_string()];
-        match adapter.disconnect_socket(opts) {
+        match adapter.disconnect_socket(opts, DisconnectReason::Kicked) {
             // todo it returns Ok, in previous commits it also returns Ok
             Err(BroadcastError::SendError(_)) | Ok(_) => {}
             e => panic!(
@@ -505,6 +2174,138 @@ mod test {
         assert!(sockets.contains(&socket2));
         assert!(sockets.contains(&socket0));
     }
+
+    #[tokio::test]
+    async fn test_disconnect_socket_reason() {
+        use engineioxide::SendPacket;
+
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([socket1]);
+        let (_, mut client_rx) = connect_socket(&ns, socket0, serde_json::json!({}));
+        ns.adapter.add_all(socket0, ["room1"]).unwrap();
+        ns.adapter.add_all(socket1, ["room2"]).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        ns.get_socket(socket0)
+            .unwrap()
+            .on_disconnect(move |socket, reason| {
+                let tx = tx.clone();
+                async move {
+                    tx.send((socket.sid, reason)).await.unwrap();
+                }
+            });
+
+        let opts = BroadcastOptions::new(socket1).to("room1");
+        ns.adapter
+            .disconnect_socket(opts, DisconnectReason::Kicked)
+            .unwrap();
+
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            (socket0, DisconnectReason::Kicked)
+        );
+        // The client is told why before being disconnected
+        let messages: Vec<_> = std::iter::from_fn(|| client_rx.try_recv().ok())
+            .map(|packet| match packet {
+                SendPacket::Message(msg) => msg,
+                packet => panic!("unexpected packet {packet:?}"),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [r#"2["disconnect_reason",{"reason":"kicked"}]"#, "1"]
+        );
+        assert!(!ns.has(socket0));
+        assert!(ns.has(socket1));
+    }
+    #[tokio::test]
+    async fn test_self_exclusion() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let socket2: Sid = 2i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1, socket2]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        // Each socket is also in a private room named after its sid
+        for sid in [socket0, socket1, socket2] {
+            adapter.add_all(sid, vec![sid.to_string()]).unwrap();
+        }
+        adapter.add_all(socket0, ["room1"]).unwrap();
+        adapter.add_all(socket1, ["room1"]).unwrap();
+        adapter.add_all(socket2, ["room1"]).unwrap();
+        let fetch = |opts| -> Vec<Sid> {
+            let mut sids: Vec<Sid> = adapter
+                .fetch_sockets(opts)
+                .unwrap()
+                .into_iter()
+                .map(|s| s.sid)
+                .collect();
+            sids.sort_by_key(|s| s.to_string());
+            sids
+        };
+        let mut others = vec![socket1, socket2];
+        others.sort_by_key(|s| s.to_string());
+
+        // Self exclusion through the sid
+        let opts = BroadcastOptions::new(socket0).to("room1");
+        assert_eq!(fetch(opts), others);
+
+        // Self exclusion through the sid room
+        let opts = BroadcastOptions::new(socket0)
+            .within("room1")
+            .except(vec![socket0.to_string()]);
+        assert_eq!(fetch(opts), others);
+
+        // Both at the same time should not exclude anyone else
+        let opts = BroadcastOptions::new(socket0)
+            .to("room1")
+            .except(vec![socket0.to_string()]);
+        assert_eq!(fetch(opts), others);
+
+        // Within includes the sender
+        let opts = BroadcastOptions::new(socket0).within("room1");
+        assert_eq!(fetch(opts).len(), 3);
+
+        // Targeting the sid room of another socket doesn't exclude it
+        let opts = BroadcastOptions::new(socket0).to(vec![socket1.to_string()]);
+        assert_eq!(fetch(opts), [socket1]);
+
+        // Without the broadcast flag, all the sockets of the room are selected,
+        // even if the sid doesn't belong to any of them
+        let mut opts = BroadcastOptions::new(10000i64.into());
+        opts.rooms = vec!["room1".to_string()];
+        assert_eq!(fetch(opts).len(), 3);
+        let mut opts = BroadcastOptions::new(socket0);
+        opts.rooms = vec!["room1".to_string()];
+        assert_eq!(fetch(opts).len(), 3);
+
+        // Without a sender, the socket with the same id as the placeholder sid is kept
+        assert_eq!(fetch(BroadcastOptions::all()).len(), 3);
+        let opts = BroadcastOptions::all().except(vec![socket2.to_string()]);
+        assert_eq!(fetch(opts), [socket0, socket1]);
+    }
+
+    #[tokio::test]
+    async fn test_apply_opts_deleted_room() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::new_dummy([socket0, socket1]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, "room1").unwrap();
+        adapter.add_all(socket1, ["room1", "room2"]).unwrap();
+        adapter.del(socket1, "room2").unwrap();
+        assert!(!adapter.has_room("room2").unwrap());
+
+        let opts = BroadcastOptions::new(socket0).within("room2");
+        assert!(adapter.apply_opts(opts).is_empty());
+        let opts = BroadcastOptions::new(socket0).within(["room1", "room2"]);
+        assert_eq!(adapter.apply_opts(opts).len(), 2);
+        let opts = BroadcastOptions::new(socket0).except("room2");
+        assert_eq!(adapter.apply_opts(opts).len(), 1);
+        assert!(adapter.sockets("room2").unwrap().is_empty());
+        assert_eq!(adapter.sockets(["room1", "room2"]).unwrap().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_apply_opts() {
         let socket0: Sid = 0i64.into();
@@ -543,5 +2344,412 @@ mod test {
         let opts = BroadcastOptions::new(10000i64.into());
         let sockets = adapter.fetch_sockets(opts).unwrap();
         assert_eq!(sockets.len(), 0);
+
+        // All the sockets are local
+        let opts = BroadcastOptions::new(socket2).local().broadcast();
+        assert!(opts.is_local());
+        let sockets = adapter.fetch_sockets(opts).unwrap();
+        assert_eq!(sockets.len(), 2);
+        let opts = BroadcastOptions::new(socket2).local().within("room3");
+        let sockets = adapter.fetch_sockets(opts).unwrap();
+        assert_eq!(sockets.len(), 2);
+    }
+    #[tokio::test]
+    async fn test_broadcast_count() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let socket2: Sid = 2i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([socket0, socket1, socket2]);
+        ns.adapter.add_all(socket0, "room1").unwrap();
+        // Each socket receives a single packet, the dummy sockets have a buffer of 1
+        let packet = || Packet::event("/".to_string(), "test".to_string(), serde_json::json!(1));
+
+        let opts = BroadcastOptions::new(socket0).broadcast();
+        assert_eq!(ns.adapter.broadcast(packet(), opts).unwrap(), 2);
+        let opts = BroadcastOptions::new(socket1).within("room1");
+        assert_eq!(ns.adapter.broadcast(packet(), opts).unwrap(), 1);
+        // No socket is reached
+        let opts = BroadcastOptions::new(socket0).to("room2");
+        assert_eq!(ns.adapter.broadcast(packet(), opts).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_rooms() {
+        use crate::socket::DisconnectReason;
+        use futures::{FutureExt, StreamExt};
+
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let sid: Sid = 0i64.into();
+        let (_, mut rx) = connect_socket(&ns, sid, serde_json::json!({}));
+        ns.adapter.add_all(sid, ["room1", "room2"]).unwrap();
+        // The socket is selected by both rooms and by the pattern
+        let opts = || {
+            BroadcastOptions::new(1i64.into())
+                .within(["room1", "room2"])
+                .within_pattern("room*")
+        };
+        let packet = || Packet::event("/".to_string(), "test".to_string(), serde_json::json!(1));
+        let mut received = || {
+            let mut count = 0;
+            while rx.try_recv().is_ok() {
+                count += 1;
+            }
+            count
+        };
+
+        assert_eq!(ns.adapter.fetch_sockets(opts()).unwrap().len(), 1);
+
+        assert_eq!(ns.adapter.broadcast(packet(), opts()).unwrap(), 1);
+        assert_eq!(received(), 1);
+
+        let acks: Vec<_> = ns
+            .adapter
+            .broadcast_with_ack::<serde_json::Value>(
+                packet(),
+                opts().timeout(Duration::from_millis(10)),
+            )
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(acks.len(), 1);
+        assert_eq!(received(), 1);
+
+        ns.adapter.add_sockets(opts(), "room3").unwrap();
+        assert_eq!(ns.adapter.sockets("room3").unwrap(), [sid]);
+        ns.adapter.del_sockets(opts(), "room3").unwrap();
+        assert!(!ns.adapter.has_room("room3").unwrap());
+
+        // A second disconnection would send a second disconnect packet
+        ns.adapter
+            .disconnect_socket(opts(), DisconnectReason::ServerNSDisconnect)
+            .unwrap();
+        assert_eq!(received(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transport_filter() {
+        use crate::{handshake::Handshake, SocketIoConfig};
+        use engineioxide::socket::FlushHandle;
+
+        // The dummy sockets use the polling transport
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let socket2: Sid = 2i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([socket0, socket1]);
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        ns.clone()
+            .connect(
+                socket2,
+                tx,
+                FlushHandle::default(),
+                TransportType::Websocket.into(),
+                Handshake::new_dummy(),
+                Arc::new(SocketIoConfig::default()),
+            )
+            .unwrap();
+        let sids = |opts: BroadcastOptions| {
+            let mut sids: Vec<_> = ns
+                .adapter
+                .fetch_sockets(opts)
+                .unwrap()
+                .into_iter()
+                .map(|socket| socket.sid)
+                .collect();
+            sids.sort_by_key(|sid| sid.to_string());
+            sids
+        };
+
+        let opts = BroadcastOptions::new(socket0).broadcast();
+        assert_eq!(sids(opts.clone()).len(), 2);
+        assert_eq!(
+            sids(opts.clone().transport(TransportType::Websocket)),
+            [socket2]
+        );
+        assert_eq!(sids(opts.transport(TransportType::Polling)), [socket1]);
+
+        // The last transport replaces the previous one
+        let opts = BroadcastOptions::new(socket0)
+            .within("room1")
+            .transport(TransportType::Polling)
+            .transport(TransportType::Websocket);
+        ns.adapter.add_all(socket2, "room1").unwrap();
+        assert_eq!(sids(opts), [socket2]);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_ack_deadline() {
+        use crate::packet::PacketData;
+        use futures::{FutureExt, StreamExt};
+        use serde_json::Value;
+
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut sockets = Vec::new();
+        for i in 0..2i64 {
+            let (socket, rx) = connect_socket(&ns, i.into(), serde_json::json!({}));
+            sockets.push((socket, rx));
+        }
+
+        let start = Instant::now();
+        let stream = ns
+            .adapter
+            .broadcast_with_ack::<Value>(
+                Packet::event("/".into(), "test".into(), Value::Null),
+                BroadcastOptions::new(2i64.into())
+                    .broadcast()
+                    .timeout(Duration::from_millis(50)),
+            )
+            .unwrap();
+        let handle = tokio::spawn(stream.collect::<Vec<_>>());
+
+        // Only the first socket responds
+        let (socket, rx) = &mut sockets[0];
+        rx.recv().await.unwrap();
+        socket
+            .clone()
+            .recv(PacketData::EventAck(Value::Null, 1))
+            .unwrap();
+
+        let acks = tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(acks.len(), 2);
+        assert!(acks
+            .iter()
+            .any(|ack| matches!(ack, Ok(ack) if ack.sid == 0i64.into())));
+        assert!(acks
+            .iter()
+            .any(|ack| matches!(ack, Err(AckError::AckTimeoutError(_)))));
+        assert!(sockets[1].0.pending_acks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_ack_max_concurrent() {
+        use crate::packet::PacketData;
+        use futures::{FutureExt, StreamExt};
+        use serde_json::Value;
+
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut sockets = Vec::new();
+        for i in 0..2i64 {
+            let (socket, rx) = connect_socket(&ns, i.into(), serde_json::json!({}));
+            sockets.push((socket, rx));
+        }
+
+        let stream = ns
+            .adapter
+            .broadcast_with_ack::<Value>(
+                Packet::event("/".into(), "test".into(), Value::Null),
+                BroadcastOptions::new(2i64.into())
+                    .broadcast()
+                    .max_concurrent_acks(1),
+            )
+            .unwrap();
+        let handle = tokio::spawn(stream.collect::<Vec<_>>());
+
+        // The packet is sent to the second socket only once the first one acknowledged it
+        let mut first = None;
+        while first.is_none() {
+            tokio::task::yield_now().await;
+            first = sockets.iter_mut().position(|(_, rx)| rx.try_recv().is_ok());
+        }
+        let first = first.unwrap();
+        let second = 1 - first;
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(sockets[second].1.try_recv().is_err());
+
+        for i in [first, second] {
+            if i == second {
+                sockets[i].1.recv().await.unwrap();
+            }
+            sockets[i]
+                .0
+                .clone()
+                .recv(PacketData::EventAck(Value::Null, 1))
+                .unwrap();
+        }
+        let acks = handle.await.unwrap();
+        assert_eq!(acks.len(), 2);
+        assert!(acks.iter().all(|ack| ack.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_ack_sid() {
+        use crate::packet::PacketData;
+        use futures::{FutureExt, StreamExt};
+        use serde_json::Value;
+
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut sockets = Vec::new();
+        for i in 0..3i64 {
+            let (socket, rx) = connect_socket(&ns, i.into(), serde_json::json!({}));
+            sockets.push((socket, rx));
+        }
+
+        let stream = ns
+            .adapter
+            .broadcast_with_ack::<Value>(
+                Packet::event("/".into(), "test".into(), Value::Null),
+                // The sender is excluded from a broadcast, it is not one of the sockets
+                BroadcastOptions::new(10i64.into()).broadcast(),
+            )
+            .unwrap();
+        let handle = tokio::spawn(stream.collect::<Vec<_>>());
+
+        for (socket, rx) in &mut sockets {
+            rx.recv().await.unwrap();
+            socket
+                .clone()
+                .recv(PacketData::EventAck(Value::Null, 1))
+                .unwrap();
+        }
+
+        let sids: HashSet<Sid> = handle
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|ack| ack.unwrap().sid)
+            .collect();
+        assert_eq!(sids, HashSet::from([0i64.into(), 1i64.into(), 2i64.into()]));
+    }
+    #[tokio::test]
+    async fn test_skip_disconnecting() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([socket0, socket1]);
+        ns.adapter.add_all(socket0, ["room1"]).unwrap();
+        ns.adapter.add_all(socket1, ["room1"]).unwrap();
+
+        // Keep the socket 1 in the disconnecting state until the handler is released
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = std::sync::Mutex::new(Some(rx));
+        let socket = ns.get_socket(socket1).unwrap();
+        socket.on_disconnecting(move |_, _, _| {
+            let rx = rx.lock().unwrap().take();
+            async move {
+                if let Some(rx) = rx {
+                    rx.await.ok();
+                }
+            }
+        });
+        // The dummy sockets cannot receive the disconnect packet
+        ns.disconnect(socket1, DisconnectReason::ServerNSDisconnect)
+            .ok();
+        assert_eq!(socket.state(), SocketState::Disconnecting);
+
+        let sids = |opts: BroadcastOptions| -> HashSet<Sid> {
+            ns.adapter
+                .apply_opts(opts)
+                .into_iter()
+                .map(|s| s.sid)
+                .collect()
+        };
+        let opts = BroadcastOptions::new(socket0).within("room1");
+        assert_eq!(sids(opts.clone()), HashSet::from([socket0, socket1]));
+        assert_eq!(
+            sids(opts.clone().skip_disconnecting()),
+            HashSet::from([socket0])
+        );
+
+        tx.send(()).unwrap();
+        while socket.state() != SocketState::Disconnected {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(sids(opts), HashSet::from([socket0]));
+        assert!(!ns.has(socket1));
+    }
+    #[cfg(feature = "diagnostics")]
+    #[tokio::test]
+    async fn test_orphan_sockets() {
+        let socket0: Sid = 0i64.into();
+        let socket1: Sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([socket0, socket1]);
+        ns.adapter.add_all(socket0, ["room1"]).unwrap();
+        ns.adapter.add_all(socket1, ["room1"]).unwrap();
+        assert!(ns.orphan_sockets().is_empty());
+
+        ns.adapter.del_all(socket1).unwrap();
+        assert_eq!(ns.orphan_sockets(), [socket1]);
+    }
+
+    #[tokio::test]
+    async fn test_composite_adapter() {
+        use crate::testing::MockAdapter;
+        use engineioxide::SendPacket;
+        use futures::FutureExt;
+
+        let ns = Namespace::<CompositeAdapter<LocalAdapter, MockAdapter>>::new(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+        );
+        let (socket0, _rx0) = connect_socket(&ns, 0i64.into(), serde_json::json!({}));
+        let (socket1, mut rx1) = connect_socket(&ns, 1i64.into(), serde_json::json!({}));
+        socket0.join(["room1", "room2"]).unwrap();
+        socket1.join("room1").unwrap();
+        socket0.leave("room2").unwrap();
+
+        // The rooms are written to both adapters
+        let room1 = vec![("room1".to_string(), 2)];
+        assert_eq!(ns.adapter.primary().rooms().unwrap(), room1);
+        assert_eq!(ns.adapter.secondary().rooms().unwrap(), room1);
+        let mut sids = ns.adapter.sockets("room1").unwrap();
+        sids.sort();
+        assert_eq!(sids, [0i64.into(), 1i64.into()]);
+
+        // The sockets of this server are selected from the primary adapter,
+        // the packet is also forwarded to both adapters
+        socket0.to("room1").emit("news", "hello").unwrap();
+        match rx1.try_recv().unwrap() {
+            SendPacket::SharedMessage(msg) => assert_eq!(msg.as_ref(), r#"2["news","hello"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+        let broadcasts = ns.adapter.secondary().broadcasts();
+        assert_eq!(broadcasts.len(), 1);
+        assert_eq!(broadcasts[0].opts.rooms, ["room1"]);
+
+        socket1.leave_all().unwrap();
+        let room1 = vec![("room1".to_string(), 1)];
+        assert_eq!(ns.adapter.primary().rooms().unwrap(), room1);
+        assert_eq!(ns.adapter.secondary().rooms().unwrap(), room1);
+    }
+
+    #[tokio::test]
+    async fn test_composite_adapter_broadcast_with_ack() {
+        use crate::packet::PacketData;
+        use futures::{FutureExt, StreamExt};
+
+        let ns = Namespace::<CompositeAdapter<LocalAdapter, LocalAdapter>>::new(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+        );
+        let mut sockets = Vec::new();
+        for i in 0..2i64 {
+            sockets.push(connect_socket(&ns, i.into(), serde_json::json!({})));
+        }
+
+        let stream = ns
+            .adapter
+            .broadcast_with_ack::<Vec<String>>(
+                Packet::event("/".into(), "test".into(), Value::Null),
+                BroadcastOptions::new(10i64.into()).broadcast(),
+            )
+            .unwrap();
+        let handle = tokio::spawn(stream.collect::<Vec<_>>());
+        for (socket, rx) in &mut sockets {
+            rx.recv().await.unwrap();
+            socket
+                .clone()
+                .recv(PacketData::EventAck(serde_json::json!(["ok"]), 1))
+                .unwrap();
+        }
+
+        let acks = handle.await.unwrap();
+        assert_eq!(acks.len(), 2);
+        for ack in acks {
+            assert_eq!(ack.unwrap().data, ["ok"]);
+        }
     }
 }
@@ -0,0 +1,76 @@
+//! A thread-local pool of buffers used to serialize packets.
+//!
+//! When broadcasting a packet to many sockets, the packet is serialized once in a buffer.
+//! With the `buffer-pool` feature, this buffer is taken from a thread-local pool and given back once the
+//! broadcast is done, so the allocation and the growth of the buffer are amortized between broadcasts.
+//!
+//! Without the feature, a new buffer is allocated for each broadcast.
+
+#[cfg(feature = "buffer-pool")]
+use std::cell::RefCell;
+
+/// The maximum number of buffers kept in the pool of each thread
+#[cfg(feature = "buffer-pool")]
+const MAX_POOLED_BUFFERS: usize = 8;
+
+/// Buffers bigger than this capacity are not given back to the pool to avoid holding a lot of memory
+#[cfg(feature = "buffer-pool")]
+const MAX_BUFFER_CAPACITY: usize = 64 * 1024;
+
+#[cfg(feature = "buffer-pool")]
+thread_local! {
+    static POOL: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Call the given function with an empty buffer.
+#[cfg(feature = "buffer-pool")]
+pub(crate) fn with_buffer<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    let mut buf = POOL
+        .with(|pool| pool.borrow_mut().pop())
+        .unwrap_or_default();
+    let res = f(&mut buf);
+    buf.clear();
+    if buf.capacity() <= MAX_BUFFER_CAPACITY {
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED_BUFFERS {
+                pool.push(buf);
+            }
+        });
+    }
+    res
+}
+
+/// Call the given function with an empty buffer.
+#[cfg(not(feature = "buffer-pool"))]
+pub(crate) fn with_buffer<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    f(&mut String::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_with_buffer() {
+        let res = with_buffer(|buf| {
+            assert!(buf.is_empty());
+            buf.push_str("foo");
+            buf.len()
+        });
+        assert_eq!(res, 3);
+
+        // A buffer given back to the pool is always cleared
+        with_buffer(|buf| assert!(buf.is_empty()));
+    }
+
+    #[cfg(feature = "buffer-pool")]
+    #[test]
+    fn test_buffer_reuse() {
+        let capacity = with_buffer(|buf| {
+            buf.push_str(&"a".repeat(1024));
+            buf.capacity()
+        });
+        with_buffer(|buf| assert_eq!(buf.capacity(), capacity));
+    }
+}
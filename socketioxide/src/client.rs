@@ -1,58 +1,80 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use engineioxide::handler::EngineIoHandler;
 use engineioxide::socket::Socket as EIoSocket;
-use serde_json::Value;
+use engineioxide::SendPacket as EnginePacket;
+use serde::Serialize;
+use serde_json::{Map, Value};
 
 use engineioxide::sid_generator::Sid;
+use futures::FutureExt;
 use tracing::debug;
 use tracing::error;
 
 use crate::adapter::Adapter;
-use crate::errors::SendError;
+use crate::errors::{BroadcastError, SendError};
 use crate::handshake::Handshake;
+use crate::socket::{DisconnectReason, Socket};
 use crate::{
-    config::SocketIoConfig,
+    config::{SocketIoConfig, UnknownNamespacePolicy},
     errors::Error,
-    ns::{EventCallback, Namespace},
-    packet::{Packet, PacketData},
+    ns::{Namespace, NsHandler, NsHandlers},
+    observer::{hash_payload, PacketMeta, PacketType},
+    packet::{event_data, event_name, Packet, PacketData, PartialPacket},
 };
 
 #[derive(Debug)]
 pub struct Client<A: Adapter> {
     pub(crate) config: Arc<SocketIoConfig>,
-    ns: HashMap<String, Arc<Namespace<A>>>,
+    /// The namespaces, shared by the clones of the client so the auto-created ones are reachable from all of them
+    ns: Arc<RwLock<HashMap<String, Arc<Namespace<A>>>>>,
+    /// The templates of the namespaces created on demand for the paths selected by their matcher
+    dynamic_ns: Vec<(String, NsHandler<A>)>,
 }
 
 impl<A: Adapter> Client<A> {
-    pub fn new(config: SocketIoConfig, ns_handlers: HashMap<String, EventCallback<A>>) -> Self {
+    pub fn new(config: SocketIoConfig, ns_handlers: NsHandlers<A>) -> Self {
+        let (mut dynamic_ns, static_ns): (Vec<_>, Vec<_>) = ns_handlers
+            .into_iter()
+            .partition(|(_, handler)| handler.config.matcher.is_some());
+        // The handlers come from a map, restore the registration order of the matchers
+        dynamic_ns.sort_by_key(|(_, handler)| handler.config.matcher.as_ref().map(|m| m.order()));
+        let ns = static_ns
+            .into_iter()
+            .map(|(path, handler)| {
+                let ns = Namespace::from_handler(path.clone(), handler);
+                (path, ns)
+            })
+            .collect::<HashMap<_, _>>();
         Self {
             config: config.into(),
-            ns: ns_handlers
-                .into_iter()
-                .map(|(path, callback)| (path.clone(), Namespace::new(path, callback)))
-                .collect(),
+            ns: Arc::new(RwLock::new(ns)),
+            dynamic_ns,
         }
     }
 
     /// Apply an incoming binary payload to a partial binary packet waiting to be filled with all the payloads
     ///
-    /// Returns true if the packet is complete and should be processed
-    fn apply_payload_on_packet(&self, data: Vec<u8>, socket: &EIoSocket<Self>) -> bool {
+    /// Returns the packet if it is complete and should be processed
+    fn apply_payload_on_packet(&self, data: Vec<u8>, socket: &EIoSocket<Self>) -> Option<Packet> {
         debug!("[sid={}] applying payload on packet", socket.sid);
-        if let Some(ref mut packet) = *socket.data.partial_bin_packet.lock().unwrap() {
-            match packet.inner {
-                PacketData::BinaryEvent(_, ref mut bin, _)
-                | PacketData::BinaryAck(ref mut bin, _) => {
-                    bin.add_payload(data);
-                    bin.is_complete()
-                }
-                _ => unreachable!("partial_bin_packet should only be set for binary packets"),
-            }
-        } else {
+        let mut partial = socket.data.partial_bin_packet.lock().unwrap();
+        let Some(packet) = partial.as_mut() else {
             debug!("[sid={}] socket received unexpected bin data", socket.sid);
-            false
+            return None;
+        };
+        match packet.push_binary(data) {
+            Ok(Some(packet)) => {
+                partial.take();
+                Some(packet)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                debug!("[sid={}] dropping binary packet: {}", socket.sid, e);
+                partial.take();
+                None
+            }
         }
     }
 
@@ -66,32 +88,93 @@ impl<A: Adapter> Client<A> {
         debug!("auth: {:?}", auth);
         let handshake = Handshake::new(auth, socket.req_data.clone());
         let sid = socket.sid;
-        if let Some(ns) = self.get_ns(&ns_path) {
-            let socket = ns.connect(sid, socket.tx.clone(), handshake, self.config.clone());
-            socket.send(Packet::connect(ns_path.clone(), sid))?;
-            Ok(())
-        } else {
-            socket
-                .tx
-                .try_send(Packet::invalid_namespace(ns_path).try_into()?)
-                .unwrap();
-            Ok(())
+        if !self.config.is_origin_allowed(handshake.origin()) {
+            debug!("[sid={sid}] origin {:?} is not allowed", handshake.origin());
+            return self.send_connect_error(Packet::origin_not_allowed(ns_path), socket);
         }
+        let (ns, ns_socket) = loop {
+            let Some(ns) = self.get_or_create_ns(&ns_path) else {
+                return self.send_connect_error(Packet::invalid_namespace(ns_path), socket);
+            };
+            let res = ns.clone().connect(
+                sid,
+                socket.tx.clone(),
+                socket.flush_handle(),
+                socket.transport_handle(),
+                handshake.clone(),
+                self.config.clone(),
+            );
+            match res {
+                Ok(ns_socket) => break (ns, ns_socket),
+                // The namespace was removed once its last socket left, it is created again
+                Err(Error::NamespaceRemoved(_)) => continue,
+                Err(Error::NamespaceFull(_)) => {
+                    debug!("[sid={sid}] namespace {ns_path} is full");
+                    return self.send_connect_error(Packet::namespace_full(ns_path), socket);
+                }
+                Err(Error::Adapter(err)) => return Err(err.into()),
+                Err(err) => {
+                    debug!("[sid={sid}] cannot connect to namespace {ns_path}: {err}");
+                    return self.send_connect_error(Packet::connect_failed(ns_path), socket);
+                }
+            }
+        };
+        let mut data = match &ns.config.connect_response {
+            Some(provider) => provider(&ns_socket.handshake),
+            None => Map::new(),
+        };
+        if ns.config.recovery_ttl.is_some() {
+            // The client sends it back in its auth payload to recover the session,
+            // it is a private id rather than the public sid so no other client can take the session over
+            data.insert("pid".into(), ns_socket.pid.to_string().into());
+        }
+        ns_socket.send(Packet::connect_with_data(ns_path.clone(), sid, data))?;
+        ns_socket.send_missed_packets()?;
+        Ok(())
+    }
+
+    /// Send a connect error packet to a socket that is not connected to its namespace
+    fn send_connect_error(
+        &self,
+        packet: Packet,
+        socket: &EIoSocket<Self>,
+    ) -> Result<(), SendError> {
+        let ns_path = packet.ns.clone();
+        let msg: String = packet.try_into()?;
+        if let Some(observer) = &self.config.packet_observer {
+            observer.on_encode(&PacketMeta {
+                ns: &ns_path,
+                sid: socket.sid,
+                packet_type: PacketType::ConnectError,
+                payload_hash: hash_payload(&msg),
+            });
+        }
+        socket.tx.try_send(EnginePacket::Message(msg)).unwrap();
+        Ok(())
     }
 
     /// Cache-in the socket data until all the binary payloads are received
-    fn sock_recv_bin_packet(&self, socket: &EIoSocket<Self>, packet: Packet) {
-        socket
-            .data
-            .partial_bin_packet
-            .lock()
-            .unwrap()
-            .replace(packet);
+    ///
+    /// A packet without any attachment is propagated right away
+    fn sock_recv_bin_packet(&self, socket: &EIoSocket<Self>, packet: Packet) -> Result<(), Error> {
+        let mut partial = PartialPacket::new(packet)?;
+        match partial.try_complete() {
+            Some(packet) => self.sock_propagate_packet(packet, socket.sid),
+            None => {
+                socket
+                    .data
+                    .partial_bin_packet
+                    .lock()
+                    .unwrap()
+                    .replace(partial);
+                Ok(())
+            }
+        }
     }
 
     /// Propagate a packet to a its target namespace
     fn sock_propagate_packet(&self, packet: Packet, sid: Sid) -> Result<(), Error> {
-        if let Some(ns) = self.ns.get(&packet.ns) {
+        if let Some(ns) = self.get_ns(&packet.ns) {
             ns.recv(sid, packet.inner)
         } else {
             debug!("invalid namespace requested: {}", packet.ns);
@@ -100,7 +183,128 @@ impl<A: Adapter> Client<A> {
     }
 
     fn get_ns(&self, path: &str) -> Option<Arc<Namespace<A>>> {
-        self.ns.get(path).cloned()
+        self.ns.read().unwrap().get(path).cloned()
+    }
+
+    /// Get the namespace at the given path, or create it if a dynamic namespace matches it
+    /// or if the [`UnknownNamespacePolicy`] allows it
+    fn get_or_create_ns(&self, path: &str) -> Option<Arc<Namespace<A>>> {
+        if let Some(ns) = self.get_ns(path) {
+            return Some(ns);
+        }
+        if let Some((template, handler)) = self.dynamic_ns.iter().find(|(_, handler)| {
+            handler
+                .config
+                .matcher
+                .as_ref()
+                .map_or(false, |matcher| matcher.matches(path))
+        }) {
+            let mut ns = self.ns.write().unwrap();
+            if let Some(ns) = ns.get(path) {
+                return Some(ns.clone());
+            }
+            if matches!(self.config.max_namespaces, Some(max) if ns.len() >= max) {
+                debug!("max namespaces reached, rejecting namespace {}", path);
+                return None;
+            }
+            debug!(
+                "creating namespace {} from dynamic namespace {}",
+                path, template
+            );
+            let new_ns = Namespace::from_handler(path.to_string(), handler.clone());
+            self.remove_when_empty(&new_ns);
+            ns.insert(path.to_string(), new_ns.clone());
+            return Some(new_ns);
+        }
+        match self.config.unknown_ns_policy {
+            UnknownNamespacePolicy::Reject => self.get_ns(path),
+            UnknownNamespacePolicy::AutoCreate => {
+                let mut ns = self.ns.write().unwrap();
+                if let Some(ns) = ns.get(path) {
+                    return Some(ns.clone());
+                }
+                if matches!(self.config.max_namespaces, Some(max) if ns.len() >= max) {
+                    debug!("max namespaces reached, rejecting namespace {}", path);
+                    return None;
+                }
+                debug!("creating namespace {}", path);
+                let new_ns = Namespace::new(path, Arc::new(|_: Arc<Socket<A>>| async {}.boxed()));
+                self.remove_when_empty(&new_ns);
+                ns.insert(path.to_string(), new_ns.clone());
+                Some(new_ns)
+            }
+        }
+    }
+
+    /// Remove a namespace created on demand from the client once its last socket left,
+    /// so they don't pile up and count towards the `max_namespaces` limit forever.
+    ///
+    /// A namespace with connection state recovery is kept, its adapter holds the sessions to recover
+    fn remove_when_empty(&self, ns: &Namespace<A>) {
+        if ns.config.recovery_ttl.is_some() {
+            return;
+        }
+        // The namespaces don't keep the map they are in alive
+        let namespaces = Arc::downgrade(&self.ns);
+        ns.remove_when_empty(move |path| {
+            let Some(namespaces) = namespaces.upgrade() else {
+                return;
+            };
+            let mut namespaces = namespaces.write().unwrap();
+            // A namespace created again at the same path is kept
+            if namespaces.get(path).map_or(false, |ns| ns.is_removed()) {
+                debug!("removing empty namespace {}", path);
+                namespaces.remove(path);
+            }
+        });
+    }
+
+    /// Get a snapshot of the namespaces, so the lock is not held while using them
+    fn namespaces(&self) -> Vec<Arc<Namespace<A>>> {
+        self.ns.read().unwrap().values().cloned().collect()
+    }
+
+    /// Broadcast an event to all the sockets of all the namespaces connected to this server.
+    ///
+    /// The event body is serialized only once, only the namespace header is rewritten for each namespace.
+    /// Returns the number of sockets reached.
+    pub(crate) fn broadcast_all(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<usize, BroadcastError> {
+        let event_packet = Packet::event("/".to_string(), event_name(event)?, event_data(data)?);
+        let mut packet = event_packet.clone();
+        if let Some(transform) = &self.config.payload_transform {
+            packet = packet.transform_payload(transform.as_ref())?;
+        }
+        let (encoded, payload) = packet.encode()?;
+
+        let mut count = 0;
+        let mut errors = Vec::new();
+        for ns in self.namespaces() {
+            let msg: Arc<str> = Packet::with_ns_header(&encoded, &ns.path).into();
+            for socket in ns.get_sockets() {
+                // The packet is serialized for each socket if the packets are rewritten for each socket
+                // or if the namespace inlines the binary attachments of the transformed payloads
+                let res = if socket.needs_own_packet() || ns.config.inline_binary {
+                    let mut packet = event_packet.clone();
+                    packet.ns = ns.path.clone();
+                    socket.send(packet)
+                } else {
+                    socket.send_shared(&msg, payload.clone(), None)
+                };
+                match res {
+                    Ok(()) => count += 1,
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors.into())
+        }
     }
 }
 
@@ -108,7 +312,7 @@ impl<A: Adapter> Client<A> {
 pub struct SocketData {
     /// Partial binary packet that is being received
     /// Stored here until all the binary payloads are received
-    pub partial_bin_packet: Mutex<Option<Packet>>,
+    pub partial_bin_packet: Mutex<Option<PartialPacket>>,
 }
 
 #[engineioxide::async_trait]
@@ -120,8 +324,8 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
     }
     fn on_disconnect(&self, socket: &EIoSocket<Self>) {
         debug!("eio socket disconnect {}", socket.sid);
-        self.ns.values().for_each(|ns| {
-            if let Err(e) = ns.remove_socket(socket.sid) {
+        self.namespaces().into_iter().for_each(|ns| {
+            if let Err(e) = ns.remove_socket(socket.sid, DisconnectReason::TransportClose) {
                 error!("Adapter error when disconnecting {}: {}, in a multiple server scenario it could leads to desyncronisation issues", socket.sid, e);
             }
         });
@@ -129,15 +333,36 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
 
     fn on_message(&self, msg: String, socket: &EIoSocket<Self>) {
         debug!("Received message: {:?}", msg);
-        let packet = match Packet::try_from(msg) {
+        // The payload is only hashed if it is observed
+        let payload_hash = self
+            .config
+            .packet_observer
+            .as_ref()
+            .map(|_| hash_payload(&msg));
+        // A malformed or oversized packet is skipped rather than closing the socket,
+        // so it doesn't discard the other packets received in the same polling batch
+        let packet = match Packet::decode(msg, self.config.max_payload_bytes) {
             Ok(packet) => packet,
             Err(e) => {
-                debug!("socket serialization error: {}", e);
-                socket.close();
+                debug!("[sid={}] skipping invalid packet: {}", socket.sid, e);
                 return;
             }
         };
         debug!("Packet: {:?}", packet);
+        // A binary packet still waiting for attachments is interrupted by this packet
+        if let Some(mut partial) = socket.data.partial_bin_packet.lock().unwrap().take() {
+            if let Some(e) = partial.interrupted() {
+                debug!("[sid={}] dropping binary packet: {}", socket.sid, e);
+            }
+        }
+        if let (Some(observer), Some(payload_hash)) = (&self.config.packet_observer, payload_hash) {
+            observer.on_decode(&PacketMeta {
+                ns: &packet.ns,
+                sid: socket.sid,
+                packet_type: PacketType::from_data(&packet.inner),
+                payload_hash,
+            });
+        }
 
         #[derive(Debug)]
         enum CurrentError {
@@ -148,10 +373,9 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
             PacketData::Connect(auth) => self
                 .sock_connect(auth, packet.ns, socket)
                 .map_err(CurrentError::SendError),
-            PacketData::BinaryEvent(_, _, _) | PacketData::BinaryAck(_, _) => {
-                self.sock_recv_bin_packet(socket, packet);
-                Ok(())
-            }
+            PacketData::BinaryEvent(_, _, _) | PacketData::BinaryAck(_, _) => self
+                .sock_recv_bin_packet(socket, packet)
+                .map_err(CurrentError::CommonError),
             _ => self
                 .sock_propagate_packet(packet, socket.sid)
                 .map_err(CurrentError::CommonError),
@@ -166,15 +390,13 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
     ///
     /// If the packet is complete, it is propagated to the namespace
     fn on_binary(&self, data: Vec<u8>, socket: &EIoSocket<Self>) {
-        if self.apply_payload_on_packet(data, socket) {
-            if let Some(packet) = socket.data.partial_bin_packet.lock().unwrap().take() {
-                if let Err(e) = self.sock_propagate_packet(packet, socket.sid) {
-                    debug!(
-                        "error while propagating packet to socket {}: {}",
-                        socket.sid, e
-                    );
-                    socket.close();
-                }
+        if let Some(packet) = self.apply_payload_on_packet(data, socket) {
+            if let Err(e) = self.sock_propagate_packet(packet, socket.sid) {
+                debug!(
+                    "error while propagating packet to socket {}: {}",
+                    socket.sid, e
+                );
+                socket.close();
             }
         }
     }
@@ -185,6 +407,278 @@ impl<A: Adapter> Clone for Client<A> {
         Self {
             config: self.config.clone(),
             ns: self.ns.clone(),
+            dynamic_ns: self.dynamic_ns.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::adapter::LocalAdapter;
+    use crate::handshake::Handshake;
+    use crate::observer::PacketObserver;
+    use crate::testing::try_connect_socket;
+    use engineioxide::SendPacket;
+
+    #[test]
+    fn test_allowed_origins() {
+        let config = SocketIoConfig::default();
+        assert!(config.is_origin_allowed(Some("https://evil.com")));
+        assert!(config.is_origin_allowed(None));
+
+        let config = SocketIoConfig::builder()
+            .allowed_origins(["https://example.com"])
+            .build();
+        assert!(config.is_origin_allowed(Some("https://example.com")));
+        assert!(config.is_origin_allowed(Some("https://EXAMPLE.com")));
+        assert!(!config.is_origin_allowed(Some("https://evil.com")));
+        assert!(!config.is_origin_allowed(Some("null")));
+        assert!(config.is_origin_allowed(None));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_ns_policy() {
+        let ns_handlers = Namespace::builder().add("/", |_| async move {}).build();
+        let client: Client<LocalAdapter> = Client::new(SocketIoConfig::default(), ns_handlers);
+        assert!(client.get_or_create_ns("/unknown").is_none());
+        assert!(client.get_ns("/unknown").is_none());
+
+        let ns_handlers = Namespace::builder().add("/", |_| async move {}).build();
+        let config = SocketIoConfig::builder()
+            .unknown_ns_policy(UnknownNamespacePolicy::AutoCreate)
+            .build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+        let ns = client.get_or_create_ns("/unknown").unwrap();
+        assert_eq!(ns.path, "/unknown");
+        try_connect_socket(
+            &ns,
+            1i64.into(),
+            Handshake::new_dummy(),
+            client.config.clone(),
+        )
+        .unwrap();
+        let ns = client.get_or_create_ns("/unknown").unwrap();
+        assert_eq!(ns.get_sockets().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_namespaces() {
+        let ns_handlers = Namespace::builder().add("/", |_| async move {}).build();
+        let config = SocketIoConfig::builder()
+            .unknown_ns_policy(UnknownNamespacePolicy::AutoCreate)
+            .build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+        let clone = client.clone();
+
+        let ns = clone.get_or_create_ns("/unknown").unwrap();
+        assert!(Arc::ptr_eq(&client.get_ns("/unknown").unwrap(), &ns));
+        assert_eq!(client.namespaces().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_remove_empty_auto_created_ns() {
+        let ns_handlers = Namespace::builder().add("/", |_| async move {}).build();
+        let config = SocketIoConfig::builder()
+            .unknown_ns_policy(UnknownNamespacePolicy::AutoCreate)
+            .build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+        let connect = |ns: &Arc<Namespace<LocalAdapter>>, sid: i64| {
+            try_connect_socket(
+                ns,
+                sid.into(),
+                Handshake::new_dummy(),
+                client.config.clone(),
+            )
+        };
+
+        let ns = client.get_or_create_ns("/unknown").unwrap();
+        connect(&ns, 1).unwrap();
+        connect(&ns, 2).unwrap();
+        ns.remove_socket(1i64.into(), DisconnectReason::TransportClose)
+            .unwrap();
+        assert!(client.get_ns("/unknown").is_some());
+        ns.remove_socket(2i64.into(), DisconnectReason::TransportClose)
+            .unwrap();
+        assert!(client.get_ns("/unknown").is_none());
+
+        // No socket can connect to the removed namespace, it is created again
+        assert!(matches!(connect(&ns, 3), Err(Error::NamespaceRemoved(_))));
+        let new_ns = client.get_or_create_ns("/unknown").unwrap();
+        assert!(!Arc::ptr_eq(&ns, &new_ns));
+        connect(&new_ns, 3).unwrap();
+
+        // The namespaces registered on the server are kept
+        let ns = client.get_ns("/").unwrap();
+        connect(&ns, 4).unwrap();
+        ns.remove_socket(4i64.into(), DisconnectReason::TransportClose)
+            .unwrap();
+        assert!(client.get_ns("/").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_max_namespaces() {
+        let ns_handlers = Namespace::builder().add("/", |_| async move {}).build();
+        let config = SocketIoConfig::builder()
+            .unknown_ns_policy(UnknownNamespacePolicy::AutoCreate)
+            .max_namespaces(3)
+            .build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+        assert!(client.get_or_create_ns("/a").is_some());
+        assert!(client.get_or_create_ns("/b").is_some());
+        assert!(client.get_or_create_ns("/c").is_none());
+
+        // Existing namespaces are still reachable once the limit is reached
+        assert!(client.get_or_create_ns("/").is_some());
+        assert!(client.get_or_create_ns("/a").is_some());
+        assert_eq!(client.namespaces().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_ns() {
+        let ns_handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .add("/workspaces", |socket| async move {
+                socket.join(socket.ns().clone()).ok();
+            })
+            .dynamic("/workspaces", |path| path.starts_with("/workspaces/"))
+            .build();
+        let config = SocketIoConfig::builder().max_namespaces(3).build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+
+        // The template itself is not instantiated
+        assert!(client.get_or_create_ns("/workspaces").is_none());
+        assert!(client.get_or_create_ns("/other").is_none());
+
+        let ns = client.get_or_create_ns("/workspaces/acme").unwrap();
+        assert_eq!(ns.path, "/workspaces/acme");
+        let (socket, _rx) = try_connect_socket(
+            &ns,
+            1i64.into(),
+            Handshake::new_dummy(),
+            client.config.clone(),
+        )
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(
+            socket.rooms().unwrap(),
+            vec!["/workspaces/acme".to_string()]
+        );
+
+        // Each matching path gets its own namespace and adapter
+        let ns = client.get_or_create_ns("/workspaces/globex").unwrap();
+        assert_eq!(ns.get_sockets().len(), 0);
+        assert_eq!(
+            client
+                .get_or_create_ns("/workspaces/acme")
+                .unwrap()
+                .get_sockets()
+                .len(),
+            1
+        );
+        assert!(client.get_or_create_ns("/workspaces/initech").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_ns_registration_order() {
+        // Every template matches, the first registered one must win whatever the map order
+        let mut builder = Namespace::builder();
+        for i in 0..8 {
+            let path = format!("/template{i}");
+            builder = builder
+                .add(path.clone(), |_| async move {})
+                .default_rooms(path.clone(), vec![i.to_string()])
+                .dynamic(path, |_| true);
+        }
+        let client: Client<LocalAdapter> = Client::new(SocketIoConfig::default(), builder.build());
+        let ns = client.get_or_create_ns("/any").unwrap();
+        assert_eq!(ns.config.default_rooms, vec!["0".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_all() {
+        let ns_handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .add("/admin", |_| async move {})
+            .add("/empty", |_| async move {})
+            .build();
+        let client = Client::new(SocketIoConfig::default(), ns_handlers);
+        let config = client.config.clone();
+
+        let mut receivers = Vec::new();
+        for (i, path) in ["/", "/admin", "/admin"].into_iter().enumerate() {
+            let ns = client.get_ns(path).unwrap();
+            let (_, rx) = try_connect_socket(
+                &ns,
+                (i as i64).into(),
+                Handshake::new_dummy(),
+                config.clone(),
+            )
+            .unwrap();
+            receivers.push(rx);
+        }
+
+        assert_eq!(client.broadcast_all("news", "hello").unwrap(), 3);
+        let mut msgs = Vec::new();
+        for rx in &mut receivers {
+            match rx.recv().await.unwrap() {
+                SendPacket::SharedMessage(msg) => msgs.push(msg),
+                p => panic!("unexpected packet: {:?}", p),
+            }
+        }
+        // The sockets of a namespace share the same encoded packet
+        assert!(Arc::ptr_eq(&msgs[1], &msgs[2]));
+        assert_eq!(
+            msgs.iter().map(|msg| msg.as_ref()).collect::<Vec<_>>(),
+            [
+                r#"2["news","hello"]"#,
+                r#"2/admin,["news","hello"]"#,
+                r#"2/admin,["news","hello"]"#
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn test_packet_observer() {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<(String, Sid, PacketType, u64)>>);
+        impl PacketObserver for Recorder {
+            fn on_encode(&self, meta: &PacketMeta<'_>) {
+                self.0.lock().unwrap().push((
+                    meta.ns.to_string(),
+                    meta.sid,
+                    meta.packet_type,
+                    meta.payload_hash,
+                ));
+            }
+        }
+        let recorder = Arc::new(Recorder::default());
+        let config = SocketIoConfig::builder()
+            .packet_observer(recorder.clone())
+            .build();
+        let ns_handlers = Namespace::builder()
+            .add("/admin", |_| async move {})
+            .build();
+        let client: Client<LocalAdapter> = Client::new(config, ns_handlers);
+
+        let sid: Sid = 1i64.into();
+        let ns = client.get_ns("/admin").unwrap();
+        let (socket, mut rx) =
+            try_connect_socket(&ns, sid, Handshake::new_dummy(), client.config.clone()).unwrap();
+        socket.emit("news", "hello").unwrap();
+        let msg = match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => msg,
+            p => panic!("unexpected packet: {:?}", p),
+        };
+
+        let observed = recorder.0.lock().unwrap();
+        assert_eq!(
+            *observed,
+            [(
+                "/admin".to_string(),
+                sid,
+                PacketType::Event,
+                hash_payload(&msg)
+            )]
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use engineioxide::config::EngineIoConfig;
 
+use crate::{observer::PacketObserver, transform::PayloadTransform};
+
 /// Builder for SocketIoConfig
 pub struct SocketIoConfigBuilder {
     config: SocketIoConfig,
@@ -65,6 +67,83 @@ impl SocketIoConfigBuilder {
         self
     }
 
+    /// The amount of time the pending acknowledgements of a disconnected socket are kept before failing
+    /// with an [`AckError::Disconnected`](crate::AckError::Disconnected) error.
+    ///
+    /// If the namespace recovers the connection state, a client reconnecting within this period
+    /// hands the pending acknowledgements over to its new socket, so the acknowledgements it sends
+    /// after reconnecting resolve them.
+    ///
+    /// Defaults to 0, the pending acknowledgements fail as soon as the socket disconnects.
+    pub fn ack_disconnect_grace(mut self, ack_disconnect_grace: Duration) -> Self {
+        self.config.ack_disconnect_grace = ack_disconnect_grace;
+        self
+    }
+
+    /// The behavior of the server when a client connects to a namespace that was not registered.
+    ///
+    /// Defaults to [`UnknownNamespacePolicy::Reject`].
+    pub fn unknown_ns_policy(mut self, policy: UnknownNamespacePolicy) -> Self {
+        self.config.unknown_ns_policy = policy;
+        self
+    }
+
+    /// The maximum number of namespaces, including the registered ones.
+    /// When it is reached, connections to namespaces that would be created on demand,
+    /// with [`UnknownNamespacePolicy::AutoCreate`] or from a dynamic namespace,
+    /// are rejected with an `Invalid namespace` connect error.
+    /// A namespace created on demand is removed once its last socket left, unless it recovers
+    /// the connection state of its sockets.
+    ///
+    /// Defaults to unbounded.
+    pub fn max_namespaces(mut self, max_namespaces: usize) -> Self {
+        self.config.max_namespaces = Some(max_namespaces);
+        self
+    }
+
+    /// The maximum size in bytes of a packet received from a client, checked before it is parsed.
+    /// Bigger packets are dropped, the binary attachments are limited by [`max_payload`](Self::max_payload).
+    ///
+    /// Defaults to 1 MiB.
+    pub fn max_payload_bytes(mut self, max_payload_bytes: usize) -> Self {
+        self.config.max_payload_bytes = max_payload_bytes;
+        self
+    }
+
+    /// The origins allowed to connect, compared with the `Origin` header of the handshake request,
+    /// for example `https://example.com`. The comparison is case insensitive.
+    ///
+    /// Connections from other origins are rejected with an `Origin not allowed` connect error.
+    /// Requests without an `Origin` header are not sent by browsers, so they are always allowed.
+    ///
+    /// Defaults to none, all the origins are allowed.
+    pub fn allowed_origins<I, S>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.allowed_origins = Some(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// An observer called with the metadata of every packet encoded and decoded by the server,
+    /// see the [`observer`](crate::observer) module.
+    ///
+    /// Defaults to none, the packets are not observed.
+    pub fn packet_observer(mut self, observer: Arc<dyn PacketObserver>) -> Self {
+        self.config.packet_observer = Some(observer);
+        self
+    }
+
+    /// A transformation applied to the payloads of the events and acknowledgements,
+    /// for example to encrypt them, see the [`transform`](crate::transform) module.
+    ///
+    /// Defaults to none, the payloads are sent as is.
+    pub fn payload_transform(mut self, transform: Arc<dyn PayloadTransform>) -> Self {
+        self.config.payload_transform = Some(transform);
+        self
+    }
+
     /// Build the config
     pub fn build(self) -> SocketIoConfig {
         self.config
@@ -77,8 +156,18 @@ impl Default for SocketIoConfigBuilder {
     }
 }
 
+/// The behavior of the server when a client connects to a namespace that was not registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownNamespacePolicy {
+    /// Reject the connection with an `Invalid namespace` connect error
+    Reject,
+    /// Create the namespace on demand, with an empty connect handler and the default namespace configuration.
+    /// It is removed once its last socket left.
+    AutoCreate,
+}
+
 /// Configuration for Socket.IO & Engine.IO
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct SocketIoConfig {
     /// The inner Engine.IO config
     pub(crate) engine_config: EngineIoConfig,
@@ -87,6 +176,42 @@ pub struct SocketIoConfig {
     ///
     /// Defaults to 5 seconds.
     pub(crate) ack_timeout: Duration,
+
+    /// The amount of time the pending acknowledgements of a disconnected socket are kept before failing.
+    /// With connection state recovery, they are handed over to the socket recovering the session.
+    ///
+    /// Defaults to 0.
+    pub(crate) ack_disconnect_grace: Duration,
+
+    /// The behavior of the server when a client connects to a namespace that was not registered.
+    ///
+    /// Defaults to [`UnknownNamespacePolicy::Reject`].
+    pub(crate) unknown_ns_policy: UnknownNamespacePolicy,
+
+    /// The maximum number of namespaces, including the registered ones.
+    ///
+    /// Defaults to unbounded.
+    pub(crate) max_namespaces: Option<usize>,
+
+    /// The maximum size in bytes of a packet received from a client, checked before it is parsed.
+    ///
+    /// Defaults to 1 MiB.
+    pub(crate) max_payload_bytes: usize,
+
+    /// The origins allowed to connect, compared with the `Origin` header of the handshake request.
+    ///
+    /// Defaults to none, all the origins are allowed.
+    pub(crate) allowed_origins: Option<Vec<String>>,
+
+    /// An observer called with the metadata of every packet encoded and decoded by the server.
+    ///
+    /// Defaults to none.
+    pub(crate) packet_observer: Option<Arc<dyn PacketObserver>>,
+
+    /// A transformation applied to the payloads of the events and acknowledgements.
+    ///
+    /// Defaults to none.
+    pub(crate) payload_transform: Option<Arc<dyn PayloadTransform>>,
 }
 
 impl Default for SocketIoConfig {
@@ -97,11 +222,42 @@ impl Default for SocketIoConfig {
                 ..Default::default()
             },
             ack_timeout: Duration::from_secs(5),
+            ack_disconnect_grace: Duration::ZERO,
+            unknown_ns_policy: UnknownNamespacePolicy::Reject,
+            max_namespaces: None,
+            max_payload_bytes: 1 << 20,
+            allowed_origins: None,
+            packet_observer: None,
+            payload_transform: None,
         }
     }
 }
+
+impl std::fmt::Debug for SocketIoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketIoConfig")
+            .field("engine_config", &self.engine_config)
+            .field("ack_timeout", &self.ack_timeout)
+            .field("ack_disconnect_grace", &self.ack_disconnect_grace)
+            .field("unknown_ns_policy", &self.unknown_ns_policy)
+            .field("max_namespaces", &self.max_namespaces)
+            .field("max_payload_bytes", &self.max_payload_bytes)
+            .field("allowed_origins", &self.allowed_origins)
+            .field("packet_observer", &self.packet_observer.is_some())
+            .field("payload_transform", &self.payload_transform.is_some())
+            .finish()
+    }
+}
 impl SocketIoConfig {
     pub fn builder() -> SocketIoConfigBuilder {
         SocketIoConfigBuilder::new()
     }
+
+    /// Check the `Origin` header of a handshake request against the allowed origins
+    pub(crate) fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed_origins, origin) {
+            (Some(allowed), Some(origin)) => allowed.iter().any(|o| o.eq_ignore_ascii_case(origin)),
+            _ => true,
+        }
+    }
 }
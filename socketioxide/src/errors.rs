@@ -1,3 +1,4 @@
+use crate::adapter::Room;
 use crate::retryer::Retryer;
 use engineioxide::sid_generator::Sid;
 use std::fmt::{Debug, Display};
@@ -15,12 +16,45 @@ pub enum Error {
     #[error("invalid event name")]
     InvalidEventName,
 
+    /// A received packet is bigger than the `max_payload_bytes` limit
+    #[error("payload too large: {size} bytes, the maximum is {max} bytes")]
+    PayloadTooLarge { size: usize, max: usize },
+
+    /// A connect error packet is not a JSON object,
+    /// it is most likely a packet whose engine.io message prefix was not stripped
+    #[error("invalid connect error packet")]
+    InvalidConnectError,
+
+    /// A binary packet received a different number of attachments than announced in its header
+    #[error("invalid attachment count: expected {expected}, got {got}")]
+    InvalidAttachmentCount { expected: usize, got: usize },
+
     #[error("cannot find socketio engine")]
     EngineGone,
 
     #[error("cannot find socketio socket")]
     SocketGone(Sid),
 
+    /// The socket reached the maximum number of acknowledgements it can wait for
+    #[error("too many pending acks for socket {0}")]
+    TooManyPendingAcks(Sid),
+
+    /// The namespace configuration given to a [`NsBuilder`](crate::NsBuilder) is invalid
+    #[error("invalid namespace config: {0}")]
+    InvalidNsConfig(&'static str),
+
+    /// The namespace reached its maximum number of connections
+    #[error("namespace {0} is full")]
+    NamespaceFull(String),
+
+    /// The namespace was created on demand and removed once its last socket left
+    #[error("namespace {0} was removed")]
+    NamespaceRemoved(String),
+
+    /// The targeted room doesn't exist
+    #[error("room not found: {0}")]
+    RoomNotFound(Room),
+
     /// An engineio error
     #[error("engineio error: {0}")]
     EngineIoError(#[from] engineioxide::errors::Error),
@@ -44,6 +78,10 @@ pub enum AckError {
     #[error("ack timeout error")]
     AckTimeoutError(#[from] tokio::time::error::Elapsed),
 
+    /// The socket disconnected before sending the ack response
+    #[error("socket disconnected before the ack response")]
+    Disconnected,
+
     /// Internal error
     #[error("internal error: {0}")]
     InternalError(#[from] Error),
@@ -65,6 +103,10 @@ pub enum BroadcastError {
 
     #[error("Adapter error: {0}")]
     Adapter(#[from] AdapterError),
+
+    /// The packet cannot be emitted, like an event with an [`Error::InvalidEventName`]
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(#[from] Error),
 }
 
 impl From<Vec<SendError>> for BroadcastError {
@@ -94,6 +136,10 @@ pub enum SendError {
 
     #[error("Adapter error: {0}")]
     AdapterError(#[from] AdapterError),
+
+    /// The packet cannot be emitted, like an event with an [`Error::InvalidEventName`]
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(#[from] Error),
 }
 
 /// Error type for the `Retryer` struct indicating various failure scenarios during the retry process.
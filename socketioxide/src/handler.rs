@@ -1,23 +1,53 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use futures::future::BoxFuture;
+use engineioxide::sid_generator::Sid;
+use futures::{future::BoxFuture, Future};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
 use crate::errors::SendError;
 use crate::{adapter::Adapter, errors::Error, packet::Packet, Socket};
 
-pub type AckResponse<T> = (T, Vec<Vec<u8>>);
+/// An acknowledgement sent back by a client.
+#[derive(Debug)]
+pub struct AckResponse<T> {
+    /// The data of the acknowledgement
+    ///
+    /// It is deserialized from the array of arguments sent by the client.
+    /// A client sending a bare value instead of an array is handled as if it sent a single argument.
+    pub data: T,
+    /// The binary payloads sent with the acknowledgement
+    pub binary: Vec<Vec<u8>>,
+    /// The id of the socket that sent the acknowledgement
+    pub sid: Sid,
+    /// The round-trip time between the moment the message was handed to the transport
+    /// and the moment the acknowledgement was received, to monitor the responsiveness of the clients
+    pub elapsed: Duration,
+}
 
-pub(crate) type BoxedHandler<A> = Box<dyn MessageCaller<A>>;
+/// Handlers are shared so they can be called without holding the handlers lock
+pub(crate) type SharedHandler<A> = Arc<dyn MessageCaller<A>>;
 pub(crate) trait MessageCaller<A: Adapter>: Send + Sync + 'static {
+    /// Deserialize the data and return the handler future, it is spawned by the namespace
     fn call(
         &self,
         s: Arc<Socket<A>>,
         v: Value,
         p: Vec<Vec<u8>>,
         ack_id: Option<i64>,
-    ) -> Result<(), Error>;
+    ) -> Result<BoxFuture<'static, ()>, Error>;
+}
+
+/// Wrap an event callback into a [`SharedHandler`]
+pub(crate) fn shared_handler<A, C, F, V>(callback: C) -> SharedHandler<A>
+where
+    A: Adapter,
+    C: Fn(Arc<Socket<A>>, V, Vec<Vec<u8>>, AckSender<A>) -> F + Send + Sync + 'static,
+    F: Future<Output = ()> + Send + 'static,
+    V: DeserializeOwned + Send + Sync + 'static,
+{
+    let handler = Box::new(move |s, v, p, ack_fn| Box::pin(callback(s, v, p, ack_fn)) as _);
+    MessageHandler::shared(handler)
 }
 
 pub(crate) struct MessageHandler<Param, F, A>
@@ -38,8 +68,8 @@ where
         + 'static,
     A: Adapter,
 {
-    pub fn boxed(handler: F) -> Box<Self> {
-        Box::new(Self {
+    pub fn shared(handler: F) -> Arc<Self> {
+        Arc::new(Self {
             param: std::marker::PhantomData,
             adapter: std::marker::PhantomData,
             handler,
@@ -62,7 +92,7 @@ where
         v: Value,
         p: Vec<Vec<u8>>,
         ack_id: Option<i64>,
-    ) -> Result<(), Error> {
+    ) -> Result<BoxFuture<'static, ()>, Error> {
         // Unwrap array if it has only one element
         let v = match v {
             Value::Array(v) => {
@@ -76,9 +106,12 @@ where
         };
         let v: Param = serde_json::from_value(v)?;
         let owned_socket = s.clone();
-        let fut = (self.handler)(s, v, p, AckSender::new(owned_socket, ack_id));
-        tokio::spawn(fut);
-        Ok(())
+        Ok((self.handler)(
+            s,
+            v,
+            p,
+            AckSender::new(owned_socket, ack_id),
+        ))
     }
 }
 
@@ -6,7 +6,7 @@ use serde::de::DeserializeOwned;
 use crate::errors::Error;
 
 /// Handshake informations bound to a socket
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Handshake {
     pub(crate) auth: serde_json::Value,
     pub issued: SystemTime,
@@ -24,12 +24,29 @@ impl Handshake {
     /// Extract the data from the handshake.
     ///
     /// It is cloned and deserialized from a json::Value to the given type.
+    /// The fields that are not part of the given type are ignored but still available with [`Handshake::auth`].
     pub fn data<T: DeserializeOwned>(&self) -> Result<T, Error> {
         Ok(serde_json::from_value(self.auth.clone())?)
     }
+
+    /// Get the raw connect payload sent by the client.
+    ///
+    /// Unlike [`Handshake::data`], it keeps all the fields,
+    /// including the ones the server doesn't model yet.
+    pub fn auth(&self) -> &serde_json::Value {
+        &self.auth
+    }
+
+    /// Get the `Origin` header of the handshake request, if it is present and valid.
+    pub fn origin(&self) -> Option<&str> {
+        self.req
+            .headers
+            .get(http::header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+    }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testing"))]
 impl Handshake {
     pub fn new_dummy() -> Self {
         Self {
@@ -42,3 +59,40 @@ impl Handshake {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[test]
+    fn test_auth_keeps_unknown_fields() {
+        #[derive(Debug, Deserialize)]
+        struct Auth {
+            token: String,
+        }
+        let handshake = Handshake {
+            auth: json!({ "token": "123", "device": "mobile" }),
+            ..Handshake::new_dummy()
+        };
+        assert_eq!(handshake.data::<Auth>().unwrap().token, "123");
+        assert_eq!(handshake.auth()["device"], "mobile");
+    }
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(Handshake::new_dummy().origin(), None);
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::ORIGIN, "https://example.com".parse().unwrap());
+        let handshake = Handshake {
+            req: Arc::new(SocketReq {
+                headers,
+                uri: Default::default(),
+            }),
+            ..Handshake::new_dummy()
+        };
+        assert_eq!(handshake.origin(), Some("https://example.com"));
+    }
+}
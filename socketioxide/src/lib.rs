@@ -61,15 +61,26 @@
 //! ```
 
 pub mod adapter;
+pub mod observer;
 pub mod retryer;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transform;
 
-pub use config::{SocketIoConfig, SocketIoConfigBuilder};
+pub use config::{SocketIoConfig, SocketIoConfigBuilder, UnknownNamespacePolicy};
+pub use engineioxide::service::TransportType;
 pub use errors::{AckError, Error as SocketError};
+pub use handler::AckResponse;
 pub use layer::SocketIoLayer;
-pub use ns::Namespace;
+pub use ns::{Namespace, NsBuilder};
+pub use operators::DeliveryReport;
+pub use packet::{BinaryPacket, ConnectErrorPacket, Packet, PacketData};
 pub use service::SocketIoService;
-pub use socket::Socket;
+pub use socket::{
+    Batch, DisconnectReason, PendingAck, SendOutcome, Socket, SocketState, SocketView, WeakSocket,
+};
 
+mod buffer_pool;
 mod client;
 mod config;
 mod errors;
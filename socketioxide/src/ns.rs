@@ -1,32 +1,210 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::Duration,
 };
 
-use crate::errors::{AdapterError, SendError};
+use crate::errors::{AdapterError, BroadcastError, SendError};
 use crate::{
-    adapter::{Adapter, LocalAdapter},
+    adapter::{Adapter, BroadcastOptions, LocalAdapter, Room},
     errors::Error,
+    handler::{shared_handler, AckSender, SharedHandler},
     handshake::Handshake,
-    packet::{Packet, PacketData},
-    socket::Socket,
+    operators::RoomParam,
+    packet::{event_data, Packet, PacketData},
+    socket::{DisconnectReason, Socket},
     SocketIoConfig,
 };
 use engineioxide::sid_generator::Sid;
+use engineioxide::socket::{FlushHandle, TransportHandle};
 use engineioxide::SendPacket as EnginePacket;
 use futures::{future::BoxFuture, Future};
-use tokio::sync::mpsc;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::{mpsc, Semaphore};
 
 pub type EventCallback<A> =
     Arc<dyn Fn(Arc<Socket<A>>) -> BoxFuture<'static, ()> + Send + Sync + 'static>;
 
-pub type NsHandlers<A> = HashMap<String, EventCallback<A>>;
+pub type NsHandlers<A> = HashMap<String, NsHandler<A>>;
+
+/// Remove a namespace created on demand from the client, it is called with the path of the namespace
+pub(crate) type RemoveFn = Box<dyn Fn(&str) + Send + Sync + 'static>;
+
+/// Provide the extra fields of the connect response from the handshake of the socket
+pub(crate) type ConnectResponseFn =
+    Arc<dyn Fn(&Handshake) -> Map<String, Value> + Send + Sync + 'static>;
+
+/// Select the namespace paths that instantiate a dynamic namespace
+#[derive(Clone)]
+pub(crate) struct NsMatcher {
+    /// The registration order, the first registered matcher wins when several select a path
+    order: usize,
+    matcher: Arc<dyn Fn(&str) -> bool + Send + Sync + 'static>,
+}
+
+impl NsMatcher {
+    fn new(matcher: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        static NEXT_ORDER: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            order: NEXT_ORDER.fetch_add(1, Ordering::Relaxed),
+            matcher: Arc::new(matcher),
+        }
+    }
+
+    pub(crate) fn order(&self) -> usize {
+        self.order
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        (self.matcher)(path)
+    }
+}
+
+/// Rewrite a packet before it is sent to a socket of the namespace
+pub(crate) type OutgoingInterceptor<A> =
+    Arc<dyn Fn(&Socket<A>, Packet) -> Packet + Send + Sync + 'static>;
+
+/// The connect callback of a namespace along with its configuration and namespace-wide event handlers
+pub struct NsHandler<A: Adapter> {
+    pub(crate) callback: EventCallback<A>,
+    pub(crate) config: NsConfig,
+    pub(crate) message_handlers: HashMap<String, SharedHandler<A>>,
+    pub(crate) outgoing_interceptor: Option<OutgoingInterceptor<A>>,
+}
+
+impl<A: Adapter> NsHandler<A> {
+    fn new(callback: EventCallback<A>, config: NsConfig) -> Self {
+        Self {
+            callback,
+            config,
+            message_handlers: HashMap::new(),
+            outgoing_interceptor: None,
+        }
+    }
+}
+
+impl<A: Adapter> Clone for NsHandler<A> {
+    fn clone(&self) -> Self {
+        Self {
+            callback: self.callback.clone(),
+            config: self.config.clone(),
+            message_handlers: self.message_handlers.clone(),
+            outgoing_interceptor: self.outgoing_interceptor.clone(),
+        }
+    }
+}
+
+impl<A: Adapter> std::fmt::Debug for NsHandler<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NsHandler")
+            .field("config", &self.config)
+            .field("message_handlers", &self.message_handlers.keys())
+            .field("outgoing_interceptor", &self.outgoing_interceptor.is_some())
+            .finish()
+    }
+}
+
+/// Configuration specific to a namespace
+#[derive(Clone)]
+pub struct NsConfig {
+    /// Rooms that every socket joins when connecting to the namespace
+    pub(crate) default_rooms: Vec<Room>,
+    /// The maximum number of acknowledgements that a socket can wait for at the same time
+    pub(crate) max_pending_acks: usize,
+    /// A function applied to the event names when registering handlers and dispatching incoming events
+    pub(crate) event_normalizer: Option<fn(&str) -> String>,
+    /// An event emitted to a socket when it doesn't acknowledge a message in time
+    pub(crate) ack_timeout_event: Option<String>,
+    /// The maximum number of event handlers running at the same time, unbounded if `None`
+    pub(crate) max_concurrent_handlers: Option<usize>,
+    /// A provider of extra fields sent to the client with the `sid` in the connect response
+    pub(crate) connect_response: Option<ConnectResponseFn>,
+    /// Whether the sockets can send binary events and acknowledgements
+    pub(crate) allow_binary: bool,
+    /// The maximum number of sockets connected at the same time, unbounded if `None`
+    pub(crate) max_connections: Option<usize>,
+    /// The initial capacity of the rooms, to avoid resizing them while they grow
+    pub(crate) room_capacity: usize,
+    /// Whether the binary attachments are sent in base64 strings rather than in binary frames
+    pub(crate) inline_binary: bool,
+    /// Whether a sequence number is appended to the events sent to the sockets
+    pub(crate) sequence_numbers: bool,
+    /// How long the session of a disconnected socket is kept to be recovered, disabled if `None`
+    pub(crate) recovery_ttl: Option<Duration>,
+    /// The maximum number of missed packets buffered for each persisted session
+    pub(crate) recovery_max_packets: usize,
+    /// Instantiate a namespace for each connecting path selected by this matcher, if set
+    pub(crate) matcher: Option<NsMatcher>,
+}
+
+impl Default for NsConfig {
+    fn default() -> Self {
+        Self {
+            default_rooms: Vec::new(),
+            max_pending_acks: 1024,
+            event_normalizer: None,
+            ack_timeout_event: None,
+            max_concurrent_handlers: None,
+            connect_response: None,
+            allow_binary: true,
+            max_connections: None,
+            room_capacity: 0,
+            inline_binary: false,
+            sequence_numbers: false,
+            recovery_ttl: None,
+            recovery_max_packets: 100,
+            matcher: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for NsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NsConfig")
+            .field("default_rooms", &self.default_rooms)
+            .field("max_pending_acks", &self.max_pending_acks)
+            .field("event_normalizer", &self.event_normalizer.is_some())
+            .field("ack_timeout_event", &self.ack_timeout_event)
+            .field("max_concurrent_handlers", &self.max_concurrent_handlers)
+            .field("connect_response", &self.connect_response.is_some())
+            .field("allow_binary", &self.allow_binary)
+            .field("max_connections", &self.max_connections)
+            .field("room_capacity", &self.room_capacity)
+            .field("inline_binary", &self.inline_binary)
+            .field("sequence_numbers", &self.sequence_numbers)
+            .field("recovery_ttl", &self.recovery_ttl)
+            .field("recovery_max_packets", &self.recovery_max_packets)
+            .field("matcher", &self.matcher.is_some())
+            .finish()
+    }
+}
 
 pub struct Namespace<A: Adapter> {
     pub path: String,
     pub(crate) adapter: A,
     callback: EventCallback<A>,
+    pub(crate) config: NsConfig,
     sockets: RwLock<HashMap<Sid, Arc<Socket<A>>>>,
+    /// The sockets bound to each external id with [`Socket::set_external_id`]
+    external_ids: RwLock<HashMap<String, HashSet<Sid>>>,
+    /// Limit the number of event handlers running at the same time, if configured
+    handler_permits: Option<Arc<Semaphore>>,
+    in_flight_handlers: Arc<AtomicUsize>,
+    /// The event handlers shared by all the sockets of the namespace
+    message_handlers: RwLock<HashMap<String, SharedHandler<A>>>,
+    /// Rewrite the packets before they are sent to the sockets, if registered
+    outgoing_interceptor: RwLock<Option<OutgoingInterceptor<A>>>,
+    /// The sockets closed with a persisted session, by private id. During the ack grace period,
+    /// their pending acks are handed over to the socket recovering their session
+    closed_sockets: Mutex<HashMap<Sid, Weak<Socket<A>>>>,
+    /// Remove the namespace once its last socket left, only set on the namespaces created on demand
+    remove_fn: RwLock<Option<RemoveFn>>,
+    /// Whether the namespace was removed, no socket can connect to it anymore
+    removed: AtomicBool,
 }
 
 impl Namespace<LocalAdapter> {
@@ -37,50 +215,404 @@ impl Namespace<LocalAdapter> {
     pub fn builder_with_adapter<CustomAdapter: Adapter>() -> NamespaceBuilder<CustomAdapter> {
         NamespaceBuilder::new()
     }
+
+    /// Return the connected sockets that are not in any room, see [`LocalAdapter::orphan_sockets`].
+    #[cfg(feature = "diagnostics")]
+    pub fn orphan_sockets(&self) -> Vec<Sid> {
+        self.adapter.orphan_sockets()
+    }
+
+    /// Create a [`NsBuilder`] to configure a single namespace in one expression.
+    pub fn ns_builder(path: impl Into<String>) -> NsBuilder<LocalAdapter> {
+        NsBuilder::new(path)
+    }
+
+    pub fn ns_builder_with_adapter<CustomAdapter: Adapter>(
+        path: impl Into<String>,
+    ) -> NsBuilder<CustomAdapter> {
+        NsBuilder::new(path)
+    }
 }
 
 impl<A: Adapter> Namespace<A> {
     pub fn new(path: impl Into<String>, callback: EventCallback<A>) -> Arc<Self> {
+        Self::with_config(path, callback, NsConfig::default())
+    }
+
+    pub fn with_config(
+        path: impl Into<String>,
+        callback: EventCallback<A>,
+        config: NsConfig,
+    ) -> Arc<Self> {
         let mut path: String = path.into();
         if !path.starts_with('/') {
             path = format!("/{}", path);
         }
+        let handler_permits = config
+            .max_concurrent_handlers
+            .map(|max| Arc::new(Semaphore::new(max)));
         Arc::new_cyclic(|ns| Self {
             path,
             callback,
+            config,
+            handler_permits,
+            in_flight_handlers: Arc::new(AtomicUsize::new(0)),
+            message_handlers: HashMap::new().into(),
+            outgoing_interceptor: None.into(),
+            closed_sockets: HashMap::new().into(),
+            remove_fn: None.into(),
+            removed: AtomicBool::new(false),
             sockets: HashMap::new().into(),
+            external_ids: HashMap::new().into(),
             adapter: A::new(ns.clone()),
         })
     }
 
+    /// Create a namespace from a handler built with `NamespaceBuilder` or [`NsBuilder`]
+    pub(crate) fn from_handler(path: impl Into<String>, handler: NsHandler<A>) -> Arc<Self> {
+        let ns = Self::with_config(path, handler.callback, handler.config);
+        for (event, handler) in handler.message_handlers {
+            let event = ns.normalize_event(event);
+            ns.message_handlers.write().unwrap().insert(event, handler);
+        }
+        *ns.outgoing_interceptor.write().unwrap() = handler.outgoing_interceptor;
+        ns
+    }
+
+    /// Register a handler for the given event, shared by all the sockets of the namespace.
+    ///
+    /// It is called for the sockets that didn't register their own handler for this event with [`Socket::on`],
+    /// a socket-level handler always takes precedence. See `NamespaceBuilder::on` to register it when building the namespace.
+    pub fn on<C, F, V>(&self, event: impl Into<String>, callback: C)
+    where
+        C: Fn(Arc<Socket<A>>, V, Vec<Vec<u8>>, AckSender<A>) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        let event = self.normalize_event(event.into());
+        self.message_handlers
+            .write()
+            .unwrap()
+            .insert(event, shared_handler(callback));
+    }
+
+    /// Register an interceptor that inspects and rewrites every packet before it is sent to a socket
+    /// of the namespace, for example to translate a message with the locale of the recipient.
+    /// It replaces the previous interceptor. See `NamespaceBuilder::intercept_outgoing` to register it
+    /// when building the namespace.
+    ///
+    /// It is called for each recipient, so a broadcast packet is serialized for each socket
+    /// rather than once for all of them.
+    pub fn intercept_outgoing<F>(&self, interceptor: F)
+    where
+        F: Fn(&Socket<A>, Packet) -> Packet + Send + Sync + 'static,
+    {
+        *self.outgoing_interceptor.write().unwrap() = Some(Arc::new(interceptor));
+    }
+
+    /// Whether the packets are rewritten for each socket by an outgoing interceptor
+    pub(crate) fn intercepts_outgoing(&self) -> bool {
+        self.outgoing_interceptor.read().unwrap().is_some()
+    }
+
+    /// Apply the outgoing interceptor of the namespace, if there is one
+    pub(crate) fn intercept(&self, socket: &Socket<A>, packet: Packet) -> Packet {
+        // The interceptor is cloned so it doesn't run under the lock
+        let interceptor = self.outgoing_interceptor.read().unwrap().clone();
+        match interceptor {
+            Some(interceptor) => interceptor(socket, packet),
+            None => packet,
+        }
+    }
+
+    /// Get the namespace-wide handler of the given (normalized) event
+    pub(crate) fn get_handler(&self, event: &str) -> Option<SharedHandler<A>> {
+        self.message_handlers.read().unwrap().get(event).cloned()
+    }
+
+    /// Apply the event name normalizer of the namespace, if there is one
+    pub(crate) fn normalize_event(&self, event: String) -> String {
+        match self.config.event_normalizer {
+            Some(normalize) => normalize(&event),
+            None => event,
+        }
+    }
+
     /// Connects a socket to a namespace
+    ///
+    /// The socket joins the namespace default rooms before the connect callback is called
     pub fn connect(
         self: Arc<Self>,
         sid: Sid,
         tx: mpsc::Sender<EnginePacket>,
+        flush: FlushHandle,
+        transport: TransportHandle,
         handshake: Handshake,
         config: Arc<SocketIoConfig>,
-    ) -> Arc<Socket<A>> {
-        let socket: Arc<Socket<A>> = Socket::new(sid, self.clone(), handshake, tx, config).into();
-        self.sockets.write().unwrap().insert(sid, socket.clone());
+    ) -> Result<Arc<Socket<A>>, Error> {
+        let socket: Arc<Socket<A>> =
+            Socket::new(sid, self.clone(), handshake, tx, flush, transport, config).into();
+        {
+            // The count is checked under the write lock so that concurrent connections cannot exceed it
+            let mut sockets = self.sockets.write().unwrap();
+            if self.removed.load(Ordering::SeqCst) {
+                return Err(Error::NamespaceRemoved(self.path.clone()));
+            }
+            if let Some(max) = self.config.max_connections {
+                if sockets.len() >= max {
+                    return Err(Error::NamespaceFull(self.path.clone()));
+                }
+            }
+            sockets.insert(sid, socket.clone());
+        }
+        let mut res = Ok(());
+        if !self.config.default_rooms.is_empty() {
+            res = self.adapter.add_all(sid, self.config.default_rooms.clone());
+        }
+        if res.is_ok() && self.config.recovery_ttl.is_some() {
+            res = self.recover_session(&socket);
+        }
+        if let Err(err) = res {
+            // The socket is not connected, it must not be reachable from the namespace
+            self.forget_socket(sid);
+            self.adapter.del_all(sid).ok();
+            return Err(AdapterError(Box::new(err)).into());
+        }
         tokio::spawn((self.callback)(socket.clone()));
-        socket
+        Ok(socket)
     }
 
-    pub fn disconnect(&self, sid: Sid) -> Result<(), SendError> {
-        if let Some(socket) = self.sockets.write().unwrap().remove(&sid) {
-            self.adapter
-                .del_all(sid)
-                .map_err(|err| AdapterError(Box::new(err)))?;
+    /// Restore the session of the previous socket of a reconnecting client,
+    /// whose private id is given in the `pid` field of the auth payload.
+    fn recover_session(&self, socket: &Socket<A>) -> Result<(), A::Error> {
+        let pid = socket
+            .handshake
+            .auth
+            .get("pid")
+            .and_then(Value::as_str)
+            .and_then(|pid| pid.parse::<Sid>().ok());
+        let Some(pid) = pid else {
+            return Ok(());
+        };
+        if let Some(session) = self.adapter.restore_session(pid)? {
+            self.adapter.add_all(socket.sid, session.rooms)?;
+            socket.set_recovered(session.missed_packets);
+            let previous = self.closed_sockets.lock().unwrap().remove(&pid);
+            if let Some(previous) = previous.and_then(|previous| previous.upgrade()) {
+                socket.take_pending_acks(&previous);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep a socket closed with a persisted session, so the socket recovering it takes its pending acks over
+    pub(crate) fn keep_closed_socket(&self, pid: Sid, socket: Weak<Socket<A>>) {
+        let mut closed_sockets = self.closed_sockets.lock().unwrap();
+        // The sockets are dropped once their grace period is over
+        closed_sockets.retain(|_, socket| socket.strong_count() > 0);
+        closed_sockets.insert(pid, socket);
+    }
+
+    /// Disconnect a socket from the namespace and send it a disconnect packet.
+    ///
+    /// The socket.io protocol has no room for a reason in the disconnect packet, so the reason is given
+    /// to the server side disconnect handlers. If the client can't infer it from the disconnect packet,
+    /// it is also sent to the client in a `disconnect_reason` event, see [`DisconnectReason::client_reason`].
+    pub fn disconnect(&self, sid: Sid, reason: DisconnectReason) -> Result<(), SendError> {
+        let socket = self.sockets.read().unwrap().get(&sid).cloned();
+        if let Some(socket) = socket {
+            // The socket is only told once, even if it is disconnected again while disconnecting
+            if !socket.clone().close(reason)? {
+                return Ok(());
+            }
+            if let Some(client_reason) = reason.client_reason() {
+                socket.send(Packet::disconnect_reason(self.path.clone(), client_reason))?;
+            }
             socket.send(Packet::disconnect(self.path.clone()))?;
         }
         Ok(())
     }
-    pub fn remove_socket(&self, sid: Sid) -> Result<(), AdapterError> {
-        self.sockets.write().unwrap().remove(&sid);
-        self.adapter
-            .del_all(sid)
-            .map_err(|err| AdapterError(Box::new(err)))
+    /// Close a socket, it is removed from the namespace once its disconnect handlers have completed.
+    pub fn remove_socket(&self, sid: Sid, reason: DisconnectReason) -> Result<(), AdapterError> {
+        let socket = self.sockets.read().unwrap().get(&sid).cloned();
+        if let Some(socket) = socket {
+            socket.close(reason)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a closed socket from the namespace.
+    ///
+    /// A namespace created on demand is removed once its last socket left
+    pub(crate) fn forget_socket(&self, sid: Sid) {
+        let mut sockets = self.sockets.write().unwrap();
+        sockets.remove(&sid);
+        if !sockets.is_empty() {
+            return;
+        }
+        if let Some(remove) = &*self.remove_fn.read().unwrap() {
+            // It is marked under the lock of the sockets, so no socket can connect to it from now on
+            self.removed.store(true, Ordering::SeqCst);
+            drop(sockets);
+            remove(&self.path);
+        }
+    }
+
+    /// Remove this namespace with the given function once its last socket left
+    pub(crate) fn remove_when_empty(&self, remove: impl Fn(&str) + Send + Sync + 'static) {
+        *self.remove_fn.write().unwrap() = Some(Box::new(remove));
+    }
+
+    /// Whether the namespace was removed once its last socket left, see [`Namespace::remove_when_empty`]
+    pub(crate) fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    /// Bind a socket to an external id, and unbind it from its previous one
+    pub(crate) fn bind_external_id(&self, sid: Sid, previous: Option<String>, id: String) {
+        let mut external_ids = self.external_ids.write().unwrap();
+        if let Some(previous) = previous {
+            Self::unbind(&mut external_ids, sid, &previous);
+        }
+        external_ids.entry(id).or_default().insert(sid);
+    }
+
+    /// Unbind a socket from an external id
+    pub(crate) fn unbind_external_id(&self, sid: Sid, id: &str) {
+        Self::unbind(&mut self.external_ids.write().unwrap(), sid, id);
+    }
+
+    fn unbind(external_ids: &mut HashMap<String, HashSet<Sid>>, sid: Sid, id: &str) {
+        if let Some(sids) = external_ids.get_mut(id) {
+            sids.remove(&sid);
+            if sids.is_empty() {
+                external_ids.remove(id);
+            }
+        }
+    }
+
+    /// Get the sockets bound to the given external id with [`Socket::set_external_id`].
+    pub fn sockets_for_external_id(&self, id: &str) -> Vec<Sid> {
+        self.external_ids
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|sids| sids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Emit a message to all the sockets bound to the given external id with [`Socket::set_external_id`].
+    ///
+    /// It does nothing if no socket is bound to this id.
+    pub fn emit_to_external_id(
+        &self,
+        id: &str,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<(), BroadcastError> {
+        self.emit_to_sids(self.sockets_for_external_id(id), event, data)
+    }
+
+    /// Emit a message to the sockets with the given ids.
+    ///
+    /// The ids of the sockets that are not connected to this namespace are ignored.
+    pub fn emit_to_sids(
+        &self,
+        sids: impl IntoIterator<Item = Sid>,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<(), BroadcastError> {
+        let event = event.into();
+        let errors: Vec<SendError> = sids
+            .into_iter()
+            .filter_map(|sid| self.get_socket(sid).ok())
+            .filter_map(|socket| socket.emit(event.clone(), &data).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.into())
+        }
+    }
+
+    /// Emit a message to the sockets computed by an async `resolver`,
+    /// for example the sockets of the followers of a user, stored in a database.
+    ///
+    /// The resolver runs before the broadcast. The sockets can connect or disconnect after
+    /// the resolution: the sockets that disconnected in the meantime are ignored,
+    /// and the sockets that connected in the meantime don't receive the message.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{adapter::LocalAdapter, Namespace};
+    /// # use std::sync::Arc;
+    /// async fn notify_followers(ns: Arc<Namespace<LocalAdapter>>, post: String) {
+    ///     ns.broadcast_to_resolver(
+    ///         |ns| async move {
+    ///             // Query the sockets of the followers from a database...
+    ///             ns.get_sockets().into_iter().map(|socket| socket.sid).collect()
+    ///         },
+    ///         "post",
+    ///         post,
+    ///     )
+    ///     .await
+    ///     .ok();
+    /// }
+    /// ```
+    pub async fn broadcast_to_resolver<R, F>(
+        self: &Arc<Self>,
+        resolver: R,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<(), BroadcastError>
+    where
+        R: FnOnce(Arc<Namespace<A>>) -> F,
+        F: Future<Output = Vec<Sid>>,
+    {
+        // The data is serialized before the resolution so it doesn't need to be kept across the await
+        let data = event_data(data)?;
+        let event = event.into();
+        let sids = resolver(self.clone()).await;
+        self.emit_to_sids(sids, event, data)
+    }
+
+    /// Spawn an event handler future.
+    ///
+    /// If the namespace limits the concurrent handlers, it waits for a permit before running,
+    /// the waiting handlers are run in the order they were received.
+    pub(crate) fn spawn_handler(&self, fut: BoxFuture<'static, ()>) {
+        let permits = self.handler_permits.clone();
+        let in_flight = self.in_flight_handlers.clone();
+        tokio::spawn(async move {
+            let _permit = match &permits {
+                Some(permits) => Some(permits.acquire().await),
+                None => None,
+            };
+            in_flight.fetch_add(1, Ordering::SeqCst);
+            // The counter is decremented even if the handler panics
+            let _in_flight = InFlightGuard(in_flight);
+            fut.await;
+        });
+    }
+
+    /// Get the number of event handlers currently running in this namespace.
+    ///
+    /// The handlers waiting for a permit because of the
+    /// `max_concurrent_handlers` limit are not counted.
+    pub fn in_flight_handlers(&self) -> usize {
+        self.in_flight_handlers.load(Ordering::SeqCst)
+    }
+
+    /// Get the adapter of this namespace, for example to inspect the
+    /// [`MockAdapter`](crate::testing::MockAdapter) in the tests.
+    pub fn adapter(&self) -> &A {
+        &self.adapter
+    }
+
+    /// Get the number of sockets currently connected to this namespace.
+    ///
+    /// The sockets stay counted until their disconnect handlers have completed.
+    pub fn connection_count(&self) -> usize {
+        self.sockets.read().unwrap().len()
     }
 
     pub fn has(&self, sid: Sid) -> bool {
@@ -95,8 +627,8 @@ impl<A: Adapter> Namespace<A> {
     pub fn recv(&self, sid: Sid, packet: PacketData) -> Result<(), Error> {
         match packet {
             PacketData::Disconnect => self
-                .remove_socket(sid)
-                .map_err(|err| AdapterError(Box::new(err)).into()),
+                .remove_socket(sid, DisconnectReason::ClientNSDisconnect)
+                .map_err(Into::into),
             PacketData::Connect(_) => unreachable!("connect packets should be handled before"),
             PacketData::ConnectError(_) => Ok(()),
             packet => self.socket_recv(sid, packet),
@@ -113,6 +645,28 @@ impl<A: Adapter> Namespace<A> {
     pub fn get_sockets(&self) -> Vec<Arc<Socket<A>>> {
         self.sockets.read().unwrap().values().cloned().collect()
     }
+
+    /// Get the ids of the sockets of the namespace, without cloning the sockets
+    pub(crate) fn sids(&self) -> Vec<Sid> {
+        self.sockets.read().unwrap().keys().copied().collect()
+    }
+
+    /// Get the sockets connected to this namespace on this node only.
+    ///
+    /// It is cheap: it only reads the local socket map and never queries other nodes.
+    pub fn local_sockets(&self) -> Vec<Arc<Socket<A>>> {
+        self.get_sockets()
+    }
+
+    /// Get the sockets connected to this namespace on all the nodes.
+    ///
+    /// It goes through the adapter, so with a clustered adapter it may need to query the other nodes,
+    /// which is potentially expensive. Prefer [`Namespace::local_sockets`] if you only need this node.
+    ///
+    /// With the [`LocalAdapter`] it returns the same sockets as [`Namespace::local_sockets`].
+    pub fn all_sockets(&self) -> Result<Vec<Arc<Socket<A>>>, A::Error> {
+        self.adapter.fetch_sockets(BroadcastOptions::all())
+    }
 }
 
 #[cfg(test)]
@@ -133,8 +687,11 @@ impl<A: Adapter> Namespace<A> {
     }
 }
 
+/// A builder to register the namespaces and configure them by path.
+///
+/// ⚠️ The options taking a `path` panic if no namespace was added for this `path` beforehand.
 pub struct NamespaceBuilder<A: Adapter> {
-    ns_handlers: HashMap<String, EventCallback<A>>,
+    ns_handlers: NsHandlers<A>,
 }
 
 impl<A: Adapter> NamespaceBuilder<A> {
@@ -144,12 +701,23 @@ impl<A: Adapter> NamespaceBuilder<A> {
         }
     }
 
+    /// Get the handler of the namespace added at the given `path`.
+    ///
+    /// It panics if no namespace was added for this `path`, the option would be silently lost otherwise.
+    fn handler_mut(&mut self, path: impl Into<String>) -> &mut NsHandler<A> {
+        let path = path.into();
+        self.ns_handlers
+            .get_mut(&path)
+            .unwrap_or_else(|| panic!("no namespace was added for the path {}", path))
+    }
+
     pub fn add<C, F>(mut self, path: impl Into<String>, callback: C) -> Self
     where
         C: Fn(Arc<Socket<A>>) -> F + Send + Sync + 'static,
         F: Future<Output = ()> + Send + 'static,
     {
-        let handler = Arc::new(move |socket| Box::pin(callback(socket)) as _);
+        let callback = Arc::new(move |socket| Box::pin(callback(socket)) as _);
+        let handler = NsHandler::new(callback, NsConfig::default());
         self.ns_handlers.insert(path.into(), handler);
         self
     }
@@ -158,24 +726,1062 @@ impl<A: Adapter> NamespaceBuilder<A> {
         C: Fn(Arc<Socket<A>>) -> F + Send + Sync + 'static,
         F: Future<Output = ()> + Send + 'static,
     {
-        let handler = Arc::new(move |socket| Box::pin(callback(socket)) as _);
+        let callback: EventCallback<A> = Arc::new(move |socket| Box::pin(callback(socket)) as _);
         for path in paths {
-            self.ns_handlers.insert(path.into(), handler.clone());
+            let handler = NsHandler::new(callback.clone(), NsConfig::default());
+            self.ns_handlers.insert(path.into(), handler);
         }
         self
     }
 
+    /// Set the rooms that every socket joins when connecting to the namespace at the given `path`.
+    ///
+    /// The rooms are joined before the connect callback is called,
+    /// so there is no need to call `socket.join()` in each handler.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         // The socket is already in the global room
+    ///         println!("rooms: {:?}", socket.rooms());
+    ///     })
+    ///     .default_rooms("/", ["global"])
+    ///     .build();
+    /// ```
+    pub fn default_rooms(mut self, path: impl Into<String>, rooms: impl RoomParam) -> Self {
+        self.handler_mut(path)
+            .config
+            .default_rooms
+            .extend(rooms.into_room_iter());
+        self
+    }
+
+    /// Set the maximum number of acknowledgements that a socket of the namespace at the given `path`
+    /// can wait for at the same time.
+    ///
+    /// Once it is reached, emitting with an acknowledgement returns a [`TooManyPendingAcks`](crate::SocketError::TooManyPendingAcks) error
+    /// until the client answers some of them or they time out.
+    ///
+    /// Defaults to 1024 acks.
+    ///
+    /// ⚠️ It panics if `max` is 0, no acknowledgement could ever be requested.
+    pub fn max_pending_acks(mut self, path: impl Into<String>, max: usize) -> Self {
+        assert!(max > 0, "max_pending_acks must be positive");
+        self.handler_mut(path).config.max_pending_acks = max;
+        self
+    }
+
+    /// Normalize the event names of the namespace at the given `path` with the given function.
+    ///
+    /// The normalizer is applied both to the event names given to [`Socket::on`] and to the incoming events,
+    /// so clients emitting `userJoin` or `userjoin` reach the same handler with a lowercase normalizer.
+    ///
+    /// It is disabled by default, event names are matched exactly.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         socket.on("userJoin", |socket, data: Value, _, _| async move {
+    ///             println!("user joined: {:?}", data);
+    ///         });
+    ///     })
+    ///     .event_normalizer("/", |event| event.to_lowercase())
+    ///     .build();
+    /// ```
+    pub fn event_normalizer(
+        mut self,
+        path: impl Into<String>,
+        normalizer: fn(&str) -> String,
+    ) -> Self {
+        self.handler_mut(path).config.event_normalizer = Some(normalizer);
+        self
+    }
+
+    /// Emit the given `event` to a socket of the namespace at the given `path`
+    /// when it doesn't acknowledge a message in time.
+    ///
+    /// The notification carries the name of the event that was not acknowledged.
+    /// It never requests an acknowledgement itself, so it can't time out in turn.
+    ///
+    /// It is disabled by default.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         // If the client doesn't answer in time, it receives an `ack_timeout` event with "ping"
+    ///         socket.emit_with_ack::<Value>("ping", ()).await.ok();
+    ///     })
+    ///     .ack_timeout_event("/", "ack_timeout")
+    ///     .build();
+    /// ```
+    pub fn ack_timeout_event(mut self, path: impl Into<String>, event: impl Into<String>) -> Self {
+        self.handler_mut(path).config.ack_timeout_event = Some(event.into());
+        self
+    }
+
+    /// Send extra fields to the clients connecting to the namespace at the given `path`,
+    /// merged with the `sid` in the connect response, for example the server time or feature flags.
+    /// It saves a round-trip to fetch the initial state after connecting.
+    ///
+    /// The `sid` field always comes from the server, a `sid` field returned by the `provider` is overwritten.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::json;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {})
+    ///     .connect_response("/", |_handshake| {
+    ///         // The client receives `{"sid":"...","features":["chat"]}`
+    ///         let mut data = serde_json::Map::new();
+    ///         data.insert("features".to_string(), json!(["chat"]));
+    ///         data
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn connect_response<F>(mut self, path: impl Into<String>, provider: F) -> Self
+    where
+        F: Fn(&Handshake) -> Map<String, Value> + Send + Sync + 'static,
+    {
+        self.handler_mut(path).config.connect_response = Some(Arc::new(provider));
+        self
+    }
+
+    /// Allow or forbid the binary events and acknowledgements sent by the clients
+    /// to the namespace at the given `path`, for example for a text-only control channel.
+    ///
+    /// When they are forbidden, they are dropped and the client receives an `error` event.
+    ///
+    /// They are allowed by default.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder()
+    ///     .add("/control", |socket| async move {
+    ///         // The binary payload is always empty
+    ///         socket.on("command", |socket, data: Value, bin, _| async move {});
+    ///     })
+    ///     .allow_binary("/control", false)
+    ///     .build();
+    /// ```
+    pub fn allow_binary(mut self, path: impl Into<String>, allow: bool) -> Self {
+        self.handler_mut(path).config.allow_binary = allow;
+        self
+    }
+
+    /// Limit the number of event handlers running at the same time in the namespace at the given `path`.
+    ///
+    /// The events received beyond the limit wait for a running handler to complete,
+    /// it protects the resources used by the handlers (e.g. a database) from a spike of connections.
+    /// The connect and disconnect handlers are not limited.
+    ///
+    /// It is unbounded by default.
+    ///
+    /// ⚠️ It panics if `max` is 0, no handler could ever run.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         socket.on("query", |socket, data: Value, _, ack| async move {
+    ///             // At most 100 queries run at the same time
+    ///             ack.send(data).ok();
+    ///         });
+    ///     })
+    ///     .max_concurrent_handlers("/", 100)
+    ///     .build();
+    /// ```
+    pub fn max_concurrent_handlers(mut self, path: impl Into<String>, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent_handlers must be positive");
+        self.handler_mut(path).config.max_concurrent_handlers = Some(max);
+        self
+    }
+
+    /// Limit the number of sockets connected at the same time to the namespace at the given `path`.
+    ///
+    /// The clients connecting beyond the limit receive a connect error
+    /// with the `{ "reason": "capacity" }` data, and can retry later.
+    ///
+    /// It is unbounded by default.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         println!("Socket connected on / namespace with id: {}", socket.sid);
+    ///     })
+    ///     .max_connections("/", 10_000)
+    ///     .build();
+    /// ```
+    pub fn max_connections(mut self, path: impl Into<String>, max: usize) -> Self {
+        self.handler_mut(path).config.max_connections = Some(max);
+        self
+    }
+
+    /// Send the binary attachments to the sockets of the namespace at the given `path` in base64 strings,
+    /// for the constrained clients that don't support the binary packets.
+    ///
+    /// The attachments replace their placeholders in the arguments of the events and acknowledgements,
+    /// which are then sent as plain text packets: `["file", <Buffer 01 02 03>]` is sent as `["file", "AQID"]`.
+    /// The base64 encoding makes the attachments about 33% bigger than with the native binary frames.
+    ///
+    /// It is disabled by default.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/legacy", |socket| async move {
+    ///         // Sent as ["file","AQID"]
+    ///         socket.bin(vec![vec![1, 2, 3]]).emit("file", ()).ok();
+    ///     })
+    ///     .inline_binary("/legacy", true)
+    ///     .build();
+    /// ```
+    pub fn inline_binary(mut self, path: impl Into<String>, enabled: bool) -> Self {
+        self.handler_mut(path).config.inline_binary = enabled;
+        self
+    }
+
+    /// Set the initial capacity of the rooms created in the namespace at the given `path`.
+    ///
+    /// The rooms are resized as sockets join them, a capacity close to the expected size of the rooms
+    /// avoids the successive resizings when many sockets join a room at once, for example a live event room.
+    /// Each room allocates this capacity, even if few sockets join it.
+    ///
+    /// Defaults to 0.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/live", |socket| async move {
+    ///         socket.join("stage").ok();
+    ///     })
+    ///     .room_capacity("/live", 10_000)
+    ///     .build();
+    /// ```
+    pub fn room_capacity(mut self, path: impl Into<String>, capacity: usize) -> Self {
+        self.handler_mut(path).config.room_capacity = capacity;
+        self
+    }
+
+    /// Append a sequence number to the events sent to each socket of the namespace at the given `path`,
+    /// so the clients can detect the missing or duplicated events, for example after a reconnection.
+    ///
+    /// The sequence number is appended as the last argument of the events, it starts at 0 and is
+    /// incremented for each event sent to the socket. The acknowledgements are not numbered.
+    /// When reconnecting, a client can report the last sequence number it received in the `_seq` field
+    /// of its auth payload, the numbering then resumes from the following number,
+    /// and the missed events can be sent again with [`Socket::replay`].
+    /// The numbers stop at 2^53 - 1, the largest integer that a javascript client reads exactly,
+    /// a larger `_seq` field is ignored.
+    ///
+    /// It is disabled by default because the standard clients would see an extra argument.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         // Sent as ["welcome", "hello", 0] to a new client
+    ///         socket.emit("welcome", "hello").ok();
+    ///     })
+    ///     .sequence_numbers("/", true)
+    ///     .build();
+    /// ```
+    pub fn sequence_numbers(mut self, path: impl Into<String>, enabled: bool) -> Self {
+        self.handler_mut(path).config.sequence_numbers = enabled;
+        self
+    }
+
+    /// Recover the connection state of the sockets of the namespace at the given `path`
+    /// when their clients reconnect after a transport close.
+    ///
+    /// The rooms of a disconnected socket are kept for `ttl`, and at most `max_packets` of the
+    /// packets broadcast to them in the meantime are buffered, the oldest ones are dropped first.
+    /// The connect response includes a private id of the socket in a `pid` field. A client reconnecting
+    /// with this `pid` in its auth payload joins the rooms again and receives the missed packets,
+    /// and its new socket is [recovered](Socket::recovered).
+    ///
+    /// It is disabled by default.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use std::time::Duration;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         if !socket.recovered() {
+    ///             socket.join("lobby").ok();
+    ///         }
+    ///     })
+    ///     .connection_state_recovery("/", Duration::from_secs(120), 100)
+    ///     .build();
+    /// ```
+    pub fn connection_state_recovery(
+        mut self,
+        path: impl Into<String>,
+        ttl: Duration,
+        max_packets: usize,
+    ) -> Self {
+        let handler = self.handler_mut(path);
+        handler.config.recovery_ttl = Some(ttl);
+        handler.config.recovery_max_packets = max_packets;
+        self
+    }
+
+    /// Turn the namespace added at the given `path` into a template for dynamic namespaces.
+    ///
+    /// When a client connects to a path that is not registered and that the `matcher` selects,
+    /// a new namespace is created for this path, with its own adapter, its own sockets and
+    /// the connect callback and configuration of the template. The socket given to the callback
+    /// returns the concrete path with [`Socket::ns`]. A regex can be used with `move |path| re.is_match(path)`.
+    ///
+    /// The `path` itself is only used to configure the template, it is not instantiated.
+    /// When several matchers select a path, the first registered one is used.
+    /// The created namespaces count in [`SocketIoConfigBuilder::max_namespaces`](crate::SocketIoConfigBuilder::max_namespaces),
+    /// they are removed once their last socket left, unless they recover the connection state of their sockets.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder()
+    ///     .add("/workspaces", |socket| async move {
+    ///         // "/workspaces/acme", "/workspaces/globex"...
+    ///         println!("socket connected to {}", socket.ns());
+    ///     })
+    ///     .dynamic("/workspaces", |path| path.starts_with("/workspaces/"))
+    ///     .build();
+    /// ```
+    pub fn dynamic<M>(mut self, path: impl Into<String>, matcher: M) -> Self
+    where
+        M: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.handler_mut(path).config.matcher = Some(NsMatcher::new(matcher));
+        self
+    }
+
+    /// Register a handler for the given event, shared by all the sockets of the namespace at the given `path`.
+    ///
+    /// It is called for the sockets that didn't register their own handler for this event with [`Socket::on`],
+    /// a socket-level handler always takes precedence.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         socket.join("chat").ok();
+    ///     })
+    ///     .on("/", "chat:message", |socket, data: Value, _, _| async move {
+    ///         socket.to("chat").emit("chat:message", data).ok();
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on<C, F, V>(
+        mut self,
+        path: impl Into<String>,
+        event: impl Into<String>,
+        callback: C,
+    ) -> Self
+    where
+        C: Fn(Arc<Socket<A>>, V, Vec<Vec<u8>>, AckSender<A>) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.handler_mut(path)
+            .message_handlers
+            .insert(event.into(), shared_handler(callback));
+        self
+    }
+
+    /// Inspect and rewrite every packet before it is sent to a socket of the namespace at the given `path`,
+    /// for example to translate a message with the locale of the recipient.
+    ///
+    /// ⚠️ It is called for each recipient, so a broadcast packet is serialized for each socket
+    /// rather than once for all of them.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{Namespace, PacketData};
+    /// # use serde_json::json;
+    /// Namespace::builder()
+    ///     .add("/", |socket| async move {
+    ///         socket.emit("greeting", "Hello").ok();
+    ///     })
+    ///     .intercept_outgoing("/", |socket, mut packet| {
+    ///         if let PacketData::Event(event, data, _) = &mut packet.inner {
+    ///             if event == "greeting" && socket.handshake.auth()["locale"] == "fr" {
+    ///                 *data = json!("Bonjour");
+    ///             }
+    ///         }
+    ///         packet
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn intercept_outgoing<F>(mut self, path: impl Into<String>, interceptor: F) -> Self
+    where
+        F: Fn(&Socket<A>, Packet) -> Packet + Send + Sync + 'static,
+    {
+        self.handler_mut(path).outgoing_interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Add a namespace configured with a [`NsBuilder`].
+    ///
+    /// It returns an [`Error::InvalidNsConfig`] error if the namespace configuration is invalid.
+    pub fn add_ns(mut self, ns: NsBuilder<A>) -> Result<Self, Error> {
+        let (path, handler) = ns.validate()?;
+        self.ns_handlers.insert(path, handler);
+        Ok(self)
+    }
+
     pub fn build(self) -> NsHandlers<A> {
         self.ns_handlers
     }
 }
 
+/// A builder to configure a single namespace with a fluent API.
+///
+/// The configuration is validated when the namespace is built.
+/// ##### Example
+/// ```
+/// # use socketioxide::Namespace;
+/// # use serde_json::Value;
+/// let ns = Namespace::ns_builder("chat")
+///     .default_rooms(["lobby"])
+///     .max_pending_acks(64)
+///     .event_normalizer(|event| event.to_lowercase())
+///     .connect(|socket| async move {
+///         socket.on("message", |socket, data: Value, _, _| async move {
+///             socket.to("lobby").emit("message", data).ok();
+///         });
+///     })
+///     .build()
+///     .unwrap();
+/// assert!(ns.contains_key("/chat"));
+/// ```
+pub struct NsBuilder<A: Adapter> {
+    path: String,
+    callback: Option<EventCallback<A>>,
+    config: NsConfig,
+    message_handlers: HashMap<String, SharedHandler<A>>,
+    outgoing_interceptor: Option<OutgoingInterceptor<A>>,
+}
+
+impl<A: Adapter> NsBuilder<A> {
+    fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            callback: None,
+            config: NsConfig::default(),
+            message_handlers: HashMap::new(),
+            outgoing_interceptor: None,
+        }
+    }
+
+    /// Set the rooms that every socket joins when connecting to the namespace,
+    /// like `NamespaceBuilder::default_rooms`.
+    pub fn default_rooms(mut self, rooms: impl RoomParam) -> Self {
+        self.config.default_rooms.extend(rooms.into_room_iter());
+        self
+    }
+
+    /// Set the maximum number of acknowledgements that a socket can wait for at the same time,
+    /// like `NamespaceBuilder::max_pending_acks`. It must be positive.
+    pub fn max_pending_acks(mut self, max: usize) -> Self {
+        self.config.max_pending_acks = max;
+        self
+    }
+
+    /// Normalize the event names of the namespace, like `NamespaceBuilder::event_normalizer`.
+    pub fn event_normalizer(mut self, normalizer: fn(&str) -> String) -> Self {
+        self.config.event_normalizer = Some(normalizer);
+        self
+    }
+
+    /// Emit the given `event` to a socket when it doesn't acknowledge a message in time,
+    /// like `NamespaceBuilder::ack_timeout_event`.
+    pub fn ack_timeout_event(mut self, event: impl Into<String>) -> Self {
+        self.config.ack_timeout_event = Some(event.into());
+        self
+    }
+
+    /// Limit the number of event handlers running at the same time,
+    /// like `NamespaceBuilder::max_concurrent_handlers`. It must be positive.
+    pub fn max_concurrent_handlers(mut self, max: usize) -> Self {
+        self.config.max_concurrent_handlers = Some(max);
+        self
+    }
+
+    /// Limit the number of sockets connected at the same time,
+    /// like `NamespaceBuilder::max_connections`.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.config.max_connections = Some(max);
+        self
+    }
+
+    /// Send the binary attachments in base64 strings, like `NamespaceBuilder::inline_binary`.
+    pub fn inline_binary(mut self, enabled: bool) -> Self {
+        self.config.inline_binary = enabled;
+        self
+    }
+
+    /// Set the initial capacity of the rooms, like `NamespaceBuilder::room_capacity`.
+    pub fn room_capacity(mut self, capacity: usize) -> Self {
+        self.config.room_capacity = capacity;
+        self
+    }
+
+    /// Append a sequence number to the events sent to the sockets,
+    /// like `NamespaceBuilder::sequence_numbers`.
+    pub fn sequence_numbers(mut self, enabled: bool) -> Self {
+        self.config.sequence_numbers = enabled;
+        self
+    }
+
+    /// Recover the connection state of the sockets when their clients reconnect,
+    /// like `NamespaceBuilder::connection_state_recovery`.
+    pub fn connection_state_recovery(mut self, ttl: Duration, max_packets: usize) -> Self {
+        self.config.recovery_ttl = Some(ttl);
+        self.config.recovery_max_packets = max_packets;
+        self
+    }
+
+    /// Turn the namespace into a template for the dynamic namespaces selected by the `matcher`,
+    /// like `NamespaceBuilder::dynamic`.
+    pub fn dynamic<M>(mut self, matcher: M) -> Self
+    where
+        M: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.config.matcher = Some(NsMatcher::new(matcher));
+        self
+    }
+
+    /// Allow or forbid the binary events and acknowledgements sent by the clients,
+    /// like `NamespaceBuilder::allow_binary`.
+    pub fn allow_binary(mut self, allow: bool) -> Self {
+        self.config.allow_binary = allow;
+        self
+    }
+
+    /// Send extra fields to the clients in the connect response, like `NamespaceBuilder::connect_response`.
+    pub fn connect_response<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&Handshake) -> Map<String, Value> + Send + Sync + 'static,
+    {
+        self.config.connect_response = Some(Arc::new(provider));
+        self
+    }
+
+    /// Register a namespace-wide handler for the given event, like `NamespaceBuilder::on`.
+    pub fn on<C, F, V>(mut self, event: impl Into<String>, callback: C) -> Self
+    where
+        C: Fn(Arc<Socket<A>>, V, Vec<Vec<u8>>, AckSender<A>) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.message_handlers
+            .insert(event.into(), shared_handler(callback));
+        self
+    }
+
+    /// Inspect and rewrite every packet before it is sent to a socket of the namespace,
+    /// like `NamespaceBuilder::intercept_outgoing`.
+    pub fn intercept_outgoing<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&Socket<A>, Packet) -> Packet + Send + Sync + 'static,
+    {
+        self.outgoing_interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Set the callback called when a socket connects to the namespace. It is required.
+    pub fn connect<C, F>(mut self, callback: C) -> Self
+    where
+        C: Fn(Arc<Socket<A>>) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.callback = Some(Arc::new(move |socket| Box::pin(callback(socket)) as _));
+        self
+    }
+
+    /// Validate the configuration and build the [`NsHandlers`] with this only namespace.
+    ///
+    /// Use `NamespaceBuilder::add_ns` to add multiple namespaces.
+    pub fn build(self) -> Result<NsHandlers<A>, Error> {
+        let (path, handler) = self.validate()?;
+        Ok(HashMap::from([(path, handler)]))
+    }
+
+    /// Check the configuration and normalize the namespace path
+    fn validate(self) -> Result<(String, NsHandler<A>), Error> {
+        let callback = self
+            .callback
+            .ok_or(Error::InvalidNsConfig("missing connect callback"))?;
+        if self.config.max_pending_acks == 0 {
+            return Err(Error::InvalidNsConfig("max_pending_acks must be positive"));
+        }
+        if self.config.max_concurrent_handlers == Some(0) {
+            return Err(Error::InvalidNsConfig(
+                "max_concurrent_handlers must be positive",
+            ));
+        }
+        let mut path = self.path;
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+        let handler = NsHandler {
+            callback,
+            config: self.config,
+            message_handlers: self.message_handlers,
+            outgoing_interceptor: self.outgoing_interceptor,
+        };
+        Ok((path, handler))
+    }
+}
+
+/// Decrements the number of running handlers of a namespace when dropped
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl<A: Adapter + std::fmt::Debug> std::fmt::Debug for Namespace<A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Namespace")
             .field("path", &self.path)
             .field("adapter", &self.adapter)
             .field("sockets", &self.sockets)
+            .field("external_ids", &self.external_ids)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{connect_socket, try_connect_socket};
+    use futures::FutureExt;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_default_rooms() {
+        let config = NsConfig {
+            default_rooms: vec!["global".to_string(), "lobby".to_string()],
+            ..Default::default()
+        };
+        let ns = Namespace::<LocalAdapter>::with_config(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+            config,
+        );
+        let sid: Sid = 1i64.into();
+        let (socket, _rx) = connect_socket(&ns, sid, json!({}));
+
+        let rooms = socket.rooms().unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert!(rooms.contains(&"global".to_string()));
+        assert!(rooms.contains(&"lobby".to_string()));
+        assert_eq!(ns.adapter.sockets("global").unwrap(), [sid]);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_once() {
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let sid: Sid = 1i64.into();
+        let (socket, mut rx) = connect_socket(&ns, sid, json!({}));
+        // Keep the socket disconnecting until the handler is released
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let release_rx = std::sync::Mutex::new(Some(release_rx));
+        socket.on_disconnect(move |_, _| {
+            let release_rx = release_rx.lock().unwrap().take();
+            async move {
+                if let Some(release_rx) = release_rx {
+                    release_rx.await.ok();
+                }
+            }
+        });
+
+        ns.disconnect(sid, DisconnectReason::ServerNSDisconnect)
+            .unwrap();
+        ns.disconnect(sid, DisconnectReason::ServerNSDisconnect)
+            .unwrap();
+        release_tx.send(()).unwrap();
+
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_and_all_sockets() {
+        // The socket with the id 0 must not be taken for a sender
+        let sids: [Sid; 3] = [0i64, 1, 2].map(Into::into);
+        let ns = Namespace::<LocalAdapter>::new_dummy(sids);
+
+        let local: Vec<Sid> = ns.local_sockets().iter().map(|s| s.sid).collect();
+        let all: Vec<Sid> = ns.all_sockets().unwrap().iter().map(|s| s.sid).collect();
+        assert_eq!(local.len(), 3);
+        assert_eq!(all.len(), 3);
+        assert!(sids
+            .iter()
+            .all(|sid| local.contains(sid) && all.contains(sid)));
+    }
+    #[tokio::test]
+    async fn test_external_id() {
+        let sids: [Sid; 3] = [1i64, 2, 3].map(Into::into);
+        let ns = Namespace::<LocalAdapter>::new_dummy(sids);
+        let sockets: Vec<_> = sids
+            .iter()
+            .map(|sid| ns.get_socket(*sid).unwrap())
+            .collect();
+        for socket in &sockets {
+            socket.set_external_id("user-1");
+        }
+        sockets[2].set_external_id("user-2");
+        assert_eq!(sockets[2].external_id().unwrap(), "user-2");
+
+        let user_sids = ns.sockets_for_external_id("user-1");
+        assert_eq!(
+            user_sids.into_iter().collect::<HashSet<_>>(),
+            HashSet::from([sids[0], sids[1]])
+        );
+        assert_eq!(ns.sockets_for_external_id("user-2"), [sids[2]]);
+        assert!(ns.sockets_for_external_id("unknown").is_empty());
+        ns.emit_to_external_id("user-1", "test", "hello").unwrap();
+
+        // The socket is unbound when it disconnects
+        ns.remove_socket(sids[2], DisconnectReason::TransportClose)
+            .unwrap();
+        while ns.has(sids[2]) {
+            tokio::task::yield_now().await;
+        }
+        assert!(ns.sockets_for_external_id("user-2").is_empty());
+        assert!(ns.external_ids.read().unwrap().get("user-2").is_none());
+
+        // A disconnected socket is not bound anymore
+        sockets[2].set_external_id("user-3");
+        assert!(sockets[2].external_id().is_none());
+        assert!(ns.sockets_for_external_id("user-3").is_empty());
+        assert!(ns.external_ids.read().unwrap().get("user-3").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ns_handlers_precedence() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let ns_tx = tx.clone();
+        let mut handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .on("/", "msg", move |_, data: String, _, _| {
+                let tx = ns_tx.clone();
+                async move { tx.send(format!("ns: {}", data)).await.unwrap() }
+            })
+            .build();
+        let ns = Namespace::from_handler("/", handlers.remove("/").unwrap());
+        let socket: Arc<Socket<LocalAdapter>> = Socket::new_dummy(1i64.into(), ns).into();
+
+        let recv = |socket: &Arc<Socket<LocalAdapter>>| {
+            socket
+                .clone()
+                .recv(PacketData::Event("msg".into(), json!("hello"), None))
+                .unwrap()
+        };
+        recv(&socket);
+        assert_eq!(rx.recv().await.unwrap(), "ns: hello");
+
+        // The socket handler takes precedence over the namespace handler
+        socket.on("msg", move |_, data: String, _, _| {
+            let tx = tx.clone();
+            async move { tx.send(format!("socket: {}", data)).await.unwrap() }
+        });
+        recv(&socket);
+        assert_eq!(rx.recv().await.unwrap(), "socket: hello");
+    }
+
+    #[tokio::test]
+    async fn test_intercept_outgoing() {
+        let handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .intercept_outgoing("/", |socket, mut packet| {
+                if let PacketData::Event(_, data, _) = &mut packet.inner {
+                    if socket.handshake.auth()["locale"] == "fr" {
+                        *data = json!("Bonjour");
+                    }
+                }
+                packet
+            })
+            .build();
+        let ns = Namespace::<LocalAdapter>::from_handler("/", handlers["/"].clone());
+        let mut rxs = Vec::new();
+        for (sid, locale) in [(1i64, "fr"), (2, "en")] {
+            let (_, rx) = connect_socket(&ns, sid.into(), json!({ "locale": locale }));
+            rxs.push(rx);
+        }
+
+        // The interceptor rewrites the packets sent to a single socket and the broadcast ones
+        ns.get_socket(1i64.into())
+            .unwrap()
+            .emit("greeting", "Hello")
+            .unwrap();
+        let packet = Packet::event("/".to_string(), "greeting".to_string(), json!("Hello"));
+        ns.adapter
+            .broadcast(packet, BroadcastOptions::new(0i64.into()).broadcast())
+            .unwrap();
+        let mut recv = |i: usize| match rxs[i].try_recv().unwrap() {
+            EnginePacket::Message(msg) => msg,
+            p => panic!("unexpected packet: {:?}", p),
+        };
+        assert_eq!(recv(0), r#"2["greeting","Bonjour"]"#);
+        assert_eq!(recv(0), r#"2["greeting","Bonjour"]"#);
+        assert_eq!(recv(1), r#"2["greeting","Hello"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_connection_state_recovery() {
+        let handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .connection_state_recovery("/", Duration::from_secs(60), 10)
+            .build();
+        let ns = Namespace::<LocalAdapter>::from_handler("/", handlers["/"].clone());
+        let connect = |sid: Sid, auth: Value| connect_socket(&ns, sid, auth);
+        let sid: Sid = 1i64.into();
+        let (socket, _rx) = connect(sid, json!({}));
+        assert!(!socket.recovered());
+        assert_ne!(socket.pid, sid);
+        let pid = socket.pid;
+        socket.join("lobby").unwrap();
+        ns.remove_socket(sid, DisconnectReason::TransportClose)
+            .unwrap();
+
+        // The public sid of the socket doesn't recover its session
+        let (socket, _rx) = connect(4i64.into(), json!({ "pid": sid.to_string() }));
+        assert!(!socket.recovered());
+
+        let packet = Packet::event("/".to_string(), "news".to_string(), json!("missed"));
+        ns.adapter
+            .broadcast(packet, BroadcastOptions::new(0i64.into()).to("lobby"))
+            .unwrap();
+
+        let (socket, mut rx) = connect(2i64.into(), json!({ "pid": pid.to_string() }));
+        assert!(socket.recovered());
+        assert_eq!(socket.rooms().unwrap(), ["lobby"]);
+        assert_eq!(socket.send_missed_packets().unwrap(), 1);
+        match rx.try_recv().unwrap() {
+            EnginePacket::Message(msg) => assert_eq!(msg, r#"2["news","missed"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // The session cannot be recovered twice
+        let (socket, _rx) = connect(3i64.into(), json!({ "pid": pid.to_string() }));
+        assert!(!socket.recovered());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_takes_pending_acks_over() {
+        let handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .connection_state_recovery("/", Duration::from_secs(60), 10)
+            .build();
+        let ns = Namespace::<LocalAdapter>::from_handler("/", handlers["/"].clone());
+        let config = Arc::new(
+            SocketIoConfig::builder()
+                .ack_disconnect_grace(Duration::from_secs(1))
+                .build(),
+        );
+        let connect = |sid: Sid, auth: Value| {
+            let handshake = Handshake {
+                auth,
+                ..Handshake::new_dummy()
+            };
+            try_connect_socket(&ns, sid, handshake, config.clone()).unwrap()
+        };
+        let (socket, mut rx) = connect(1i64.into(), json!({}));
+        let pid = socket.pid;
+        let emitter = socket.clone();
+        let ack = tokio::spawn(async move { emitter.emit_with_ack::<Value>("test", "foo").await });
+        rx.recv().await.unwrap();
+        ns.remove_socket(1i64.into(), DisconnectReason::TransportClose)
+            .unwrap();
+
+        // The client reconnects during the grace period and acknowledges with the previous ack id
+        let (socket, _rx) = connect(2i64.into(), json!({ "pid": pid.to_string() }));
+        assert!(socket.recovered());
+        socket
+            .clone()
+            .recv(PacketData::EventAck(json!(["bar"]), 1))
+            .unwrap();
+        let res = ack.await.unwrap().unwrap();
+        assert_eq!(res.data, json!(["bar"]));
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_handlers() {
+        let config = NsConfig {
+            max_concurrent_handlers: Some(1),
+            ..Default::default()
+        };
+        let ns = Namespace::<LocalAdapter>::with_config(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+            config,
+        );
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let (done_tx, mut done_rx) = mpsc::channel(2);
+
+        let tx = done_tx.clone();
+        ns.spawn_handler(
+            async move {
+                release_rx.await.unwrap();
+                tx.send(1).await.unwrap();
+            }
+            .boxed(),
+        );
+        ns.spawn_handler(async move { done_tx.send(2).await.unwrap() }.boxed());
+
+        while ns.in_flight_handlers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        // The second handler waits for the first one to complete
+        tokio::task::yield_now().await;
+        assert_eq!(ns.in_flight_handlers(), 1);
+        assert!(done_rx.try_recv().is_err());
+
+        release_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv().await.unwrap(), 1);
+        assert_eq!(done_rx.recv().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_releases_permit() {
+        let config = NsConfig {
+            max_concurrent_handlers: Some(1),
+            ..Default::default()
+        };
+        let ns = Namespace::<LocalAdapter>::with_config(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+            config,
+        );
+        let (done_tx, mut done_rx) = mpsc::channel(1);
+        ns.spawn_handler(async move { panic!("handler panic") }.boxed());
+        ns.spawn_handler(async move { done_tx.send(()).await.unwrap() }.boxed());
+
+        done_rx.recv().await.unwrap();
+        while ns.in_flight_handlers() > 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max_concurrent_handlers must be positive")]
+    fn test_max_concurrent_handlers_zero() {
+        Namespace::builder()
+            .add("/", |_| async move {})
+            .max_concurrent_handlers("/", 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_pending_acks must be positive")]
+    fn test_max_pending_acks_zero() {
+        Namespace::builder()
+            .add("/", |_| async move {})
+            .max_pending_acks("/", 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "no namespace was added for the path /admin")]
+    fn test_builder_unknown_path() {
+        Namespace::builder()
+            .add("/", |_| async move {})
+            .max_connections("/admin", 10);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let config = NsConfig {
+            max_connections: Some(2),
+            ..Default::default()
+        };
+        let ns = Namespace::<LocalAdapter>::with_config(
+            "/",
+            Arc::new(|_| async move {}.boxed()),
+            config,
+        );
+        let connect = |sid: i64| {
+            let config = Arc::new(SocketIoConfig::default());
+            try_connect_socket(&ns, sid.into(), Handshake::new_dummy(), config)
+        };
+        assert!(connect(1).is_ok());
+        assert!(connect(2).is_ok());
+        assert!(matches!(connect(3), Err(Error::NamespaceFull(path)) if path == "/"));
+        assert_eq!(ns.connection_count(), 2);
+        assert!(!ns.has(3i64.into()));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_resolver() {
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut receivers = Vec::new();
+        for sid in 1..=3i64 {
+            let (_, rx) = connect_socket(&ns, sid.into(), json!({}));
+            receivers.push(rx);
+        }
+
+        // The socket 42 is not connected, it is ignored
+        ns.broadcast_to_resolver(
+            |ns| async move {
+                assert_eq!(ns.connection_count(), 3);
+                vec![1i64.into(), 3i64.into(), 42i64.into()]
+            },
+            "news",
+            "hello",
+        )
+        .await
+        .unwrap();
+
+        for (i, rx) in receivers.iter_mut().enumerate() {
+            match rx.try_recv() {
+                Ok(EnginePacket::Message(msg)) if i != 1 => assert_eq!(msg, r#"2["news","hello"]"#),
+                Err(_) if i == 1 => {}
+                res => panic!("unexpected packet for socket {}: {:?}", i + 1, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ns_builder() {
+        let handlers = Namespace::ns_builder("chat")
+            .default_rooms(["lobby"])
+            .max_pending_acks(8)
+            .connect(|_| async move {})
+            .build()
+            .unwrap();
+        let handler = handlers.get("/chat").unwrap();
+        assert_eq!(handler.config.default_rooms, ["lobby"]);
+        assert_eq!(handler.config.max_pending_acks, 8);
+
+        let handlers = Namespace::builder()
+            .add("/", |_| async move {})
+            .add_ns(Namespace::ns_builder("/admin").connect(|_| async move {}))
+            .unwrap()
+            .build();
+        assert_eq!(handlers.len(), 2);
+        assert!(handlers.contains_key("/admin"));
+
+        assert!(matches!(
+            Namespace::ns_builder("/").build(),
+            Err(Error::InvalidNsConfig(_))
+        ));
+        assert!(matches!(
+            Namespace::ns_builder("/")
+                .max_pending_acks(0)
+                .connect(|_| async move {})
+                .build(),
+            Err(Error::InvalidNsConfig(_))
+        ));
+    }
+}
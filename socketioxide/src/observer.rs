@@ -0,0 +1,131 @@
+//! Observation of the packets encoded and decoded by the server, for example to keep an audit trail.
+//!
+//! A [`PacketObserver`] is registered with [`SocketIoConfigBuilder::packet_observer`](crate::SocketIoConfigBuilder::packet_observer).
+//! It only receives the metadata of the packets: the payload itself is never exposed, only its hash.
+//! When no observer is registered, the payloads are not hashed at all.
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{observer::{PacketMeta, PacketObserver}, SocketIoConfig};
+//! # use std::sync::Arc;
+//! struct Audit;
+//! impl PacketObserver for Audit {
+//!     fn on_encode(&self, meta: &PacketMeta<'_>) {
+//!         println!("[{}] out {} {:?} {:x}", meta.sid, meta.ns, meta.packet_type, meta.payload_hash);
+//!     }
+//!     fn on_decode(&self, meta: &PacketMeta<'_>) {
+//!         println!("[{}] in {} {:?} {:x}", meta.sid, meta.ns, meta.packet_type, meta.payload_hash);
+//!     }
+//! }
+//! let config = SocketIoConfig::builder().packet_observer(Arc::new(Audit)).build();
+//! ```
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use engineioxide::sid_generator::Sid;
+
+use crate::packet::PacketData;
+
+/// Observer called for each packet encoded or decoded by the server.
+///
+/// It is called synchronously on the send and receive paths, so it should be cheap,
+/// for example by forwarding the metadata to a channel.
+pub trait PacketObserver: Send + Sync + 'static {
+    /// Called when a packet is encoded to be sent to a socket.
+    /// A broadcast packet is observed once for each socket it is sent to.
+    fn on_encode(&self, _meta: &PacketMeta<'_>) {}
+
+    /// Called when a packet received from a socket is decoded.
+    /// Malformed packets are skipped before being observed.
+    fn on_decode(&self, _meta: &PacketMeta<'_>) {}
+}
+
+/// The metadata of an encoded or decoded packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketMeta<'a> {
+    /// The namespace of the packet
+    pub ns: &'a str,
+    /// The id of the socket sending or receiving the packet
+    pub sid: Sid,
+    /// The type of the packet
+    pub packet_type: PacketType,
+    /// A hash of the encoded text packet, binary attachments are not included.
+    ///
+    /// It is not a cryptographic hash, it is only meant to correlate packets without storing them.
+    pub payload_hash: u64,
+}
+
+/// The type of a socket.io packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketType {
+    Connect,
+    Disconnect,
+    Event,
+    EventAck,
+    ConnectError,
+    BinaryEvent,
+    BinaryAck,
+}
+
+impl PacketType {
+    /// Get the type of an encoded packet from its first character
+    pub(crate) fn from_encoded(msg: &str) -> Option<Self> {
+        match msg.as_bytes().first()? {
+            b'0' => Some(PacketType::Connect),
+            b'1' => Some(PacketType::Disconnect),
+            b'2' => Some(PacketType::Event),
+            b'3' => Some(PacketType::EventAck),
+            b'4' => Some(PacketType::ConnectError),
+            b'5' => Some(PacketType::BinaryEvent),
+            b'6' => Some(PacketType::BinaryAck),
+            _ => None,
+        }
+    }
+
+    /// Get the type of a decoded packet
+    pub(crate) fn from_data(data: &PacketData) -> Self {
+        match data {
+            PacketData::Connect(_) => PacketType::Connect,
+            PacketData::Disconnect => PacketType::Disconnect,
+            PacketData::Event(_, _, _) => PacketType::Event,
+            PacketData::EventAck(_, _) => PacketType::EventAck,
+            PacketData::ConnectError(_) => PacketType::ConnectError,
+            PacketData::BinaryEvent(_, _, _) => PacketType::BinaryEvent,
+            PacketData::BinaryAck(_, _) => PacketType::BinaryAck,
+        }
+    }
+}
+
+/// Hash an encoded packet for its [`PacketMeta`]
+pub(crate) fn hash_payload(msg: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    msg.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::Packet;
+    use serde_json::json;
+
+    #[test]
+    fn test_packet_type() {
+        let packets = [
+            Packet::connect_with_data("/".to_string(), 1i64.into(), Default::default()),
+            Packet::disconnect("/".to_string()),
+            Packet::event("/".to_string(), "event".to_string(), json!(1)),
+            Packet::invalid_namespace("/admin".to_string()),
+        ];
+        for packet in packets {
+            let packet_type = PacketType::from_data(&packet.inner);
+            let msg: String = packet.try_into().unwrap();
+            assert_eq!(PacketType::from_encoded(&msg), Some(packet_type));
+        }
+        assert_eq!(PacketType::from_encoded(""), None);
+        assert_eq!(hash_payload("2[\"a\"]"), hash_payload("2[\"a\"]"));
+    }
+}
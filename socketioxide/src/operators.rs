@@ -1,18 +1,18 @@
 use std::{sync::Arc, time::Duration};
 
-use engineioxide::sid_generator::Sid;
-use futures::stream::BoxStream;
-use itertools::Itertools;
+use engineioxide::{service::TransportType, sid_generator::Sid};
+use futures::{future, stream::BoxStream, Future};
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 
-use crate::errors::BroadcastError;
+use crate::errors::{BroadcastError, Error};
 use crate::{
-    adapter::{Adapter, BroadcastFlags, BroadcastOptions, Room},
+    adapter::{Adapter, BroadcastFlags, BroadcastOptions, RemoteSocket, Room},
     errors::AckError,
     handler::AckResponse,
     ns::Namespace,
-    packet::Packet,
-    Socket,
+    packet::{event_data, event_name, Packet},
+    DisconnectReason, Socket, SocketState,
 };
 
 /// A trait for types that can be used as a room parameter.
@@ -48,6 +48,15 @@ impl<const COUNT: usize> RoomParam for [&'static str; COUNT] {
     }
 }
 
+/// The final report of [`Operators::emit_with_ack_retry`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    /// The sockets that acknowledged the message
+    pub acked: Vec<Sid>,
+    /// The sockets that didn't acknowledge the message after all the attempts
+    pub failed: Vec<Sid>,
+}
+
 /// Operators are used to select clients to send a packet to, or to configure the packet that will be emitted.
 #[derive(Debug)]
 pub struct Operators<A: Adapter> {
@@ -84,8 +93,7 @@ impl<A: Adapter> Operators<A> {
     ///     });
     /// });
     pub fn to(mut self, rooms: impl RoomParam) -> Self {
-        self.opts.rooms.extend(rooms.into_room_iter().unique());
-        self.opts.flags.insert(BroadcastFlags::Broadcast);
+        self.opts = self.opts.to(rooms);
         self
     }
 
@@ -108,7 +116,27 @@ impl<A: Adapter> Operators<A> {
     ///     });
     /// });
     pub fn within(mut self, rooms: impl RoomParam) -> Self {
-        self.opts.rooms.extend(rooms.into_room_iter().unique());
+        self.opts = self.opts.within(rooms);
+        self
+    }
+
+    /// Select all clients in the rooms matching the given glob pattern, including the current socket.
+    ///
+    /// In the pattern, `*` matches any sequence of characters and `?` matches a single character.
+    /// The pattern is matched against all the rooms of the namespace, so it is O(number of rooms).
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         // In all the game rooms like game:1 or game:2, but not in the lobby room
+    ///         socket.within_pattern("game:*").emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn within_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.opts = self.opts.within_pattern(pattern);
         self
     }
 
@@ -131,8 +159,25 @@ impl<A: Adapter> Operators<A> {
     ///     });
     /// });
     pub fn except(mut self, rooms: impl RoomParam) -> Self {
-        self.opts.except.extend(rooms.into_room_iter().unique());
-        self.opts.flags.insert(BroadcastFlags::Broadcast);
+        self.opts = self.opts.except(rooms);
+        self
+    }
+
+    /// Filter out all clients selected with the previous operators which are in the rooms
+    /// matching the given glob pattern, like [`Operators::within_pattern`].
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         // To all the clients except the ones in the rooms like user:1:notifications
+    ///         socket.broadcast().except_pattern("user:1:*").emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn except_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.opts = self.opts.except_pattern(pattern);
         self
     }
 
@@ -149,7 +194,7 @@ impl<A: Adapter> Operators<A> {
     ///     });
     /// });
     pub fn local(mut self) -> Self {
-        self.opts.flags.insert(BroadcastFlags::Local);
+        self.opts = self.opts.local();
         self
     }
 
@@ -165,7 +210,7 @@ impl<A: Adapter> Operators<A> {
     ///     });
     /// });
     pub fn broadcast(mut self) -> Self {
-        self.opts.flags.insert(BroadcastFlags::Broadcast);
+        self.opts = self.opts.broadcast();
         self
     }
 
@@ -187,7 +232,7 @@ impl<A: Adapter> Operators<A> {
     ///             .timeout(Duration::from_secs(5))
     ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
+    ///                    Ok(ack) => println!("Ack received from {}: {:?}", ack.sid, ack.data),
     ///                    Err(err) => println!("Ack error {:?}", err),
     ///                }
     ///             }).await;
@@ -195,7 +240,114 @@ impl<A: Adapter> Operators<A> {
     /// });
     ///
     pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.opts.flags.insert(BroadcastFlags::Timeout(timeout));
+        self.opts = self.opts.timeout(timeout);
+        self
+    }
+
+    /// Only get a page of the selected clients with [`Operators::sockets`],
+    /// the `limit` clients following the first `offset` ones, ordered by id.
+    ///
+    /// It allows to go through the clients of a large namespace without fetching them all at once.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("list", |socket, page: usize, _, _| async move {
+    ///         let sockets = socket.broadcast().page(page * 100, 100).sockets().unwrap();
+    ///         for socket in sockets {
+    ///             println!("{}", socket.sid);
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn page(mut self, offset: usize, limit: usize) -> Self {
+        self.opts = self.opts.page(offset, limit);
+        self
+    }
+
+    /// Limit the number of clients waited for at the same time by [`Operators::emit_with_ack`],
+    /// to bound the resources used when emitting to large rooms.
+    ///
+    /// The message is sent to the next client once an acknowledgement is received or timed out.
+    /// All the acknowledgements still share the same timeout, counted from the call to `emit_with_ack`.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use futures::stream::StreamExt;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         let acks = socket
+    ///             .to("room1")
+    ///             .max_concurrent_acks(100)
+    ///             .emit_with_ack::<Value>("test", data)
+    ///             .unwrap();
+    ///         acks.for_each(|ack| async move { println!("{:?}", ack) }).await;
+    ///     });
+    /// });
+    /// ```
+    pub fn max_concurrent_acks(mut self, max: usize) -> Self {
+        self.opts = self.opts.max_concurrent_acks(max);
+        self
+    }
+
+    /// Skip the sockets that are disconnecting, for example during a server drain.
+    ///
+    /// By default the disconnecting sockets still receive the broadcasted messages,
+    /// the disconnected sockets never do.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         socket.to("room1").skip_disconnecting().emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn skip_disconnecting(mut self) -> Self {
+        self.opts = self.opts.skip_disconnecting();
+        self
+    }
+
+    /// Drop the message if it is not handed to the transport before the given `ttl`.
+    ///
+    /// Messages with binary payloads are never dropped.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use std::time::Duration;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         socket.to("room1").ttl(Duration::from_millis(100)).emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.opts = self.opts.ttl(ttl);
+        self
+    }
+
+    /// Only select the sockets currently using the given transport, see [`Socket::transport`].
+    ///
+    /// For example to send binary-heavy messages only to the websocket clients.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{Namespace, TransportType};
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, bin, _| async move {
+    ///         socket
+    ///             .broadcast()
+    ///             .transport(TransportType::Websocket)
+    ///             .bin(bin)
+    ///             .emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn transport(mut self, transport: TransportType) -> Self {
+        self.opts = self.opts.transport(transport);
         self
     }
 
@@ -215,7 +367,30 @@ impl<A: Adapter> Operators<A> {
         self
     }
 
-    /// Emit a message to all clients selected with the previous operators.
+    /// Disconnect all the sockets selected with the previous operators.
+    ///
+    /// The given [`DisconnectReason`] is passed to the disconnect handlers of each socket.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{Namespace, DisconnectReason};
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("kick", |socket, room: String, _, _| async move {
+    ///         // Kick all the sockets of the room, except the moderator
+    ///         socket.to(room).disconnect(DisconnectReason::Kicked).ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn disconnect(self, reason: DisconnectReason) -> Result<(), BroadcastError> {
+        self.ns.adapter.disconnect_socket(self.opts, reason)
+    }
+
+    /// Emit a message to all clients selected with the previous operators
+    /// and return the number of clients it was sent to.
+    ///
+    /// The data is serialized only once, before selecting the clients.
+    /// If it can't be serialized (e.g. a map with non-string keys),
+    /// a `BroadcastError::Serialize` error is returned right away and nothing is sent.
     /// #### Example
     /// ```
     /// # use socketioxide::Namespace;
@@ -230,7 +405,7 @@ impl<A: Adapter> Operators<A> {
         mut self,
         event: impl Into<String>,
         data: impl serde::Serialize,
-    ) -> Result<(), BroadcastError> {
+    ) -> Result<usize, BroadcastError> {
         let packet = self.get_packet(event, data)?;
         self.ns.adapter.broadcast(packet, self.opts)
     }
@@ -252,7 +427,7 @@ impl<A: Adapter> Operators<A> {
     ///             .bin(bin)
     ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
+    ///                    Ok(ack) => println!("Ack received from {}: {:?}", ack.sid, ack.data),
     ///                    Err(err) => println!("Ack error {:?}", err),
     ///                }
     ///             }).await;
@@ -268,6 +443,82 @@ impl<A: Adapter> Operators<A> {
         self.ns.adapter.broadcast_with_ack(packet, self.opts)
     }
 
+    /// Emit a message to all clients selected with the previous operators, with an at-least-once delivery
+    /// to the connected sockets: the message is sent again to each socket that doesn't acknowledge it,
+    /// up to `retries` times, so each socket receives it at most `retries + 1` times.
+    ///
+    /// Each attempt has a timeout specified in the config (5s by default) or with the `timeout()` operator.
+    /// The first retry waits for `backoff`, then the delay doubles at each retry.
+    /// A socket can receive the message several times if its acknowledgement arrives late,
+    /// and it is not retried once it is disconnected.
+    ///
+    /// The returned future resolves to a [`DeliveryReport`] of the sockets that acknowledged the message or not.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use std::time::Duration;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///    socket.on("alert", |socket, data: Value, _, _| async move {
+    ///       let report = socket.within("ops")
+    ///             .timeout(Duration::from_secs(2))
+    ///             .emit_with_ack_retry("alert", data, 3, Duration::from_millis(500))
+    ///             .unwrap()
+    ///             .await;
+    ///       println!("acked: {:?}, failed: {:?}", report.acked, report.failed);
+    ///    });
+    /// });
+    /// ```
+    pub fn emit_with_ack_retry(
+        mut self,
+        event: impl Into<String>,
+        data: impl serde::Serialize,
+        retries: usize,
+        backoff: Duration,
+    ) -> Result<impl Future<Output = DeliveryReport>, BroadcastError> {
+        let packet = self.get_packet(event, data)?;
+        let timeout = self.opts.flags.iter().find_map(|flag| match flag {
+            BroadcastFlags::Timeout(duration) => Some(*duration),
+            _ => None,
+        });
+        let sockets = self
+            .ns
+            .adapter
+            .fetch_sockets(self.opts)
+            .map_err(|e| BroadcastError::Adapter(e.into()))?;
+
+        let deliveries = sockets.into_iter().map(move |socket| {
+            let packet = packet.clone();
+            async move {
+                let mut delay = backoff;
+                for attempt in 0..=retries {
+                    if attempt > 0 {
+                        tokio::time::sleep(delay).await;
+                        delay = delay.saturating_mul(2);
+                    }
+                    match socket.send_with_ack::<Value>(packet.clone(), timeout).await {
+                        Ok(_) => return (socket.sid, true),
+                        Err(AckError::Disconnected) => break,
+                        Err(_) if socket.state() == SocketState::Disconnected => break,
+                        Err(_) => continue,
+                    }
+                }
+                (socket.sid, false)
+            }
+        });
+        Ok(async move {
+            let mut report = DeliveryReport::default();
+            for (sid, acked) in future::join_all(deliveries).await {
+                if acked {
+                    report.acked.push(sid);
+                } else {
+                    report.failed.push(sid);
+                }
+            }
+            report
+        })
+    }
+
     /// Get all sockets selected with the previous operators.
     ///
     /// It can be used to retrieve any extension data from the sockets or to make some sockets join other rooms.
@@ -288,19 +539,49 @@ impl<A: Adapter> Operators<A> {
         self.ns.adapter.fetch_sockets(self.opts)
     }
 
+    /// Get a serializable summary of all the sockets selected with the previous operators,
+    /// including the ones connected to the other servers if the adapter spans several servers.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///   socket.on("test", |socket, _: (), _, _| async move {
+    ///     for remote in socket.within("room1").remote_sockets().unwrap() {
+    ///         println!("{} is in {:?}", remote.sid, remote.rooms);
+    ///     }
+    ///   });
+    /// });
+    /// ```
+    pub fn remote_sockets(self) -> Result<Vec<RemoteSocket>, A::Error> {
+        self.ns.adapter.fetch_remote_sockets(self.opts)
+    }
+
+    /// Get all sockets selected with the previous operators.
+    ///
+    /// Unlike [`Operators::sockets`], it returns an [`Error::RoomNotFound`] if one of the selected rooms doesn't exist.
+    /// It is useful to not silently succeed on a mistyped room name.
+    pub fn sockets_strict(self) -> Result<Vec<Arc<Socket<A>>>, Error> {
+        self.ns.adapter.check_rooms(&self.opts.rooms)?;
+        self.ns
+            .adapter
+            .fetch_sockets(self.opts)
+            .map_err(|e| Error::Adapter(e.into()))
+    }
+
     /// Create a packet with the given event and data.
     fn get_packet(
         &mut self,
         event: impl Into<String>,
         data: impl Serialize,
-    ) -> Result<Packet, serde_json::Error> {
+    ) -> Result<Packet, BroadcastError> {
         let ns = self.ns.clone();
-        let data = serde_json::to_value(data)?;
+        let event = event_name(event)?;
+        let data = event_data(data)?;
         let packet = if self.binary.is_empty() {
-            Packet::event(ns.path.clone(), event.into(), data)
+            Packet::event(ns.path.clone(), event, data)
         } else {
             let binary = std::mem::take(&mut self.binary);
-            Packet::bin_event(ns.path.clone(), event.into(), data, binary)
+            Packet::bin_event(ns.path.clone(), event, data, binary)
         };
         Ok(packet)
     }
@@ -1,9 +1,11 @@
-use itertools::{Itertools, PeekingNext};
+use base64::{engine::general_purpose, Engine};
+use itertools::Itertools;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use tracing::debug;
 
 use crate::errors::Error;
+use crate::transform::PayloadTransform;
 use engineioxide::sid_generator::Sid;
 use engineioxide::SendPacket as EnginePacket;
 
@@ -26,6 +28,27 @@ impl Packet {
             ns,
         }
     }
+
+    /// Create a connect packet with extra fields besides the `sid`.
+    /// The `sid` always comes from the server, a `sid` field in `data` is overwritten.
+    pub fn connect_with_data(ns: String, sid: Sid, mut data: Map<String, Value>) -> Self {
+        data.insert("sid".to_string(), Value::String(sid.to_string()));
+        Self {
+            inner: PacketData::Connect(Value::Object(data)),
+            ns,
+        }
+    }
+    /// Create the `disconnect_reason` event telling a client why it is disconnected,
+    /// it is sent right before the disconnect packet which cannot carry the reason
+    pub fn disconnect_reason(ns: String, reason: &str) -> Self {
+        Self::event(
+            ns,
+            "disconnect_reason".to_string(),
+            json!({ "reason": reason }),
+        )
+    }
+
+    /// Create a disconnect packet for the given namespace
     pub fn disconnect(ns: String) -> Self {
         Self {
             inner: PacketData::Disconnect,
@@ -35,15 +58,53 @@ impl Packet {
 }
 
 impl Packet {
+    /// Reject a connection to a namespace that doesn't exist
     pub fn invalid_namespace(ns: String) -> Self {
         Self {
             inner: PacketData::ConnectError(ConnectErrorPacket {
                 message: "Invalid namespace".to_string(),
+                data: None,
+            }),
+            ns,
+        }
+    }
+
+    /// Reject a connection to a namespace that reached its maximum number of connections
+    pub fn namespace_full(ns: String) -> Self {
+        Self {
+            inner: PacketData::ConnectError(ConnectErrorPacket {
+                message: "Namespace full".to_string(),
+                data: Some(json!({ "reason": "capacity" })),
+            }),
+            ns,
+        }
+    }
+
+    /// Reject a connection from an origin that is not allowed by the server
+    pub fn origin_not_allowed(ns: String) -> Self {
+        Self {
+            inner: PacketData::ConnectError(ConnectErrorPacket {
+                message: "Origin not allowed".to_string(),
+                data: Some(json!({ "reason": "origin" })),
             }),
             ns,
         }
     }
 
+    /// Reject a connection that failed on the server side.
+    ///
+    /// The error itself is not sent, it may leak server internals to the client
+    pub fn connect_failed(ns: String) -> Self {
+        Self {
+            inner: PacketData::ConnectError(ConnectErrorPacket {
+                message: "Connection failed".to_string(),
+                data: Some(json!({ "reason": "server" })),
+            }),
+            ns,
+        }
+    }
+
+    /// Create an event packet, the `data` are the arguments of the event
     pub fn event(ns: String, e: String, data: Value) -> Self {
         Self {
             inner: PacketData::Event(e, data, None),
@@ -51,6 +112,7 @@ impl Packet {
         }
     }
 
+    /// Create an event packet with binary attachments, `bin` must not be empty
     pub fn bin_event(ns: String, e: String, data: Value, bin: Vec<Vec<u8>>) -> Self {
         debug_assert!(!bin.is_empty());
 
@@ -61,12 +123,14 @@ impl Packet {
         }
     }
 
+    /// Create an acknowledgement packet responding to the event with the given `ack` id
     pub fn ack(ns: String, data: Value, ack: i64) -> Self {
         Self {
             inner: PacketData::EventAck(data, ack),
             ns,
         }
     }
+    /// Create an acknowledgement packet with binary attachments, `bin` must not be empty
     pub fn bin_ack(ns: String, data: Value, bin: Vec<Vec<u8>>, ack: i64) -> Self {
         debug_assert!(!bin.is_empty());
         let packet = BinaryPacket::outgoing(data, bin);
@@ -77,6 +141,92 @@ impl Packet {
     }
 }
 
+impl Packet {
+    /// Encode the binary attachments of an event or an ack in base64 strings, in place of their placeholders.
+    ///
+    /// The packet becomes a plain event or ack, for the clients that don't support the binary packets.
+    pub(crate) fn inline_binary(self) -> Self {
+        let inner = match self.inner {
+            PacketData::BinaryEvent(e, bin, ack) => PacketData::Event(e, bin.inlined(), ack),
+            PacketData::BinaryAck(bin, ack) => PacketData::EventAck(bin.inlined(), ack),
+            inner => inner,
+        };
+        Self { inner, ns: self.ns }
+    }
+
+    /// Transform the arguments and the attachments of an event or an ack with the given [`PayloadTransform`].
+    ///
+    /// The transformed JSON arguments become the first attachment, followed by the transformed attachments,
+    /// so the event name is kept in clear -> 51-["event",{"_placeholder":true,"num":0}]
+    pub(crate) fn transform_payload(
+        self,
+        transform: &dyn PayloadTransform,
+    ) -> Result<Self, serde_json::Error> {
+        let encode = |args: Value, bin: Vec<Vec<u8>>| -> Result<BinaryPacket, serde_json::Error> {
+            let mut attachments = Vec::with_capacity(bin.len() + 1);
+            attachments.push(transform.encode(&serde_json::to_vec(&args)?));
+            attachments.extend(bin.iter().map(|b| transform.encode(b)));
+            Ok(BinaryPacket::outgoing(Value::Array(vec![]), attachments))
+        };
+        // The placeholders of the outgoing binary packets are removed from their arguments
+        let inner = match self.inner {
+            PacketData::Event(e, data, ack) => {
+                PacketData::BinaryEvent(e, encode(ack_args(data), vec![])?, ack)
+            }
+            PacketData::BinaryEvent(e, bin, ack) => {
+                let args = BinaryPacket::incoming(bin.data).data;
+                PacketData::BinaryEvent(e, encode(args, bin.bin)?, ack)
+            }
+            PacketData::EventAck(data, ack) => {
+                PacketData::BinaryAck(encode(ack_array(data), vec![])?, ack)
+            }
+            PacketData::BinaryAck(bin, ack) => {
+                let args = BinaryPacket::incoming(bin.data).data;
+                PacketData::BinaryAck(encode(args, bin.bin)?, ack)
+            }
+            inner => inner,
+        };
+        Ok(Self { inner, ns: self.ns })
+    }
+}
+
+impl PacketData {
+    /// Restore the arguments and the attachments of a received event or ack
+    /// transformed like [`Packet::transform_payload`].
+    ///
+    /// Returns `None` if the packet was not transformed or cannot be restored.
+    pub(crate) fn restore_payload(self, transform: &dyn PayloadTransform) -> Option<Self> {
+        let decode = |bin: BinaryPacket| -> Option<(Value, Vec<Vec<u8>>)> {
+            let mut attachments = bin.bin.into_iter();
+            let args = transform.decode(&attachments.next()?)?;
+            let args = ack_args(serde_json::from_slice(&args).ok()?);
+            let bin = attachments
+                .map(|b| transform.decode(&b))
+                .collect::<Option<Vec<_>>>()?;
+            Some((args, bin))
+        };
+        match self {
+            PacketData::BinaryEvent(e, bin, ack) => {
+                let (data, bin) = decode(bin)?;
+                Some(if bin.is_empty() {
+                    PacketData::Event(e, data, ack)
+                } else {
+                    PacketData::BinaryEvent(e, BinaryPacket::decoded(data, bin), ack)
+                })
+            }
+            PacketData::BinaryAck(bin, ack) => {
+                let (data, bin) = decode(bin)?;
+                Some(if bin.is_empty() {
+                    PacketData::EventAck(data, ack)
+                } else {
+                    PacketData::BinaryAck(BinaryPacket::decoded(data, bin), ack)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
 /// | Type          | ID  | Usage                                                                                 |
 /// |---------------|-----|---------------------------------------------------------------------------------------|
 /// | CONNECT       | 0   | Used during the [connection to a namespace](#connection-to-a-namespace).              |
@@ -88,15 +238,22 @@ impl Packet {
 /// | BINARY_ACK    | 6   | Used to [acknowledge](#acknowledgement) an event (the response includes binary data). |
 #[derive(Debug, Clone, PartialEq)]
 pub enum PacketData {
+    /// The auth payload sent by a client, exposed with `Handshake::auth`,
+    /// or the `sid` and the extra fields of the server response
     Connect(Value),
     Disconnect,
+    /// The event name, its data and the ack id if an acknowledgement is requested
     Event(String, Value, Option<i64>),
+    /// The data of an acknowledgement and the id of the acknowledged event
     EventAck(Value, i64),
     ConnectError(ConnectErrorPacket),
+    /// Like [`PacketData::Event`], with binary attachments
     BinaryEvent(String, BinaryPacket, Option<i64>),
+    /// Like [`PacketData::EventAck`], with binary attachments
     BinaryAck(BinaryPacket, i64),
 }
 
+/// The data of a packet along with its binary attachments
 #[derive(Debug, Clone, PartialEq)]
 pub struct BinaryPacket {
     pub data: Value,
@@ -117,6 +274,38 @@ impl PacketData {
         }
     }
 
+    /// Take the binary payloads out of the packet
+    /// The payload count is kept so the packet can still be serialized with its placeholders
+    pub(crate) fn take_bin(&mut self) -> Vec<Vec<u8>> {
+        match self {
+            PacketData::BinaryEvent(_, bin, _) | PacketData::BinaryAck(bin, _) => {
+                std::mem::take(&mut bin.bin)
+            }
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn is_event(&self) -> bool {
+        matches!(
+            self,
+            PacketData::Event(_, _, _) | PacketData::BinaryEvent(_, _, _)
+        )
+    }
+
+    /// Append an argument to the arguments of an event
+    /// It will only append it to the event packets
+    pub(crate) fn push_event_arg(&mut self, arg: Value) {
+        let data = match self {
+            PacketData::Event(_, data, _) => data,
+            PacketData::BinaryEvent(_, bin, _) => &mut bin.data,
+            _ => return,
+        };
+        match data {
+            Value::Array(v) => v.push(arg),
+            _ => *data = Value::Array(vec![data.take(), arg]),
+        }
+    }
+
     /// Set the ack id for the packet
     /// It will only set the ack id for the packets that support it
     pub(crate) fn set_ack_id(&mut self, ack_id: i64) {
@@ -159,6 +348,16 @@ impl BinaryPacket {
         }
     }
 
+    /// Create a binary packet from received data, keeping its placeholders in place
+    /// so they can be replaced by their attachments once they are all received
+    fn received(data: Value) -> Self {
+        Self {
+            payload_count: count_placeholders(&data),
+            data,
+            bin: Vec::new(),
+        }
+    }
+
     /// Create a binary packet from outgoing data and a payload
     pub fn outgoing(data: Value, bin: Vec<Vec<u8>>) -> Self {
         let mut data = match data {
@@ -178,6 +377,34 @@ impl BinaryPacket {
             payload_count,
         }
     }
+    /// Create a complete binary packet from received data without placeholders and its payloads
+    pub(crate) fn decoded(data: Value, bin: Vec<Vec<u8>>) -> Self {
+        Self {
+            data,
+            payload_count: bin.len(),
+            bin,
+        }
+    }
+    /// Expect the attachment count announced in the packet header rather than the count of placeholders
+    fn expecting(mut self, payload_count: Option<usize>) -> Self {
+        if let Some(payload_count) = payload_count {
+            self.payload_count = payload_count;
+        }
+        self
+    }
+
+    /// Get the data with the placeholders replaced by their payload encoded in base64
+    fn inlined(self) -> Value {
+        let mut data = self.data;
+        replace_placeholders(&mut data, &self.bin);
+        data
+    }
+
+    /// The number of payloads that are still expected before the packet is complete
+    pub fn missing_payloads(&self) -> usize {
+        self.payload_count.saturating_sub(self.bin.len())
+    }
+
     pub fn add_payload(&mut self, payload: Vec<u8>) {
         self.bin.push(payload);
     }
@@ -186,11 +413,144 @@ impl BinaryPacket {
     }
 }
 
+/// A binary packet waiting for its attachments.
+///
+/// The header of a binary event or acknowledgement is received in a text frame,
+/// its attachments are then received one by one in the following binary frames.
+///
+/// Once they are all received, each `{"_placeholder":true,"num":N}` object of the arguments,
+/// at any depth, is replaced by the attachment N encoded in base64.
+/// The attachments are also given to the handlers in the order of their frames, the attachment N
+/// being the N-th binary frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialPacket {
+    /// The packet being filled, taken once all its attachments are received
+    packet: Option<Packet>,
+    /// The number of attachments announced in the header of the packet
+    expected: usize,
+}
+
+impl PartialPacket {
+    /// Start the reassembly of a binary packet from its header.
+    ///
+    /// Returns an [`Error::InvalidPacketType`] if the packet is not a binary event or acknowledgement.
+    pub fn new(packet: Packet) -> Result<Self, Error> {
+        match &packet.inner {
+            PacketData::BinaryEvent(_, bin, _) | PacketData::BinaryAck(bin, _) => Ok(Self {
+                expected: bin.payload_count,
+                packet: Some(packet),
+            }),
+            _ => Err(Error::InvalidPacketType),
+        }
+    }
+
+    /// Get the error of a packet interrupted by another packet before receiving all its attachments.
+    ///
+    /// Returns `None` if the packet is complete.
+    pub fn interrupted(&mut self) -> Option<Error> {
+        let got = self.binary()?.bin.len();
+        (got < self.expected).then(|| Error::InvalidAttachmentCount {
+            expected: self.expected,
+            got,
+        })
+    }
+
+    /// Get the packet, with its placeholders replaced by their attachments,
+    /// if it doesn't expect any more attachment.
+    pub fn try_complete(&mut self) -> Option<Packet> {
+        let bin = self.binary()?;
+        if !bin.is_complete() {
+            return None;
+        }
+        replace_placeholders(&mut bin.data, &bin.bin);
+        self.packet.take()
+    }
+
+    /// Add the next attachment to the packet.
+    ///
+    /// Returns the packet once all its attachments are received,
+    /// or an [`Error::InvalidAttachmentCount`] if the packet didn't expect any more attachment.
+    pub fn push_binary(&mut self, data: Vec<u8>) -> Result<Option<Packet>, Error> {
+        let expected = self.expected;
+        let bin = match self.binary() {
+            Some(bin) if !bin.is_complete() => bin,
+            _ => {
+                return Err(Error::InvalidAttachmentCount {
+                    expected,
+                    got: expected + 1,
+                })
+            }
+        };
+        bin.add_payload(data);
+        debug!("{} payloads missing", bin.missing_payloads());
+        Ok(self.try_complete())
+    }
+
+    fn binary(&mut self) -> Option<&mut BinaryPacket> {
+        match &mut self.packet.as_mut()?.inner {
+            PacketData::BinaryEvent(_, bin, _) | PacketData::BinaryAck(bin, _) => Some(bin),
+            _ => None,
+        }
+    }
+}
+
 impl TryInto<String> for Packet {
     type Error = serde_json::Error;
 
     fn try_into(self) -> Result<String, Self::Error> {
-        let mut res = self.inner.index().to_string();
+        let mut res = String::new();
+        self.encode_into(&mut res)?;
+        Ok(res)
+    }
+}
+
+impl Packet {
+    /// Decode a packet received from a client, rejecting it before parsing it
+    /// if it is bigger than `max_payload_bytes`.
+    pub(crate) fn decode(value: String, max_payload_bytes: usize) -> Result<Self, Error> {
+        if value.len() > max_payload_bytes {
+            return Err(Error::PayloadTooLarge {
+                size: value.len(),
+                max: max_payload_bytes,
+            });
+        }
+        Self::try_from(value)
+    }
+
+    /// Rewrite the namespace header of a packet encoded for the main namespace `/`.
+    ///
+    /// It allows to serialize the body of a packet only once and to reuse it for multiple namespaces.
+    pub(crate) fn with_ns_header(encoded: &str, ns: &str) -> String {
+        if ns.is_empty() || ns == "/" {
+            return encoded.to_string();
+        }
+        // The attachment count of binary packets comes before the namespace -> 51-/admin,[...]
+        let header_len = match encoded.as_bytes().first() {
+            Some(b'5' | b'6') => encoded.find('-').map_or(1, |i| i + 1),
+            _ => 1,
+        };
+        let (index, body) = encoded.split_at(header_len);
+        format!("{}{},{}", index, ns, body)
+    }
+
+    /// Serialize the packet and take its binary attachments, so the transport can send them as separate frames.
+    ///
+    /// The attachments are replaced by placeholders in the serialized packet.
+    pub fn encode(mut self) -> Result<(String, Vec<Vec<u8>>), serde_json::Error> {
+        let bin = self.inner.take_bin();
+        Ok((self.try_into()?, bin))
+    }
+
+    /// Serialize the packet and append it to the given buffer.
+    ///
+    /// It allows to reuse a buffer when encoding many packets.
+    pub fn encode_into(self, res: &mut String) -> Result<(), serde_json::Error> {
+        res.push_str(&self.inner.index().to_string());
+        // The attachment count comes before the namespace -> 51-/admin,["event",{"_placeholder":true,"num":0}]
+        if let PacketData::BinaryEvent(_, bin, _) | PacketData::BinaryAck(bin, _) = &self.inner {
+            res.push_str(&bin.payload_count.to_string());
+            res.push('-');
+        }
         if !self.ns.is_empty() && self.ns != "/" {
             res.push_str(&format!("{},", self.ns));
         }
@@ -198,69 +558,122 @@ impl TryInto<String> for Packet {
         match self.inner {
             PacketData::Connect(data) => res.push_str(&serde_json::to_string(&data)?),
             PacketData::Disconnect => (),
-            PacketData::Event(event, data, ack) => {
+            // The placeholders of the binary packets are already in their data
+            PacketData::Event(event, data, ack)
+            | PacketData::BinaryEvent(event, BinaryPacket { data, .. }, ack) => {
                 if let Some(ack) = ack {
                     res.push_str(&ack.to_string());
                 }
-                // Expand the packet if it is an array -> ["event", ...data]
-                let packet = match data {
-                    Value::Array(mut v) => {
-                        v.insert(0, Value::String(event));
-                        serde_json::to_string(&v)?
-                    }
-                    _ => serde_json::to_string(&(event, data))?,
-                };
+                let packet = serde_json::to_string(&event_array(event, data))?;
+                debug_assert!(
+                    is_event_array(&packet),
+                    "encoded event must be a JSON array starting with the event name: {packet}"
+                );
                 res.push_str(&packet)
             }
-            PacketData::EventAck(data, ack) => {
+            PacketData::EventAck(data, ack)
+            | PacketData::BinaryAck(BinaryPacket { data, .. }, ack) => {
                 res.push_str(&ack.to_string());
-                // Enforce that the packet is an array -> [data]
-                let packet = match data {
-                    Value::Array(_) => data,
-                    Value::Null => Value::Array(vec![]),
-                    _ => Value::Array(vec![data]),
-                };
-                let packet = serde_json::to_string(&packet)?;
-                res.push_str(&packet)
+                res.push_str(&serde_json::to_string(&ack_array(data))?)
             }
             PacketData::ConnectError(data) => res.push_str(&serde_json::to_string(&data)?),
-            PacketData::BinaryEvent(event, bin, ack) => {
-                res.push_str(&bin.payload_count.to_string());
-                res.push('-');
+        };
+        Ok(())
+    }
+}
 
-                if let Some(ack) = ack {
-                    res.push_str(&ack.to_string());
-                }
-                // Expand the packet if it is an array -> ["event", ...data]
-                let array = match bin.data {
-                    Value::Array(mut v) => {
-                        v.insert(0, Value::String(event));
-                        v
-                    }
-                    _ => vec![Value::String(event), bin.data],
-                };
-
-                let packet = serde_json::to_string(&array)?;
-                res.push_str(&packet)
-            }
-            PacketData::BinaryAck(packet, ack) => {
-                res.push_str(&packet.payload_count.to_string());
-                res.push('-');
-                res.push_str(&ack.to_string());
-                // Enforce that the packet is an array -> [data]
-                let data = match packet.data {
-                    Value::Array(_) => packet.data,
-                    Value::Null => Value::Array(vec![]),
-                    _ => Value::Array(vec![packet.data]),
-                };
-                let packet = serde_json::to_string(&data)?;
-                res.push_str(&packet)
+/// Count the `{"_placeholder":true,"num":N}` objects of a value, at any depth
+fn count_placeholders(value: &Value) -> usize {
+    match value {
+        Value::Array(v) => v.iter().map(count_placeholders).sum(),
+        Value::Object(o) if o.get("_placeholder") == Some(&Value::Bool(true)) => 1,
+        Value::Object(o) => o.values().map(count_placeholders).sum(),
+        _ => 0,
+    }
+}
+
+/// Replace the `{"_placeholder":true,"num":N}` objects with the payload N encoded in base64
+fn replace_placeholders(value: &mut Value, bin: &[Vec<u8>]) {
+    match value {
+        Value::Array(v) => v.iter_mut().for_each(|v| replace_placeholders(v, bin)),
+        Value::Object(o) => {
+            let num = match (o.get("_placeholder"), o.get("num")) {
+                (Some(Value::Bool(true)), Some(num)) => num.as_u64(),
+                _ => None,
+            };
+            match num.and_then(|num| bin.get(num as usize)) {
+                Some(payload) => *value = Value::String(general_purpose::STANDARD.encode(payload)),
+                None => o.values_mut().for_each(|v| replace_placeholders(v, bin)),
             }
-        };
-        Ok(res)
+        }
+        _ => {}
+    }
+}
+
+/// The events that the socket.io clients reserve for their lifecycle
+const RESERVED_EVENTS: [&str; 6] = [
+    "connect",
+    "connect_error",
+    "disconnect",
+    "disconnecting",
+    "newListener",
+    "removeListener",
+];
+
+/// Check that an event can be emitted to a client and return its name.
+///
+/// An empty event name is rejected, and so are the reserved ones:
+/// a client would handle them as lifecycle events rather than dispatching them to its listeners.
+pub(crate) fn event_name(event: impl Into<String>) -> Result<String, Error> {
+    let event = event.into();
+    if event.is_empty() || RESERVED_EVENTS.contains(&event.as_str()) {
+        Err(Error::InvalidEventName)
+    } else {
+        Ok(event)
     }
 }
 
+/// Expand the data of an event in an array led by the event name -> ["event", ...data]
+fn event_array(event: String, data: Value) -> Value {
+    match data {
+        Value::Array(mut v) => {
+            v.insert(0, Value::String(event));
+            Value::Array(v)
+        }
+        data => Value::Array(vec![Value::String(event), data]),
+    }
+}
+
+/// Enforce that the data of an ack is an array -> [data]
+fn ack_array(data: Value) -> Value {
+    match data {
+        Value::Array(_) => data,
+        Value::Null => Value::Array(vec![]),
+        data => Value::Array(vec![data]),
+    }
+}
+
+/// Serialize the data of an event.
+///
+/// Zero-sized payloads serialized as `null` (like `()` or unit structs) mean that there is no data,
+/// so they are turned into an empty array and the event is encoded as `["event"]` rather than `["event",null]`.
+pub(crate) fn event_data<T: Serialize>(data: T) -> Result<Value, serde_json::Error> {
+    let is_zero_sized = std::mem::size_of_val(&data) == 0;
+    match serde_json::to_value(data)? {
+        Value::Null if is_zero_sized => Ok(Value::Array(vec![])),
+        data => Ok(data),
+    }
+}
+
+/// Check that an encoded event is a JSON array whose first element is the event name.
+/// It is only used to guard the encoding invariant in debug builds.
+fn is_event_array(data: &str) -> bool {
+    matches!(
+        serde_json::from_str::<Value>(data),
+        Ok(Value::Array(v)) if v.first().map_or(false, Value::is_string)
+    )
+}
+
 /// Deserialize an event packet from a string, formated as:
 /// ```text
 /// ["<event name>", ...<JSON-stringified payload without binary>]
@@ -282,6 +695,30 @@ fn deserialize_event_packet(data: &str) -> Result<(String, Value), Error> {
     Ok((event, payload))
 }
 
+/// Parse the attachment count and the `-` separator of a binary packet
+fn parse_attachment_count(chars: &mut std::str::Chars<'_>) -> Result<usize, Error> {
+    let count = chars
+        .take_while_ref(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .map_err(|_| Error::InvalidPacketType)?;
+    match chars.next() {
+        Some('-') => Ok(count),
+        _ => Err(Error::InvalidPacketType),
+    }
+}
+
+/// Coerce an ack payload to an array of arguments.
+///
+/// Some clients send a bare value rather than an array when acknowledging with a single argument,
+/// it is handled as an array containing only this argument.
+fn ack_args(data: Value) -> Value {
+    match data {
+        Value::Array(_) => data,
+        data => Value::Array(vec![data]),
+    }
+}
+
 fn deserialize_packet<T: DeserializeOwned>(data: &str) -> Result<Option<T>, Error> {
     debug!("Deserializing packet: {:?}", data);
     let packet = if data.is_empty() {
@@ -298,6 +735,10 @@ fn deserialize_packet<T: DeserializeOwned>(data: &str) -> Result<Option<T>, Erro
 /// <packet type>[<# of binary attachments>-][<namespace>,][<acknowledgment id>][JSON-stringified payload without binary]
 /// + binary attachments extracted
 /// ```
+///
+/// The string is the payload of an engine.io message packet, without its `4` message prefix.
+/// The engine strips this prefix before handing the message to the socket.io layer,
+/// a prefixed string would be misread as a connect error packet.
 impl TryFrom<String> for Packet {
     type Error = Error;
 
@@ -305,30 +746,23 @@ impl TryFrom<String> for Packet {
         let mut chars = value.chars();
         let index = chars.next().ok_or(Error::InvalidPacketType)?;
 
-        let attachments: u8 = if index == '5' || index == '6' {
-            chars
-                .take_while_ref(|c| *c != '-')
-                .collect::<String>()
-                .parse()
-                .unwrap_or(0)
+        // The attachment count of binary packets comes before the namespace -> 51-/admin,[...]
+        let attachments = if index == '5' || index == '6' {
+            Some(parse_attachment_count(&mut chars)?)
         } else {
-            0
+            None
         };
 
-        // If there are attachments, skip the `-` separator
-        chars.peeking_next(|c| attachments > 0 && !c.is_ascii_digit());
-
-        let mut ns: String = chars
-            .take_while_ref(|c| *c != ',' && *c != '{' && *c != '[' && !c.is_ascii_digit())
-            .collect();
-
-        // If there is a namespace, skip the `,` separator
-        if !ns.is_empty() {
+        // The namespace is omitted for the main namespace, otherwise it starts with `/` and ends with `,`.
+        // It may contain digits, so it can't be delimited by the ack id.
+        let ns: String = if chars.clone().next() == Some('/') {
+            let ns = chars.take_while_ref(|c| *c != ',').collect();
+            // Skip the `,` separator
             chars.next();
-        }
-        if !ns.starts_with('/') {
-            ns.insert(0, '/');
-        }
+            ns
+        } else {
+            "/".to_string()
+        };
 
         let ack: Option<i64> = chars
             .take_while_ref(|c| c.is_ascii_digit())
@@ -346,22 +780,27 @@ impl TryFrom<String> for Packet {
             }
             '3' => {
                 let packet = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
-                PacketData::EventAck(packet, ack.ok_or(Error::InvalidPacketType)?)
+                PacketData::EventAck(ack_args(packet), ack.ok_or(Error::InvalidPacketType)?)
             }
             '4' => {
-                let payload = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
-                PacketData::ConnectError(payload)
+                // A connect error is a JSON object, an event with a leftover engine.io prefix
+                // (42["event"]) would otherwise be read as a connect error with an ack id
+                let payload: Value = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
+                if ack.is_some() || !payload.is_object() {
+                    return Err(Error::InvalidConnectError);
+                }
+                PacketData::ConnectError(serde_json::from_value(payload)?)
             }
+            // The attachments are received in separate frames, the packets wait for the announced count
             '5' => {
                 let (event, payload) = deserialize_event_packet(data)?;
-                PacketData::BinaryEvent(event, BinaryPacket::incoming(payload), ack)
+                let bin = BinaryPacket::received(payload).expecting(attachments);
+                PacketData::BinaryEvent(event, bin, ack)
             }
             '6' => {
                 let packet = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
-                PacketData::BinaryAck(
-                    BinaryPacket::incoming(packet),
-                    ack.ok_or(Error::InvalidPacketType)?,
-                )
+                let bin = BinaryPacket::received(ack_args(packet)).expecting(attachments);
+                PacketData::BinaryAck(bin, ack.ok_or(Error::InvalidPacketType)?)
             }
             _ => return Err(Error::InvalidPacketType),
         };
@@ -382,13 +821,17 @@ pub struct ConnectPacket {
     sid: String,
 }
 
+/// The reason why a connection to a namespace was rejected
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConnectErrorPacket {
     message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
 }
 
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
     use serde_json::json;
 
     use super::*;
@@ -422,4 +865,609 @@ mod test {
             packet.unwrap()
         );
     }
+
+    #[test]
+    fn test_inline_binary() {
+        let packet = Packet::bin_event(
+            "/".to_string(),
+            "file".to_string(),
+            json!("name"),
+            vec![vec![1, 2, 3], vec![255]],
+        );
+        let payload: String = packet.inline_binary().try_into().unwrap();
+        assert_eq!(payload, r#"2["file","name","AQID","/w=="]"#);
+
+        let packet = Packet::bin_ack("/".to_string(), json!([]), vec![vec![1, 2, 3]], 4);
+        let payload: String = packet.inline_binary().try_into().unwrap();
+        assert_eq!(payload, r#"34["AQID"]"#);
+
+        // The packets without attachments are kept as is
+        let packet = Packet::event("/".to_string(), "event".to_string(), json!(1));
+        assert_eq!(packet.clone().inline_binary(), packet);
+    }
+
+    #[test]
+    fn test_event_name() {
+        for event in ["", "connect", "disconnect", "connect_error"] {
+            assert!(
+                matches!(event_name(event), Err(Error::InvalidEventName)),
+                "event {event:?} should be rejected"
+            );
+        }
+        assert_eq!(event_name("connected").unwrap(), "connected");
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let payload = format!(r#"2["event","{}"]"#, "a".repeat(100));
+        let max = payload.len();
+        assert!(Packet::decode(payload.clone(), max).is_ok());
+        assert!(matches!(
+            Packet::decode(payload, max - 1),
+            Err(Error::PayloadTooLarge { size, max: m }) if size == max && m == max - 1
+        ));
+    }
+
+    #[test]
+    fn test_constructors() {
+        let ns = || "/admin".to_string();
+        let packets = [
+            (Packet::disconnect(ns()), PacketData::Disconnect),
+            (
+                Packet::ack(ns(), json!(["ok"]), 3),
+                PacketData::EventAck(json!(["ok"]), 3),
+            ),
+            (
+                Packet::bin_ack(ns(), json!("ok"), vec![vec![1]], 4),
+                PacketData::BinaryAck(BinaryPacket::outgoing(json!("ok"), vec![vec![1]]), 4),
+            ),
+        ];
+        for (packet, inner) in packets {
+            assert_eq!(packet, Packet { inner, ns: ns() });
+        }
+    }
+
+    #[test]
+    fn test_decode_connect_auth() {
+        let packet =
+            Packet::try_from(r#"0/admin,{"token":"123","device":"mobile"}"#.to_string()).unwrap();
+        assert_eq!(packet.ns, "/admin");
+        assert_eq!(
+            packet.inner,
+            PacketData::Connect(json!({ "token": "123", "device": "mobile" }))
+        );
+
+        // A client may connect without any auth payload
+        let packet = Packet::try_from("0/admin,".to_string()).unwrap();
+        assert_eq!(packet.inner, PacketData::Connect(json!({})));
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let mut buf = String::with_capacity(64);
+        Packet::event("/".to_string(), "event".to_string(), json!({ "data": 1 }))
+            .encode_into(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "2[\"event\",{\"data\":1}]");
+
+        // The packet is appended to the existing content
+        Packet::disconnect("/admin".to_string())
+            .encode_into(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "2[\"event\",{\"data\":1}]1/admin,");
+    }
+    #[test]
+    fn test_encode_ack() {
+        // The ack id comes right after the optional namespace header
+        for (ns, data, expected) in [
+            ("/", json!(["ok", 1]), r#"312["ok",1]"#),
+            ("/admin", json!(["ok", 1]), r#"3/admin,12["ok",1]"#),
+            ("/", json!({ "ok": true }), r#"312[{"ok":true}]"#),
+            ("/admin", json!(null), r#"3/admin,12[]"#),
+        ] {
+            let packet = Packet {
+                inner: PacketData::EventAck(data, 12),
+                ns: ns.to_string(),
+            };
+            let payload: String = packet.try_into().unwrap();
+            assert_eq!(payload, expected);
+        }
+    }
+    #[test]
+    fn test_is_event_array() {
+        assert!(is_event_array(r#"["event"]"#));
+        assert!(is_event_array(r#"["event",{"data":1},2]"#));
+        assert!(!is_event_array(r#"[{"data":1},"event"]"#));
+        assert!(!is_event_array(r#"{"event":"data"}"#));
+        assert!(!is_event_array("[]"));
+        assert!(!is_event_array("\"event\""));
+
+        // Every event shape is encoded as an array led by the event name
+        for data in [
+            json!(null),
+            json!("str"),
+            json!({"a": 1}),
+            json!([1, 2]),
+            json!([]),
+        ] {
+            let payload: String = Packet::event("/".to_string(), "event".to_string(), data)
+                .try_into()
+                .unwrap();
+            assert!(is_event_array(&payload[1..]));
+        }
+    }
+    #[test]
+    fn test_encode_event_without_data() {
+        let payload: String =
+            Packet::event("/".to_string(), "ping".to_string(), event_data(()).unwrap())
+                .try_into()
+                .unwrap();
+        assert_eq!(payload, r#"2["ping"]"#);
+
+        let payload: String = Packet::event(
+            "/".to_string(),
+            "ping".to_string(),
+            event_data(Value::Null).unwrap(),
+        )
+        .try_into()
+        .unwrap();
+        assert_eq!(payload, r#"2["ping",null]"#);
+
+        let payload: String = Packet::event(
+            "/".to_string(),
+            "ping".to_string(),
+            event_data(None::<u8>).unwrap(),
+        )
+        .try_into()
+        .unwrap();
+        assert_eq!(payload, r#"2["ping",null]"#);
+    }
+    #[test]
+    fn test_with_ns_header() {
+        let data = json!({"data": "value™"});
+        let encoded: String = Packet::event("/".to_string(), "event".to_string(), data.clone())
+            .try_into()
+            .unwrap();
+        assert_eq!(Packet::with_ns_header(&encoded, "/"), encoded);
+        let expected: String = Packet::event("/admin™".to_string(), "event".to_string(), data)
+            .try_into()
+            .unwrap();
+        assert_eq!(Packet::with_ns_header(&encoded, "/admin™"), expected);
+
+        // The namespace comes after the attachment count of binary packets
+        let bin_event = |ns: &str| {
+            Packet::bin_event(ns.to_string(), "event".to_string(), json!(1), vec![vec![1]])
+        };
+        let encoded: String = bin_event("/").try_into().unwrap();
+        let expected: String = bin_event("/admin").try_into().unwrap();
+        assert_eq!(Packet::with_ns_header(&encoded, "/admin"), expected);
+    }
+    #[test]
+    fn test_transform_payload() {
+        struct Reverse;
+        impl PayloadTransform for Reverse {
+            fn encode(&self, data: &[u8]) -> Vec<u8> {
+                data.iter().rev().copied().collect()
+            }
+            fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+                Some(data.iter().rev().copied().collect())
+            }
+        }
+
+        let packet = Packet::event("/".to_string(), "msg".to_string(), json!("hello"))
+            .transform_payload(&Reverse)
+            .unwrap();
+        let (payload, bin) = packet.clone().encode().unwrap();
+        // The event name is kept in clear
+        assert_eq!(payload, r#"51-["msg",{"_placeholder":true,"num":0}]"#);
+        assert_eq!(bin, [br#"]"olleh"["#.to_vec()]);
+
+        // Receive the packet as it was sent
+        let mut received = Packet::try_from(payload).unwrap();
+        if let PacketData::BinaryEvent(_, ref mut received_bin, _) = received.inner {
+            bin.into_iter().for_each(|b| received_bin.add_payload(b));
+        }
+        assert_eq!(
+            received.inner.restore_payload(&Reverse),
+            Some(PacketData::Event("msg".to_string(), json!(["hello"]), None))
+        );
+
+        // The binary attachments are transformed too
+        let packet = Packet::bin_ack("/".to_string(), json!(1), vec![vec![1, 2]], 3)
+            .transform_payload(&Reverse)
+            .unwrap();
+        let (payload, bin) = packet.clone().encode().unwrap();
+        assert_eq!(
+            payload,
+            r#"62-3[{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#
+        );
+        assert_eq!(bin, [b"]1[".to_vec(), vec![2, 1]]);
+        match packet.inner.restore_payload(&Reverse) {
+            Some(PacketData::BinaryAck(bin, 3)) => {
+                assert_eq!(bin.data, json!([1]));
+                assert_eq!(bin.bin, [vec![1, 2]]);
+            }
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // Packets that were not transformed are rejected
+        let packet = PacketData::Event("msg".to_string(), json!(["hello"]), None);
+        assert_eq!(packet.restore_payload(&Reverse), None);
+    }
+
+    #[test]
+    fn test_binary_packet_with_ns() {
+        let packet = Packet::bin_event(
+            "/admin1".to_string(),
+            "event".to_string(),
+            json!({"data": 1}),
+            vec![vec![1, 2, 3]],
+        );
+        let payload: String = packet.clone().try_into().unwrap();
+        assert_eq!(
+            payload,
+            r#"51-/admin1,["event",{"data":1},{"_placeholder":true,"num":0}]"#
+        );
+        let decoded = Packet::try_from(payload).unwrap();
+        assert_eq!(decoded.ns, "/admin1");
+    }
+
+    #[test]
+    fn test_encode_binary_event() {
+        let bin = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        let packet = Packet::bin_event(
+            "/admin".to_string(),
+            "event".to_string(),
+            json!({"data": 1}),
+            bin.clone(),
+        );
+        let (payload, attachments) = packet.encode().unwrap();
+        assert_eq!(
+            payload,
+            r#"52-/admin,["event",{"data":1},{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#
+        );
+        assert_eq!(attachments, bin);
+
+        let (payload, attachments) = Packet::event("/".to_string(), "event".to_string(), json!(1))
+            .encode()
+            .unwrap();
+        assert_eq!(payload, r#"2["event",1]"#);
+        assert!(attachments.is_empty());
+    }
+
+    #[test]
+    fn test_encode_binary_ack() {
+        let bin = vec![vec![1, 2, 3]];
+        let packet = Packet::bin_ack("/admin".to_string(), json!({"data": 1}), bin.clone(), 12);
+        let (payload, attachments) = packet.encode().unwrap();
+        assert_eq!(
+            payload,
+            r#"61-/admin,12[{"data":1},{"_placeholder":true,"num":0}]"#
+        );
+        assert_eq!(attachments, bin);
+
+        let packet = Packet::bin_ack("/".to_string(), json!(["a", "b"]), bin, 3);
+        let payload: String = packet.try_into().unwrap();
+        assert_eq!(payload, r#"61-3["a","b",{"_placeholder":true,"num":0}]"#);
+    }
+
+    #[test]
+    fn test_decode_ack() {
+        // The ack id comes right after the optional namespace header
+        for (payload, ns) in [
+            (r#"3123["ok",1]"#, "/"),
+            (r#"3/admin,123["ok",1]"#, "/admin"),
+        ] {
+            let packet = Packet::try_from(payload.to_string()).unwrap();
+            assert_eq!(packet.ns, ns);
+            assert_eq!(packet.inner, PacketData::EventAck(json!(["ok", 1]), 123));
+        }
+        // An event may request an ack
+        let packet = Packet::try_from(r#"2/admin,7["event",1]"#.to_string()).unwrap();
+        assert_eq!(
+            packet.inner,
+            PacketData::Event("event".to_string(), json!([1]), Some(7))
+        );
+        // An ack without id can't be matched to an emitted event
+        assert!(Packet::try_from(r#"3["ok"]"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_decode_binary_event() {
+        let payload = r#"52-/admin,3["event",{"data":1},{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#;
+        let mut packet = Packet::try_from(payload.to_string()).unwrap();
+        assert_eq!(packet.ns, "/admin");
+        match packet.inner {
+            PacketData::BinaryEvent(ref event, ref mut bin, ack) => {
+                assert_eq!(event, "event");
+                assert_eq!(ack, Some(3));
+                // The placeholders are kept until the attachments are received
+                assert_eq!(
+                    bin.data,
+                    json!([{"data": 1}, {"_placeholder": true, "num": 0}, {"_placeholder": true, "num": 1}])
+                );
+                // The packet waits for the 2 announced attachments
+                bin.add_payload(vec![1]);
+                assert!(!bin.is_complete());
+                bin.add_payload(vec![2]);
+                assert!(bin.is_complete());
+            }
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // The attachment count is mandatory
+        for payload in [r#"5["event"]"#, r#"5-["event"]"#, r#"5x-["event"]"#] {
+            assert!(Packet::try_from(payload.to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_binary_ack() {
+        let payload = r#"62-/admin,12[{"data":1},{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#;
+        let mut packet = Packet::try_from(payload.to_string()).unwrap();
+        assert_eq!(packet.ns, "/admin");
+        match packet.inner {
+            PacketData::BinaryAck(ref mut bin, 12) => {
+                assert_eq!(
+                    bin.data,
+                    json!([{"data": 1}, {"_placeholder": true, "num": 0}, {"_placeholder": true, "num": 1}])
+                );
+                assert_eq!(bin.missing_payloads(), 2);
+                bin.add_payload(vec![1]);
+                assert_eq!(bin.missing_payloads(), 1);
+                bin.add_payload(vec![2]);
+                assert_eq!(bin.missing_payloads(), 0);
+                assert!(bin.is_complete());
+            }
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // The attachment count and the ack id are mandatory
+        for payload in [r#"6[1]"#, r#"61-[1]"#] {
+            assert!(Packet::try_from(payload.to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ack_non_array_payload() {
+        let expected = Packet {
+            inner: PacketData::EventAck(json!([{ "ok": true }]), 1),
+            ns: "/".to_string(),
+        };
+        for payload in [r#"31[{"ok":true}]"#, r#"31{"ok":true}"#] {
+            assert_eq!(Packet::try_from(payload.to_string()).unwrap(), expected);
+        }
+        let packet = Packet::try_from(r#"31"ok""#.to_string()).unwrap();
+        assert_eq!(packet.inner, PacketData::EventAck(json!(["ok"]), 1));
+    }
+
+    #[test]
+    fn test_connect_with_data() {
+        let sid: Sid = 1i64.into();
+        let data = json!({ "sid": "forged", "features": ["chat"] });
+        let Value::Object(data) = data else {
+            unreachable!()
+        };
+        let payload: String = Packet::connect_with_data("/admin".to_string(), sid, data)
+            .try_into()
+            .unwrap();
+        let expected = format!(r#"0/admin,{{"features":["chat"],"sid":"{}"}}"#, sid);
+        assert_eq!(payload, expected);
+
+        let payload: String = Packet::connect_with_data("/".to_string(), sid, Map::new())
+            .try_into()
+            .unwrap();
+        assert_eq!(payload, format!(r#"0{{"sid":"{}"}}"#, sid));
+    }
+
+    #[test]
+    fn test_decode_without_engineio_prefix() {
+        // The engine.io layer strips the `4` message prefix of 42["event",1]
+        let packet = Packet::try_from(r#"2["event",1]"#.to_string()).unwrap();
+        assert_eq!(
+            packet,
+            Packet::event("/".to_string(), "event".to_string(), json!([1]))
+        );
+
+        // If the prefix is not stripped, the packet is rejected rather than read as a connect error
+        assert!(matches!(
+            Packet::try_from(r#"42["event",1]"#.to_string()),
+            Err(Error::InvalidConnectError)
+        ));
+    }
+
+    #[test]
+    fn test_partial_packet() {
+        let payload = r#"52-["event",{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#;
+        let packet = Packet::try_from(payload.to_string()).unwrap();
+        let mut partial = PartialPacket::new(packet).unwrap();
+        assert!(partial.try_complete().is_none());
+        assert!(partial.push_binary(vec![1]).unwrap().is_none());
+        let packet = partial.push_binary(vec![2]).unwrap().unwrap();
+        match packet.inner {
+            PacketData::BinaryEvent(event, bin, None) => {
+                assert_eq!(event, "event");
+                // Each placeholder is replaced by the attachment it references
+                assert_eq!(bin.data, json!(["AQ==", "Ag=="]));
+                assert_eq!(bin.bin, vec![vec![1], vec![2]]);
+            }
+            _ => panic!("expected a binary event"),
+        }
+
+        // The placeholders are matched by their number, at any depth
+        let payload =
+            r#"52-["event",{"b":{"_placeholder":true,"num":1}},[{"_placeholder":true,"num":0}]]"#;
+        let packet = Packet::try_from(payload.to_string()).unwrap();
+        let mut partial = PartialPacket::new(packet).unwrap();
+        partial.push_binary(vec![1]).unwrap();
+        let packet = partial.push_binary(vec![2]).unwrap().unwrap();
+        match packet.inner {
+            PacketData::BinaryEvent(_, bin, None) => {
+                assert_eq!(bin.data, json!([{"b": "Ag=="}, ["AQ=="]]))
+            }
+            _ => panic!("expected a binary event"),
+        }
+        // More attachments than declared
+        assert!(matches!(
+            partial.push_binary(vec![3]),
+            Err(Error::InvalidAttachmentCount {
+                expected: 2,
+                got: 3
+            })
+        ));
+
+        // Interrupted by another packet before all the attachments are received
+        let packet = Packet::try_from(payload.to_string()).unwrap();
+        let mut partial = PartialPacket::new(packet).unwrap();
+        partial.push_binary(vec![1]).unwrap();
+        assert!(matches!(
+            partial.interrupted(),
+            Some(Error::InvalidAttachmentCount {
+                expected: 2,
+                got: 1
+            })
+        ));
+
+        let packet = Packet::try_from(r#"60-1["ok"]"#.to_string()).unwrap();
+        let mut partial = PartialPacket::new(packet).unwrap();
+        assert!(partial.try_complete().is_some());
+
+        let packet = Packet::event("/".to_string(), "event".to_string(), json!(1));
+        assert!(matches!(
+            PartialPacket::new(packet),
+            Err(Error::InvalidPacketType)
+        ));
+    }
+
+    #[test]
+    fn test_namespace_full() {
+        let payload: String = Packet::namespace_full("/chat".to_string())
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            payload,
+            r#"4/chat,{"message":"Namespace full","data":{"reason":"capacity"}}"#
+        );
+    }
+
+    #[test]
+    fn test_origin_not_allowed() {
+        let payload: String = Packet::origin_not_allowed("/".to_string())
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            payload,
+            r#"4{"message":"Origin not allowed","data":{"reason":"origin"}}"#
+        );
+    }
+
+    #[test]
+    fn test_connect_failed() {
+        let payload: String = Packet::connect_failed("/chat".to_string())
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            payload,
+            r#"4/chat,{"message":"Connection failed","data":{"reason":"server"}}"#
+        );
+    }
+
+    #[test]
+    fn test_decode_event_name_with_comma() {
+        // The namespace is parsed before the payload, so the first `,` of the payload isn't a separator
+        for (payload, ns, ack) in [
+            (r#"2["a,b",1]"#, "/", None),
+            (r#"2/ns,["a,b",1]"#, "/ns", None),
+            (r#"2/ns,12["a,b",1]"#, "/ns", Some(12)),
+        ] {
+            let packet = Packet::try_from(payload.to_string()).unwrap();
+            assert_eq!(packet.ns, ns);
+            match packet.inner {
+                PacketData::Event(event, _, id) => {
+                    assert_eq!(event, "a,b");
+                    assert_eq!(id, ack);
+                }
+                p => panic!("unexpected packet: {:?}", p),
+            }
+        }
+    }
+
+    fn value_strategy() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(Value::from),
+            ".*".prop_map(Value::String),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Value::Array),
+                prop::collection::btree_map("[a-z]{0,5}", inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    fn packet_strategy() -> impl Strategy<Value = Packet> {
+        let ns = prop_oneof![Just("/".to_string()), "/[a-zA-Z0-9_-]{1,10}"];
+        let object = prop::collection::btree_map("[a-z]{0,5}", value_strategy(), 0..4)
+            .prop_map(|map| Value::Object(map.into_iter().collect()));
+        // Event and ack data are always decoded as arrays
+        let array = || prop::collection::vec(value_strategy(), 0..4).prop_map(Value::Array);
+        let bin = || {
+            (
+                array(),
+                prop::collection::vec(prop::collection::vec(any::<u8>(), 0..8), 1..4),
+            )
+                .prop_map(|(data, bin)| BinaryPacket::outgoing(data, bin))
+        };
+        let ack = || 0..i64::MAX;
+        let inner = prop_oneof![
+            object.prop_map(PacketData::Connect),
+            Just(PacketData::Disconnect),
+            (".*", array(), proptest::option::of(ack()))
+                .prop_map(|(e, data, ack)| PacketData::Event(e, data, ack)),
+            (array(), ack()).prop_map(|(data, ack)| PacketData::EventAck(data, ack)),
+            ".*".prop_map(|message| PacketData::ConnectError(ConnectErrorPacket {
+                message,
+                data: None
+            })),
+            (".*", bin(), proptest::option::of(ack()))
+                .prop_map(|(e, bin, ack)| PacketData::BinaryEvent(e, bin, ack)),
+            (bin(), ack()).prop_map(|(bin, ack)| PacketData::BinaryAck(bin, ack)),
+        ];
+        (ns, inner).prop_map(|(ns, inner)| Packet { inner, ns })
+    }
+
+    /// The packet as it is seen by the receiving side:
+    /// the binary payloads are sent separately, the placeholders wait for them.
+    fn received(packet: &Packet) -> Packet {
+        let header = |bin: &BinaryPacket| BinaryPacket {
+            data: bin.data.clone(),
+            bin: vec![],
+            payload_count: bin.payload_count,
+        };
+        let inner = match &packet.inner {
+            PacketData::BinaryEvent(e, bin, ack) => {
+                PacketData::BinaryEvent(e.clone(), header(bin), *ack)
+            }
+            PacketData::BinaryAck(bin, ack) => PacketData::BinaryAck(header(bin), *ack),
+            inner => inner.clone(),
+        };
+        Packet {
+            inner,
+            ns: packet.ns.clone(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_packet_round_trip(packet in packet_strategy()) {
+            let expected = received(&packet);
+            let encoded: String = packet.try_into().unwrap();
+            let decoded = Packet::try_from(encoded.clone());
+            prop_assert!(decoded.is_ok(), "cannot decode {}: {:?}", encoded, decoded);
+            prop_assert_eq!(decoded.unwrap(), expected, "encoded: {}", encoded);
+        }
+    }
 }
@@ -4,7 +4,11 @@ use http_body::Body;
 use std::task::{Context, Poll};
 use tower::Service;
 
-use crate::{adapter::Adapter, client::Client, ns::NsHandlers, SocketIoConfig};
+use serde::Serialize;
+
+use crate::{
+    adapter::Adapter, client::Client, errors::BroadcastError, ns::NsHandlers, SocketIoConfig,
+};
 
 /// The service for Socket.IO
 ///
@@ -54,6 +58,29 @@ impl<A: Adapter, S: Clone> SocketIoService<A, S> {
         self.engine_svc.into_make_service()
     }
 
+    /// Broadcast an event to all the sockets of all the namespaces, and return the number of sockets reached.
+    ///
+    /// The event is serialized only once for all the namespaces.
+    /// It only reaches the sockets connected to this server, whatever the adapter is.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{Namespace, SocketIoService};
+    /// let ns = Namespace::builder()
+    ///     .add("/", |_| async move {})
+    ///     .add("/admin", |_| async move {})
+    ///     .build();
+    /// let svc = SocketIoService::new(ns);
+    /// let count = svc.broadcast_all("announcement", "The server is going down").unwrap();
+    /// assert_eq!(count, 0);
+    /// ```
+    pub fn broadcast_all(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<usize, BroadcastError> {
+        self.engine_svc.handler().broadcast_all(event, data)
+    }
+
     /// Create a new [`EngineIoService`] with a custom inner service.
     pub fn with_inner(inner: S, ns_handlers: NsHandlers<A>) -> Self {
         SocketIoService::with_config_inner(inner, ns_handlers, SocketIoConfig::default())
@@ -2,41 +2,178 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     sync::{
-        atomic::{AtomicI64, Ordering},
-        Arc, RwLock,
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, RwLock, Weak,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use engineioxide::{sid_generator::Sid, SendPacket as EnginePacket};
-use futures::Future;
+use engineioxide::{
+    service::TransportType,
+    sid_generator::{generate_sid, Sid},
+    socket::{FlushHandle, TransportHandle},
+    SendPacket as EnginePacket,
+};
+use futures::{future::BoxFuture, Future};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc::error::TrySendError, oneshot};
 
-use crate::errors::SendError;
+use crate::errors::{RetryerError, SendError};
 use crate::retryer::Retryer;
 use crate::{
     adapter::{Adapter, Room},
-    errors::{AckError, Error},
-    extensions::Extensions,
-    handler::{AckResponse, AckSender, BoxedHandler, MessageHandler},
+    errors::{AckError, AdapterError, BroadcastError, Error},
+    extensions::{Extensions, Ref},
+    handler::{shared_handler, AckResponse, AckSender, SharedHandler},
     handshake::Handshake,
     ns::Namespace,
+    observer::{hash_payload, PacketMeta, PacketType},
     operators::{Operators, RoomParam},
-    packet::{BinaryPacket, Packet, PacketData},
+    packet::{event_data, event_name, BinaryPacket, Packet, PacketData},
     SocketIoConfig,
 };
 
+/// All the possible reasons for a [`Socket`] to be disconnected from a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The underlying engine.io connection was closed
+    TransportClose,
+    /// The client disconnected the socket from the namespace (with `socket.disconnect()` on the client side)
+    ClientNSDisconnect,
+    /// The server disconnected the socket from the namespace (with [`Socket::disconnect`])
+    ServerNSDisconnect,
+    /// The server kicked the socket out of the namespace, for example when disconnecting all the sockets of a room
+    Kicked,
+}
+
+impl DisconnectReason {
+    /// The name of the reason used by the socket.io reference implementation, for example in the logs.
+    ///
+    /// The disconnect packet cannot carry a reason, so the clients disconnected by the server
+    /// always get `io server disconnect`. [`DisconnectReason::Kicked`] is also named `server namespace disconnect`,
+    /// the clients are told about it with a `disconnect_reason` event (see [`DisconnectReason::client_reason`]).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisconnectReason::TransportClose => "transport close",
+            DisconnectReason::ClientNSDisconnect => "client namespace disconnect",
+            DisconnectReason::ServerNSDisconnect | DisconnectReason::Kicked => {
+                "server namespace disconnect"
+            }
+        }
+    }
+
+    /// The reason sent to the client in a `disconnect_reason` event before the disconnect packet,
+    /// `None` if the disconnect packet is enough for the client to know why it was disconnected.
+    pub fn client_reason(&self) -> Option<&'static str> {
+        match self {
+            DisconnectReason::Kicked => Some("kicked"),
+            DisconnectReason::TransportClose
+            | DisconnectReason::ClientNSDisconnect
+            | DisconnectReason::ServerNSDisconnect => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The connection state of a [`Socket`], see [`Socket::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SocketState {
+    /// The socket is connected to the namespace
+    Connected,
+    /// The socket is disconnecting, its disconnecting handlers are running and it is still in its rooms
+    Disconnecting,
+    /// The socket left its rooms and was removed from the namespace
+    Disconnected,
+}
+
+impl SocketState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => SocketState::Connected,
+            1 => SocketState::Disconnecting,
+            _ => SocketState::Disconnected,
+        }
+    }
+}
+
+/// The outcome of a packet emitted with [`Socket::emit_with_outcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// The packet was handed to the transport
+    Sent,
+    /// The packet was dropped because the socket buffer was full
+    Dropped,
+    /// The packet could not be sent because the socket is closed
+    Failed,
+}
+
+/// Metadata about an acknowledgement that the server is still waiting for, see [`Socket::pending_acks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingAck {
+    /// The ack id sent with the packet
+    pub id: i64,
+    /// The time elapsed since the packet was sent
+    pub age: Duration,
+}
+
+/// Removes a pending acknowledgement from its socket when the future waiting for it is dropped,
+/// so an ack that is never awaited doesn't count towards `max_pending_acks`.
+struct AckGuard<'a, A: Adapter> {
+    socket: &'a Socket<A>,
+    ack: i64,
+}
+
+impl<A: Adapter> Drop for AckGuard<'_, A> {
+    fn drop(&mut self) {
+        self.socket.ack_message.write().unwrap().remove(&self.ack);
+    }
+}
+
+/// The largest sequence number, a javascript client reads the larger integers inexactly
+const MAX_SEQUENCE: u64 = (1 << 53) - 1;
+
+type DisconnectingCallback<A> = Box<
+    dyn Fn(Arc<Socket<A>>, DisconnectReason, Vec<Room>) -> BoxFuture<'static, ()>
+        + Send
+        + Sync
+        + 'static,
+>;
+type DisconnectCallback<A> =
+    Box<dyn Fn(Arc<Socket<A>>, DisconnectReason) -> BoxFuture<'static, ()> + Send + Sync + 'static>;
+/// The sender of an awaited acknowledgement with the time it was requested at
+type AwaitedAck = (oneshot::Sender<AckResponse<Value>>, Instant);
+
 /// A Socket represents a client connected to a namespace.
 /// It is used to send and receive messages from the client, join and leave rooms, etc.
 pub struct Socket<A: Adapter> {
-    config: Arc<SocketIoConfig>,
+    pub(crate) config: Arc<SocketIoConfig>,
     ns: Arc<Namespace<A>>,
-    message_handlers: RwLock<HashMap<String, BoxedHandler<A>>>,
-    ack_message: RwLock<HashMap<i64, oneshot::Sender<AckResponse<Value>>>>,
+    message_handlers: RwLock<HashMap<String, SharedHandler<A>>>,
+    disconnecting_handlers: RwLock<Vec<DisconnectingCallback<A>>>,
+    disconnect_handlers: RwLock<Vec<DisconnectCallback<A>>>,
+    ack_message: RwLock<HashMap<i64, AwaitedAck>>,
     ack_counter: AtomicI64,
+    /// The sequence number of the next event, if the namespace numbers the events
+    send_seq: AtomicU64,
+    state: AtomicU8,
+    /// Whether the session of a previous socket was restored, see [`Socket::recovered`]
+    recovered: AtomicBool,
+    /// The packets missed by the restored session, sent after the connect response
+    missed_packets: Mutex<Vec<Packet>>,
+    /// The private id of the socket, only sent to its client to recover its session.
+    /// Unlike the `sid` it is never shared with the other clients
+    pub(crate) pid: Sid,
+    external_id: RwLock<Option<String>>,
     tx: tokio::sync::mpsc::Sender<EnginePacket>,
+    flush: FlushHandle,
+    transport: TransportHandle,
     pub handshake: Handshake,
     pub sid: Sid,
     pub extensions: Extensions,
@@ -48,14 +185,34 @@ impl<A: Adapter> Socket<A> {
         ns: Arc<Namespace<A>>,
         handshake: Handshake,
         tx: tokio::sync::mpsc::Sender<EnginePacket>,
+        flush: FlushHandle,
+        transport: TransportHandle,
         config: Arc<SocketIoConfig>,
     ) -> Self {
+        // A reconnecting client reports the last sequence number it received,
+        // a number the client couldn't have received is ignored
+        let send_seq = handshake
+            .auth
+            .get("_seq")
+            .and_then(Value::as_u64)
+            .filter(|seq| *seq < MAX_SEQUENCE)
+            .map_or(0, |seq| seq + 1);
         Self {
             tx,
+            flush,
+            transport,
             ns,
             message_handlers: RwLock::new(HashMap::new()),
+            disconnecting_handlers: RwLock::new(Vec::new()),
+            disconnect_handlers: RwLock::new(Vec::new()),
             ack_message: RwLock::new(HashMap::new()),
             ack_counter: AtomicI64::new(0),
+            send_seq: AtomicU64::new(send_seq),
+            state: AtomicU8::new(SocketState::Connected as u8),
+            recovered: AtomicBool::new(false),
+            missed_packets: Mutex::new(Vec::new()),
+            pid: generate_sid(),
+            external_id: RwLock::new(None),
             handshake,
             sid,
             extensions: Extensions::new(),
@@ -74,6 +231,9 @@ impl<A: Adapter> Socket<A> {
     ///
     /// If the client sent a normal message without expecting an ack, the ack callback will do nothing.
     ///
+    /// A handler registered on the socket takes precedence over a namespace-wide handler
+    /// registered for the same event with [`Namespace::on`].
+    ///
     /// #### Simple example with a closure:
     /// ```
     /// # use socketioxide::Namespace;
@@ -121,14 +281,73 @@ impl<A: Adapter> Socket<A> {
         F: Future<Output = ()> + Send + 'static,
         V: DeserializeOwned + Send + Sync + 'static,
     {
-        let handler = Box::new(move |s, v, p, ack_fn| Box::pin(callback(s, v, p, ack_fn)) as _);
+        let event = self.normalize_event(event.into());
         self.message_handlers
             .write()
             .unwrap()
-            .insert(event.into(), MessageHandler::boxed(handler));
+            .insert(event, shared_handler(callback));
+    }
+
+    /// ### Register a disconnecting handler for this socket.
+    ///
+    /// It is called with the [`DisconnectReason`] and the rooms of the socket when it starts disconnecting,
+    /// **before** it leaves its rooms. It is useful to notify the rooms that the socket is leaving.
+    ///
+    /// Multiple handlers can be registered, they run one after the other in the registration order.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on_disconnecting(|socket, reason, rooms| async move {
+    ///         for room in rooms {
+    ///             socket.to(room).emit("user-left", socket.sid.to_string()).ok();
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn on_disconnecting<C, F>(&self, callback: C)
+    where
+        C: Fn(Arc<Socket<A>>, DisconnectReason, Vec<Room>) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Box::new(move |s, r, rooms| Box::pin(callback(s, r, rooms)) as _);
+        self.disconnecting_handlers.write().unwrap().push(handler);
+    }
+
+    /// ### Register a disconnect handler for this socket.
+    ///
+    /// It is called with the [`DisconnectReason`] when this specific socket disconnects from the namespace.
+    ///
+    /// The handlers run **after** the socket left its rooms and was removed from the namespace,
+    /// use [`Socket::on_disconnecting`] to access its rooms. The [`Socket::extensions`] are still available.
+    ///
+    /// Multiple handlers can be registered, they run one after the other in the registration order.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on_disconnect(|socket, reason| async move {
+    ///         println!("Socket {} disconnected: {:?}", socket.sid, reason);
+    ///     });
+    /// });
+    /// ```
+    pub fn on_disconnect<C, F>(&self, callback: C)
+    where
+        C: Fn(Arc<Socket<A>>, DisconnectReason) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Box::new(move |s, r| Box::pin(callback(s, r)) as _);
+        self.disconnect_handlers.write().unwrap().push(handler);
     }
 
     /// Emit a message to the client
+    ///
+    /// If the data can't be serialized (e.g. a map with non-string keys),
+    /// a `SendError::Serialize` error is returned right away and nothing is sent.
+    /// An empty event name, or a name reserved by the clients for their lifecycle
+    /// like `connect` or `disconnect`, is rejected with an [`Error::InvalidEventName`].
+    ///
+    /// Use `()` as data to emit an event without any argument.
     /// ##### Example
     /// ```
     /// # use socketioxide::Namespace;
@@ -137,12 +356,68 @@ impl<A: Adapter> Socket<A> {
     ///     socket.on("test", |socket, data: Value, bin, _| async move {
     ///         // Emit a test message to the client
     ///         socket.emit("test", data);
+    ///         // Emit a ping message without any argument
+    ///         socket.emit("ping", ());
     ///     });
     /// });
     pub fn emit(&self, event: impl Into<String>, data: impl Serialize) -> Result<(), SendError> {
         let ns = self.ns.path.clone();
-        let data = serde_json::to_value(data)?;
-        self.send(Packet::event(ns, event.into(), data))
+        let data = event_data(data)?;
+        self.send(Packet::event(ns, event_name(event)?, data))
+    }
+
+    /// Emit a message to the client without waiting, and post the [`SendOutcome`] to the given channel
+    /// once the transport handled the packet.
+    ///
+    /// It is useful to track the delivery status of the packets without blocking the sender.
+    ///
+    /// Contrary to [`Socket::emit`], a packet that cannot be sent because the buffer is full is dropped
+    /// and reported as [`SendOutcome::Dropped`].
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{Namespace, SendOutcome};
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    ///     tokio::spawn(async move {
+    ///         while let Some(outcome) = rx.recv().await {
+    ///             println!("Send outcome: {:?}", outcome);
+    ///         }
+    ///     });
+    ///     socket.on("test", move |socket, data: Value, _, _| {
+    ///         let tx = tx.clone();
+    ///         async move {
+    ///             socket.emit_with_outcome("test", data, tx).ok();
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn emit_with_outcome(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+        outcome_tx: tokio::sync::mpsc::Sender<SendOutcome>,
+    ) -> Result<(), SendError> {
+        let ns = self.ns.path.clone();
+        let data = event_data(data)?;
+        let res = match self.send(Packet::event(ns, event_name(event)?, data)) {
+            Ok(()) => Ok(()),
+            Err(SendError::RetryerError(RetryerError::Remaining(_))) => Err(SendOutcome::Dropped),
+            Err(e @ SendError::Serialize(_)) => return Err(e),
+            Err(_) => Err(SendOutcome::Failed),
+        };
+        let flush = self.flush.clone();
+        tokio::spawn(async move {
+            let outcome = match res {
+                Ok(()) => {
+                    flush.flush().await;
+                    SendOutcome::Sent
+                }
+                Err(outcome) => outcome,
+            };
+            outcome_tx.send(outcome).await.ok();
+        });
+        Ok(())
     }
 
     /// Emit a message to the client and wait for acknowledgement.
@@ -156,7 +431,7 @@ impl<A: Adapter> Socket<A> {
     ///     socket.on("test", |socket, data: Value, bin, _| async move {
     ///         // Emit a test message and wait for an acknowledgement
     ///         match socket.emit_with_ack::<Value>("test", data).await {
-    ///             Ok(ack) => println!("Ack received {:?}", ack),
+    ///             Ok(ack) => println!("Ack received {:?}", ack.data),
     ///             Err(err) => println!("Ack error {:?}", err),
     ///         }
     ///    });
@@ -170,12 +445,263 @@ impl<A: Adapter> Socket<A> {
         V: DeserializeOwned + Send + Sync + 'static,
     {
         let ns = self.ns.path.clone();
-        let data = serde_json::to_value(data)?;
-        let packet = Packet::event(ns, event.into(), data);
+        let data = event_data(data)?;
+        let packet = Packet::event(ns, event_name(event)?, data);
 
         self.send_with_ack(packet, None).await
     }
 
+    /// Emit a message to the client and wait for acknowledgement with the given timeout,
+    /// instead of the one of the config.
+    ///
+    /// The message is sent with a new ack id, the future resolves when the client acknowledges it,
+    /// or to an [`AckError::AckTimeoutError`] once the `timeout` elapsed.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use std::time::Duration;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         match socket.emit_with_ack_timeout::<Value>("test", data, Duration::from_secs(1)).await {
+    ///             Ok(ack) => println!("Ack received {:?}", ack.data),
+    ///             Err(err) => println!("Ack error {:?}", err),
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub async fn emit_with_ack_timeout<V>(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+        timeout: Duration,
+    ) -> Result<AckResponse<V>, AckError>
+    where
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        let ns = self.ns.path.clone();
+        let data = event_data(data)?;
+        let packet = Packet::event(ns, event_name(event)?, data);
+
+        self.send_with_ack(packet, Some(timeout)).await
+    }
+
+    /// Emit a message to the client and wait for acknowledgement with the given timeout,
+    /// resolving to the `default` value if the acknowledgement times out.
+    ///
+    /// The timeout is swallowed: the returned [`AckResponse`] holds the `default` data,
+    /// no binary payload and the id of this socket. Other errors are still returned.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use std::time::Duration;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         // Assume the client is not ready if it doesn't answer in time
+    ///         let ready = socket
+    ///             .emit_with_ack_or("ready?", data, Duration::from_secs(1), false)
+    ///             .await
+    ///             .map(|ack| ack.data)
+    ///             .unwrap_or(false);
+    ///         println!("client ready: {}", ready);
+    ///     });
+    /// });
+    /// ```
+    pub async fn emit_with_ack_or<V>(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+        timeout: Duration,
+        default: V,
+    ) -> Result<AckResponse<V>, AckError>
+    where
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        let ns = self.ns.path.clone();
+        let data = event_data(data)?;
+        let packet = Packet::event(ns, event_name(event)?, data);
+
+        match self.send_with_ack(packet, Some(timeout)).await {
+            Err(AckError::AckTimeoutError(_)) => Ok(AckResponse {
+                data: default,
+                binary: vec![],
+                sid: self.sid,
+                elapsed: timeout,
+            }),
+            res => res,
+        }
+    }
+
+    /// Emit several messages to the client in a row, so the transport sends them together:
+    /// in a single polling response, or in consecutive websocket frames flushed only once.
+    ///
+    /// The messages are serialized when they are emitted in the [`Batch`],
+    /// and sent when the closure returns or when [`Batch::flush`] is called.
+    /// They are sent in the order they were emitted in the batch, either all of them or none of them,
+    /// see [`Batch::flush`].
+    /// Messages emitted concurrently from other tasks to this socket may still be interleaved.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("sync", |socket, data: Value, _, _| async move {
+    ///         socket.batch(|batch| {
+    ///             batch.emit("users", &data).ok();
+    ///             batch.emit("rooms", &data).ok();
+    ///             batch.emit("synced", ()).ok();
+    ///         }).ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn batch<F>(&self, f: F) -> Result<(), Vec<SendError>>
+    where
+        F: FnOnce(&mut Batch<'_, A>),
+    {
+        let mut batch = Batch {
+            socket: self,
+            packets: Vec::new(),
+        };
+        f(&mut batch);
+        batch.flush()
+    }
+
+    /// Emit several distinct events to the client in one call, like [`Socket::batch`].
+    ///
+    /// All the events are encoded before any of them is sent, then they are written together
+    /// in the given order: either all of them are queued or, if the socket buffer is too small
+    /// or closed, none of them is. The outcome of each event is returned at the same index:
+    /// an event that cannot be serialized is skipped without preventing the other ones from being sent.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::{json, Value};
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     let results = socket.emit_many([
+    ///         ("users", json!(["alice", "bob"])),
+    ///         ("rooms", json!(["general"])),
+    ///         ("synced", Value::Null),
+    ///     ]);
+    ///     for (i, res) in results.into_iter().enumerate() {
+    ///         if let Err(e) = res {
+    ///             println!("event {} was not sent: {}", i, e);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn emit_many<E, T>(
+        &self,
+        events: impl IntoIterator<Item = (E, T)>,
+    ) -> Vec<Result<(), SendError>>
+    where
+        E: Into<String>,
+        T: Serialize,
+    {
+        let mut results = Vec::new();
+        let mut encoded = Vec::new();
+        for (event, data) in events {
+            let packet = event_name(event)
+                .map_err(SendError::from)
+                .and_then(|event| Ok((event, event_data(data)?)))
+                .map(|(event, data)| Packet::event(self.ns.path.clone(), event, data))
+                .and_then(|packet| self.encode(packet));
+            match packet {
+                Ok(packet) => {
+                    encoded.push(packet);
+                    results.push(Ok(()));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        if let Err(errors) = self.send_raw_many(encoded) {
+            // The unsent events are the encoded ones, in the same order
+            let mut errors = errors.into_iter();
+            for res in results.iter_mut().filter(|res| res.is_ok()) {
+                *res = Err(errors.next().unwrap());
+            }
+        }
+        results
+    }
+
+    /// Replay buffered packets to the client in order, for example after the socket reconnected.
+    ///
+    /// The offsets are absolute: the first packet of `packets` has the offset `base_offset`,
+    /// which is not 0 once the oldest packets of the buffer were trimmed, and the following ones
+    /// are numbered from there. If the client reports the offset of the last packet it received with
+    /// `from_offset`, only the following packets are sent, so the client gets neither duplicates nor gaps.
+    /// Otherwise the whole buffer is sent. If the client missed packets that were already trimmed,
+    /// the whole buffer is sent too, the trimmed packets cannot be recovered.
+    ///
+    /// The packets are sent on the namespace of this socket. If a packet cannot be sent, the remaining
+    /// ones are discarded and the error is returned. Returns the number of packets sent.
+    pub fn replay(
+        &self,
+        packets: Vec<Packet>,
+        base_offset: u64,
+        from_offset: Option<u64>,
+    ) -> Result<usize, SendError> {
+        let skip = match from_offset {
+            Some(offset) => offset
+                .saturating_add(1)
+                .saturating_sub(base_offset)
+                .try_into()
+                .unwrap_or(usize::MAX),
+            None => 0,
+        };
+        let mut count = 0;
+        for mut packet in packets.into_iter().skip(skip) {
+            packet.ns = self.ns.path.clone();
+            self.send(packet)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Emit a message to the client and return the ack id allocated for it,
+    /// along with a future waiting for the acknowledgement.
+    ///
+    /// The message is sent immediately, the ack id can be used to correlate logs with the acknowledgement,
+    /// it is the same id as in [`Socket::pending_acks`].
+    ///
+    /// The ack timeout starts when the message is sent, not when the future is first polled.
+    /// Dropping the future discards the pending acknowledgement.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         let (id, ack) = socket.emit_with_ack_id::<Value>("test", data).unwrap();
+    ///         println!("sent request #{}", id);
+    ///         match ack.await {
+    ///             Ok(ack) => println!("got ack #{}: {:?}", id, ack.data),
+    ///             Err(err) => println!("ack #{} error: {:?}", id, err),
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn emit_with_ack_id<V>(
+        &self,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<
+        (
+            i64,
+            impl Future<Output = Result<AckResponse<V>, AckError>> + '_,
+        ),
+        AckError,
+    >
+    where
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        let ns = self.ns.path.clone();
+        let data = event_data(data)?;
+        let packet = Packet::event(ns, event_name(event)?, data);
+
+        self.send_with_ack_id(packet, None)
+    }
+
     // Room actions
 
     /// Join the given rooms.
@@ -194,10 +720,62 @@ impl<A: Adapter> Socket<A> {
     }
 
     /// Get all rooms where the socket is connected.
+    ///
+    /// It is delegated to [`Adapter::socket_rooms`], the [`LocalAdapter`](crate::adapter::LocalAdapter)
+    /// keeps a reverse index of the rooms of each socket so it doesn't scan all the rooms.
+    /// The socket leaves its rooms before the [`Socket::on_disconnect`] handlers are called,
+    /// so the rooms should be read in the [`Socket::on_disconnecting`] handlers instead.
     pub fn rooms(&self) -> Result<Vec<Room>, A::Error> {
         self.ns.adapter.socket_rooms(self.sid)
     }
 
+    // External id
+
+    /// Bind the socket to an application level id, like a user id, replacing its previous one.
+    ///
+    /// Several sockets can share the same external id, for example a user connected from several tabs.
+    /// The socket is unbound when it disconnects, and can't be bound anymore once disconnected.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("login", |socket, user_id: String, _, _| async move {
+    ///         socket.set_external_id(user_id);
+    ///     });
+    ///     socket.on("dm", |socket, (user_id, msg): (String, Value), _, _| async move {
+    ///         // Reach all the sockets of the user
+    ///         socket.emit_to_external_id(&user_id, "dm", msg).ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn set_external_id(&self, id: impl Into<String>) {
+        // The guard is held while binding, so concurrent calls and the disconnection can't interleave
+        let mut external_id = self.external_id.write().unwrap();
+        if self.state() == SocketState::Disconnected {
+            return;
+        }
+        let id = id.into();
+        let previous = external_id.replace(id.clone());
+        self.ns.bind_external_id(self.sid, previous, id);
+    }
+
+    /// Get the external id of the socket set with [`Socket::set_external_id`].
+    pub fn external_id(&self) -> Option<String> {
+        self.external_id.read().unwrap().clone()
+    }
+
+    /// Emit a message to all the sockets of the namespace bound to the given external id,
+    /// see [`Namespace::emit_to_external_id`].
+    pub fn emit_to_external_id(
+        &self,
+        id: &str,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<(), BroadcastError> {
+        self.ns.emit_to_external_id(id, event, data)
+    }
+
     // Socket operators
 
     /// Select all clients in the given rooms except the current socket.
@@ -244,6 +822,25 @@ impl<A: Adapter> Socket<A> {
         Operators::new(self.ns.clone(), self.sid).within(rooms)
     }
 
+    /// Select all clients in the rooms matching the given glob pattern, including the current socket.
+    ///
+    /// In the pattern, `*` matches any sequence of characters and `?` matches a single character.
+    /// The pattern is matched against all the rooms of the namespace, so it is O(number of rooms).
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         // In all the game rooms like game:1 or game:2, but not in the lobby room
+    ///         socket.within_pattern("game:*").emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn within_pattern(&self, pattern: impl Into<String>) -> Operators<A> {
+        Operators::new(self.ns.clone(), self.sid).within_pattern(pattern)
+    }
+
     /// Filter out all clients selected with the previous operators which are in the given rooms.
     /// ##### Example
     /// ```
@@ -266,6 +863,23 @@ impl<A: Adapter> Socket<A> {
         Operators::new(self.ns.clone(), self.sid).except(rooms)
     }
 
+    /// Filter out all clients which are in the rooms matching the given glob pattern,
+    /// like [`Socket::within_pattern`].
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("test", |socket, data: Value, _, _| async move {
+    ///         // To all the clients except the current socket and the ones in the rooms like user:1:notifications
+    ///         socket.except_pattern("user:1:*").emit("test", data);
+    ///     });
+    /// });
+    /// ```
+    pub fn except_pattern(&self, pattern: impl Into<String>) -> Operators<A> {
+        Operators::new(self.ns.clone(), self.sid).except_pattern(pattern)
+    }
+
     /// Broadcast to all clients only connected on this node (when using multiple nodes).
     /// When using the default in-memory adapter, this operator is a no-op.
     /// ##### Example
@@ -300,7 +914,7 @@ impl<A: Adapter> Socket<A> {
     ///             .timeout(Duration::from_secs(5))
     ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
+    ///                    Ok(ack) => println!("Ack received from {}: {:?}", ack.sid, ack.data),
     ///                    Err(err) => println!("Ack error {:?}", err),
     ///                }
     ///             }).await;
@@ -311,6 +925,26 @@ impl<A: Adapter> Socket<A> {
         Operators::new(self.ns.clone(), self.sid).timeout(timeout)
     }
 
+    /// Drop the message if it is not handed to the transport before the given `ttl`.
+    ///
+    /// It is useful for realtime data that is worthless once stale, like positions or prices.
+    /// Messages with binary payloads are never dropped.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// # use std::time::Duration;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("position", |socket, data: Value, _, _| async move {
+    ///         // The position is dropped if it is still buffered after 100ms
+    ///         socket.to("room1").ttl(Duration::from_millis(100)).emit("position", data).ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn ttl(&self, ttl: Duration) -> Operators<A> {
+        Operators::new(self.ns.clone(), self.sid).ttl(ttl)
+    }
+
     /// Add a binary payload to the message.
     /// ##### Example
     /// ```
@@ -342,47 +976,515 @@ impl<A: Adapter> Socket<A> {
     }
 
     /// Disconnect the socket from the current namespace.
+    ///
+    /// If you need to ensure that a last message is sent before disconnecting, call [`Socket::flush`] first.
     pub fn disconnect(&self) -> Result<(), SendError> {
-        self.ns.disconnect(self.sid)
+        self.disconnect_with_reason(DisconnectReason::ServerNSDisconnect)
     }
 
-    /// Get the current namespace path.
-    pub fn ns(&self) -> &String {
-        &self.ns.path
+    /// Disconnect the socket from the current namespace,
+    /// the given reason is passed to the [`Socket::on_disconnect`] handlers.
+    pub(crate) fn disconnect_with_reason(&self, reason: DisconnectReason) -> Result<(), SendError> {
+        self.ns.disconnect(self.sid, reason)
     }
 
-    pub(crate) fn send(&self, mut packet: Packet) -> Result<(), SendError> {
-        let payload = match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) | PacketData::BinaryAck(ref mut bin, _) => {
-                std::mem::take(&mut bin.bin)
+    /// Get the transport currently used by the client.
+    ///
+    /// It may change from [`TransportType::Polling`] to [`TransportType::Websocket`] if the client upgrades the connection.
+    pub fn transport(&self) -> TransportType {
+        self.transport.transport()
+    }
+
+    /// Whether this socket restored the session of a previous socket of the same client,
+    /// if the namespace recovers the connection state.
+    ///
+    /// A recovered socket is back in the rooms of the previous socket
+    /// and received the packets broadcast to them while it was disconnected.
+    pub fn recovered(&self) -> bool {
+        self.recovered.load(Ordering::Relaxed)
+    }
+
+    /// Mark the socket as recovered and keep the missed packets until the connect response is sent.
+    pub(crate) fn set_recovered(&self, missed_packets: Vec<Packet>) {
+        self.recovered.store(true, Ordering::Relaxed);
+        *self.missed_packets.lock().unwrap() = missed_packets;
+    }
+
+    /// Send the packets missed by the restored session, it must be called after the connect response.
+    pub(crate) fn send_missed_packets(&self) -> Result<usize, SendError> {
+        let packets = std::mem::take(&mut *self.missed_packets.lock().unwrap());
+        self.replay(packets, 0, None)
+    }
+
+    /// Wait until all the packets queued for this socket have been written by the engine.io transport.
+    ///
+    /// ⚠️ It only guarantees that the packets were written to the OS socket buffer (or to a polling response),
+    /// **not** that the client received them. Use an acknowledgement if you need a proof of receipt.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::Namespace;
+    /// # use serde_json::Value;
+    /// Namespace::builder().add("/", |socket| async move {
+    ///     socket.on("quit", |socket, data: Value, _, _| async move {
+    ///         socket.emit("goodbye", data).ok();
+    ///         // Make sure the goodbye message is sent before disconnecting
+    ///         socket.flush().await;
+    ///         socket.disconnect().ok();
+    ///     });
+    /// });
+    /// ```
+    pub async fn flush(&self) {
+        self.flush.flush().await
+    }
+
+    /// Get the connection state of the socket.
+    pub fn state(&self) -> SocketState {
+        SocketState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    /// Get the string form of the socket id, as sent to the client in the connect packet.
+    ///
+    /// It is the `socket.id` seen by the client, so it can be used to cross-reference the client and server logs.
+    pub fn id(&self) -> String {
+        self.sid.to_string()
+    }
+
+    /// Get the sequence number of the next event sent to this socket.
+    ///
+    /// The events are only numbered if it is enabled with `NamespaceBuilder::sequence_numbers`.
+    pub fn next_sequence(&self) -> u64 {
+        self.send_seq.load(Ordering::SeqCst)
+    }
+
+    /// Get the current namespace path.
+    pub fn ns(&self) -> &String {
+        &self.ns.path
+    }
+
+    /// Get the acknowledgements that this socket is still waiting for, ordered by id.
+    ///
+    /// It only exposes metadata, it is useful to find clients that never respond to acks.
+    pub fn pending_acks(&self) -> Vec<PendingAck> {
+        let mut acks: Vec<_> = self
+            .ack_message
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, (_, sent_at))| PendingAck {
+                id: *id,
+                age: sent_at.elapsed(),
+            })
+            .collect();
+        acks.sort_unstable_by_key(|ack| ack.id);
+        acks
+    }
+
+    /// Get a read-only [`SocketView`] of this socket.
+    ///
+    /// It can be handed to untrusted code that should only observe the socket state
+    /// without being able to emit, join rooms or disconnect it.
+    pub fn as_view(&self) -> SocketView<'_, A> {
+        SocketView { socket: self }
+    }
+
+    /// Get a [`WeakSocket`] handle to this socket.
+    ///
+    /// Unlike an `Arc<Socket>`, it doesn't keep the socket alive,
+    /// so it can be stored in application state (e.g. a user → socket map) without leaking disconnected sockets.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{Namespace, WeakSocket, adapter::LocalAdapter};
+    /// # use std::sync::{Arc, Mutex};
+    /// let registry: Arc<Mutex<Vec<WeakSocket<LocalAdapter>>>> = Default::default();
+    /// Namespace::builder().add("/", move |socket| {
+    ///     let registry = registry.clone();
+    ///     async move {
+    ///         registry.lock().unwrap().push(socket.downgrade());
+    ///         // Only the sockets that are still connected are reached
+    ///         for socket in registry.lock().unwrap().iter().filter_map(|s| s.upgrade()) {
+    ///             socket.emit("joined", socket.sid.to_string()).ok();
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn downgrade(self: &Arc<Self>) -> WeakSocket<A> {
+        WeakSocket {
+            sid: self.sid,
+            socket: Arc::downgrade(self),
+        }
+    }
+
+    /// Run the disconnecting handlers, remove the socket from all its rooms and from the namespace
+    /// and then run the disconnect handlers.
+    ///
+    /// If there is no handler, the socket is removed immediately.
+    /// Otherwise it is done in a separate task, until the socket left its rooms
+    /// it is in the [`SocketState::Disconnecting`] state.
+    ///
+    /// It does nothing and returns `false` if the socket is already disconnecting.
+    pub(crate) fn close(self: Arc<Self>, reason: DisconnectReason) -> Result<bool, AdapterError> {
+        let connected = SocketState::Connected as u8;
+        let disconnecting = SocketState::Disconnecting as u8;
+        if self
+            .state
+            .compare_exchange(connected, disconnecting, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Ok(false);
+        }
+
+        // Drop the pending acks, the waiting emitters will get a `Disconnected` error
+        let grace = self.config.ack_disconnect_grace;
+        if grace.is_zero() {
+            self.ack_message.write().unwrap().clear();
+        } else {
+            // Until then, the socket recovering the session takes the pending acks over
+            if self.persists_session(reason) {
+                self.ns.keep_closed_socket(self.pid, Arc::downgrade(&self));
             }
-            _ => vec![],
+            let socket = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(grace).await;
+                socket.ack_message.write().unwrap().clear();
+            });
+        }
+        let disconnecting_handlers =
+            std::mem::take(&mut *self.disconnecting_handlers.write().unwrap());
+        let handlers = std::mem::take(&mut *self.disconnect_handlers.write().unwrap());
+        if disconnecting_handlers.is_empty() && handlers.is_empty() {
+            let res = self.leave_rooms(reason);
+            self.set_disconnected();
+            return res.map(|_| true).map_err(|err| AdapterError(Box::new(err)));
+        }
+
+        tokio::spawn(async move {
+            if !disconnecting_handlers.is_empty() {
+                let rooms = self.rooms().unwrap_or_else(|e| {
+                    tracing::error!("[sid={}] adapter error when getting rooms: {}", self.sid, e);
+                    vec![]
+                });
+                for handler in disconnecting_handlers {
+                    handler(self.clone(), reason, rooms.clone()).await;
+                }
+            }
+            if let Err(e) = self.leave_rooms(reason) {
+                tracing::error!("[sid={}] adapter error when leaving rooms: {}", self.sid, e);
+            }
+            self.set_disconnected();
+            for handler in handlers {
+                handler(self.clone(), reason).await;
+            }
+        });
+        Ok(true)
+    }
+
+    /// Leave all the rooms when closing the socket.
+    ///
+    /// If the transport was closed and the namespace recovers the connection state,
+    /// the session is persisted first so the client can recover it when reconnecting.
+    fn leave_rooms(&self, reason: DisconnectReason) -> Result<(), A::Error> {
+        if self.persists_session(reason) {
+            self.ns.adapter.persist_session(self.sid, self.pid)?;
+        }
+        self.ns.adapter.del_all(self.sid)
+    }
+
+    /// Whether the session is persisted when the socket is closed for the given reason
+    fn persists_session(&self, reason: DisconnectReason) -> bool {
+        reason == DisconnectReason::TransportClose && self.ns.config.recovery_ttl.is_some()
+    }
+
+    /// Take the pending acks of the socket whose session was recovered by this one over,
+    /// so the acknowledgements that the client sends after reconnecting resolve them.
+    pub(crate) fn take_pending_acks(&self, previous: &Socket<A>) {
+        let acks = std::mem::take(&mut *previous.ack_message.write().unwrap());
+        // The client acknowledges with the ids of the previous socket, the new ids must not collide with them
+        if let Some(max) = acks.keys().max() {
+            self.ack_counter.fetch_max(*max, Ordering::SeqCst);
+        }
+        self.ack_message
+            .write()
+            .unwrap()
+            .extend(acks.into_iter().filter(|(_, (tx, _))| !tx.is_closed()));
+    }
+
+    fn set_disconnected(&self) {
+        // Held until the state is stored, so `set_external_id` can't bind the socket in between
+        let mut external_id = self.external_id.write().unwrap();
+        if let Some(id) = external_id.take() {
+            self.ns.unbind_external_id(self.sid, &id);
+        }
+        self.ns.forget_socket(self.sid);
+        self.state
+            .store(SocketState::Disconnected as u8, Ordering::SeqCst);
+    }
+
+    pub(crate) fn send(&self, packet: Packet) -> Result<(), SendError> {
+        self.send_with_deadline(packet, None)
+    }
+
+    /// Send a packet, dropping it if it is not handed to the transport before the `deadline`
+    pub(crate) fn send_with_deadline(
+        &self,
+        packet: Packet,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendError> {
+        let (msg, payload) = self.encode(packet)?;
+        self.send_raw(msg, payload, deadline)
+    }
+
+    /// Rewrite a packet for this socket and serialize it along with its binary payloads
+    fn encode(&self, packet: Packet) -> Result<(String, Vec<Vec<u8>>), SendError> {
+        let mut packet = self.ns.intercept(self, packet);
+        if self.ns.config.sequence_numbers && packet.inner.is_event() {
+            // The numbering stops at the largest number that the clients can read exactly
+            let seq = self
+                .send_seq
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |seq| {
+                    Some(seq.saturating_add(1).min(MAX_SEQUENCE))
+                })
+                .unwrap();
+            packet.inner.push_event_arg(seq.into());
+        }
+        let packet = match &self.config.payload_transform {
+            Some(transform) => packet.transform_payload(transform.as_ref())?,
+            None => packet,
+        };
+        let packet = self.inline_binary(packet);
+        // Binary attachments are sent as separate engine.io binary packets.
+        // The engine encodes them in base64 if they are handed to a polling transport.
+        Ok(packet.encode()?)
+    }
+
+    /// Inline the binary attachments of a packet in base64 strings if the namespace is configured so
+    pub(crate) fn inline_binary(&self, packet: Packet) -> Packet {
+        if self.ns.config.inline_binary {
+            packet.inline_binary()
+        } else {
+            packet
+        }
+    }
+
+    /// Whether the packets sent to this socket are rewritten for it, with its own sequence numbers
+    /// or by the outgoing interceptor of the namespace, in which case a broadcast packet cannot be
+    /// serialized once for all the sockets
+    pub(crate) fn needs_own_packet(&self) -> bool {
+        self.ns.config.sequence_numbers || self.ns.intercepts_outgoing()
+    }
+
+    /// Send an already serialized packet along with its binary payloads
+    ///
+    /// If a `deadline` is given, the packet is dropped if it is not handed to the transport before it.
+    /// Packets with binary payloads are never dropped because the client would receive
+    /// orphan attachments or wait for missing ones.
+    pub(crate) fn send_raw(
+        &self,
+        msg: String,
+        payload: Vec<Vec<u8>>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendError> {
+        self.observe_encoded(&msg);
+        self.send_message(EnginePacket::Message(msg), payload, deadline)
+    }
+
+    /// Send an already serialized packet shared with other sockets, like a broadcast packet,
+    /// along with its binary payloads. The message is only copied when it is written by the transport.
+    ///
+    /// See [`Socket::send_raw`] for the `deadline`.
+    pub(crate) fn send_shared(
+        &self,
+        msg: &Arc<str>,
+        payload: Vec<Vec<u8>>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendError> {
+        self.observe_encoded(msg);
+        self.send_message(EnginePacket::SharedMessage(msg.clone()), payload, deadline)
+    }
+
+    fn send_message(
+        &self,
+        msg: EnginePacket,
+        payload: Vec<Vec<u8>>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendError> {
+        let packet = match deadline {
+            Some(deadline) if payload.is_empty() => EnginePacket::Expiring(Box::new(msg), deadline),
+            _ => msg,
         };
-        let packet = packet.try_into()?;
         Retryer::new(self.sid, self.tx.clone(), Some(packet), payload.into()).retry()?;
 
         Ok(())
     }
 
+    /// Send already serialized packets together along with their binary payloads.
+    ///
+    /// The buffer capacity for all of them is reserved before any of them is written,
+    /// so either all of them are queued in order, or none of them is and each packet
+    /// is returned in its own error, in the same order.
+    pub(crate) fn send_raw_many(
+        &self,
+        packets: Vec<(String, Vec<Vec<u8>>)>,
+    ) -> Result<(), Vec<SendError>> {
+        for (msg, _) in &packets {
+            self.observe_encoded(msg);
+        }
+        let frames = packets.iter().map(|(_, payload)| 1 + payload.len()).sum();
+        let mut permits = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            match self.tx.try_reserve() {
+                Ok(permit) => permits.push(permit),
+                Err(e) => {
+                    // The reserved capacity is released before the packets are handed back
+                    drop(permits);
+                    let closed = matches!(e, TrySendError::Closed(_));
+                    let errors = packets.into_iter().map(|(msg, payload)| {
+                        let err = if closed {
+                            RetryerError::SocketClosed { sid: self.sid }
+                        } else {
+                            let packet = Some(EnginePacket::Message(msg));
+                            let retryer =
+                                Retryer::new(self.sid, self.tx.clone(), packet, payload.into());
+                            RetryerError::Remaining(retryer)
+                        };
+                        err.into()
+                    });
+                    return Err(errors.collect());
+                }
+            }
+        }
+        let frames = packets.into_iter().flat_map(|(msg, payload)| {
+            std::iter::once(EnginePacket::Message(msg))
+                .chain(payload.into_iter().map(EnginePacket::Binary))
+        });
+        for (permit, frame) in permits.into_iter().zip(frames) {
+            permit.send(frame);
+        }
+        Ok(())
+    }
+
+    /// Report an encoded packet to the packet observer of the config, if any
+    fn observe_encoded(&self, msg: &str) {
+        if let Some(observer) = &self.config.packet_observer {
+            observer.on_encode(&PacketMeta {
+                ns: &self.ns.path,
+                sid: self.sid,
+                packet_type: PacketType::from_encoded(msg).unwrap_or(PacketType::Event),
+                payload_hash: hash_payload(msg),
+            });
+        }
+    }
+
     pub(crate) async fn send_with_ack<V: DeserializeOwned>(
         &self,
-        mut packet: Packet,
+        packet: Packet,
         timeout: Option<Duration>,
     ) -> Result<AckResponse<V>, AckError> {
+        let (_, ack) = self.send_with_ack_id(packet, timeout)?;
+        ack.await
+    }
+
+    /// Send the packet with a new ack id and return this id along with a future waiting for the ack
+    pub(crate) fn send_with_ack_id<V: DeserializeOwned>(
+        &self,
+        packet: Packet,
+        timeout: Option<Duration>,
+    ) -> Result<
+        (
+            i64,
+            impl Future<Output = Result<AckResponse<V>, AckError>> + '_,
+        ),
+        AckError,
+    > {
+        let deadline = tokio::time::Instant::now() + timeout.unwrap_or(self.config.ack_timeout);
+        self.send_with_ack_until(packet, deadline)
+    }
+
+    /// Like [`Socket::send_with_ack_id`], but the ack times out at the given `deadline`,
+    /// so several sockets can share the same deadline.
+    pub(crate) fn send_with_ack_until<V: DeserializeOwned>(
+        &self,
+        mut packet: Packet,
+        deadline: tokio::time::Instant,
+    ) -> Result<
+        (
+            i64,
+            impl Future<Output = Result<AckResponse<V>, AckError>> + '_,
+        ),
+        AckError,
+    > {
         let (tx, rx) = oneshot::channel();
         let ack = self.ack_counter.fetch_add(1, Ordering::SeqCst) + 1;
-        self.ack_message.write().unwrap().insert(ack, tx);
+        {
+            let mut acks = self.ack_message.write().unwrap();
+            if acks.len() >= self.ns.config.max_pending_acks {
+                return Err(Error::TooManyPendingAcks(self.sid).into());
+            }
+            // The round-trip time is measured from here, right before the packet is handed to the transport,
+            // the ack must be registered before the packet is sent so a fast ack is not missed
+            acks.insert(ack, (tx, Instant::now()));
+        }
         packet.inner.set_ack_id(ack);
-        self.send(packet)?;
-        let timeout = timeout.unwrap_or(self.config.ack_timeout);
-        let v = tokio::time::timeout(timeout, rx).await??;
-        Ok((serde_json::from_value(v.0)?, v.1))
+        // The event name is only kept if the client must be notified of a timeout
+        let event = match (&packet.inner, &self.ns.config.ack_timeout_event) {
+            (PacketData::Event(e, _, _) | PacketData::BinaryEvent(e, _, _), Some(_)) => {
+                Some(e.clone())
+            }
+            _ => None,
+        };
+        if let Err(e) = self.send(packet) {
+            self.ack_message.write().unwrap().remove(&ack);
+            return Err(e.into());
+        }
+        // The guard is moved into the future so the ack is discarded even if it is never polled
+        let guard = AckGuard { socket: self, ack };
+        let fut = async move {
+            // Once the future completes or is dropped, the ack doesn't count as pending anymore
+            let _guard = guard;
+            let v = match tokio::time::timeout_at(deadline, rx).await {
+                // The sender is only dropped when the socket disconnects
+                Ok(v) => v.map_err(|_| AckError::Disconnected)?,
+                Err(e) => {
+                    if let (Some(notification), Some(event)) =
+                        (&self.ns.config.ack_timeout_event, event)
+                    {
+                        // A plain emit never requests an ack, so the notification can't time out in turn
+                        self.emit(notification.clone(), event).ok();
+                    }
+                    return Err(e.into());
+                }
+            };
+            Ok(AckResponse {
+                data: serde_json::from_value(v.data)?,
+                binary: v.binary,
+                sid: v.sid,
+                elapsed: v.elapsed,
+            })
+        };
+        Ok((ack, fut))
     }
 
     // Receive data from client:
 
     pub(crate) fn recv(self: Arc<Self>, packet: PacketData) -> Result<(), Error> {
+        let packet = match &self.config.payload_transform {
+            Some(transform) => match packet.restore_payload(transform.as_ref()) {
+                Some(packet) => packet,
+                None => {
+                    tracing::debug!("[sid={}] dropping untransformed packet", self.sid);
+                    return Ok(());
+                }
+            },
+            None => packet,
+        };
         match packet {
+            PacketData::BinaryEvent(_, _, _) | PacketData::BinaryAck(_, _)
+                if !self.ns.config.allow_binary =>
+            {
+                tracing::debug!("[sid={}] dropping forbidden binary packet", self.sid);
+                self.emit("error", "binary packets are not allowed in this namespace")
+                    .ok();
+                Ok(())
+            }
             PacketData::Event(e, data, ack) => self.recv_event(e, data, ack),
             PacketData::EventAck(data, ack_id) => self.recv_ack(data, ack_id),
             PacketData::BinaryEvent(e, packet, ack) => self.recv_bin_event(e, packet, ack),
@@ -392,8 +1494,12 @@ impl<A: Adapter> Socket<A> {
     }
 
     fn recv_event(self: Arc<Self>, e: String, data: Value, ack: Option<i64>) -> Result<(), Error> {
-        if let Some(handler) = self.message_handlers.read().unwrap().get(&e) {
-            handler.call(self.clone(), data, vec![], ack)?;
+        let e = self.normalize_event(e);
+        // The lock is released before calling the handler so that it can freely access the socket
+        let handler = self.message_handlers.read().unwrap().get(&e).cloned();
+        if let Some(handler) = handler.or_else(|| self.ns.get_handler(&e)) {
+            let fut = handler.call(self.clone(), data, vec![], ack)?;
+            self.ns.spawn_handler(fut);
         }
         Ok(())
     }
@@ -404,27 +1510,182 @@ impl<A: Adapter> Socket<A> {
         packet: BinaryPacket,
         ack: Option<i64>,
     ) -> Result<(), Error> {
-        if let Some(handler) = self.message_handlers.read().unwrap().get(&e) {
-            handler.call(self.clone(), packet.data, packet.bin, ack)?;
+        let e = self.normalize_event(e);
+        let handler = self.message_handlers.read().unwrap().get(&e).cloned();
+        if let Some(handler) = handler.or_else(|| self.ns.get_handler(&e)) {
+            let fut = handler.call(self.clone(), packet.data, packet.bin, ack)?;
+            self.ns.spawn_handler(fut);
         }
         Ok(())
     }
 
+    /// Apply the event name normalizer of the namespace, if there is one
+    fn normalize_event(&self, event: String) -> String {
+        self.ns.normalize_event(event)
+    }
+
     fn recv_ack(self: Arc<Self>, data: Value, ack: i64) -> Result<(), Error> {
-        if let Some(tx) = self.ack_message.write().unwrap().remove(&ack) {
-            tx.send((data, vec![])).ok();
+        if let Some((tx, sent_at)) = self.ack_message.write().unwrap().remove(&ack) {
+            tx.send(AckResponse {
+                data,
+                binary: vec![],
+                sid: self.sid,
+                elapsed: sent_at.elapsed(),
+            })
+            .ok();
         }
         Ok(())
     }
 
     fn recv_bin_ack(self: Arc<Self>, packet: BinaryPacket, ack: i64) -> Result<(), Error> {
-        if let Some(tx) = self.ack_message.write().unwrap().remove(&ack) {
-            tx.send((packet.data, packet.bin)).ok();
+        if let Some((tx, sent_at)) = self.ack_message.write().unwrap().remove(&ack) {
+            tx.send(AckResponse {
+                data: packet.data,
+                binary: packet.bin,
+                sid: self.sid,
+                elapsed: sent_at.elapsed(),
+            })
+            .ok();
         }
         Ok(())
     }
 }
 
+/// A read-only view over a [`Socket`], obtained with [`Socket::as_view`].
+///
+/// It only exposes read accessors, so it is enforced at the type level that the holder
+/// cannot mutate the connection state.
+pub struct SocketView<'a, A: Adapter> {
+    socket: &'a Socket<A>,
+}
+
+impl<'a, A: Adapter> SocketView<'a, A> {
+    /// Get the socket id.
+    pub fn sid(&self) -> Sid {
+        self.socket.sid
+    }
+
+    /// Get the current namespace path.
+    pub fn ns(&self) -> &'a str {
+        &self.socket.ns.path
+    }
+
+    /// Get all rooms where the socket is connected.
+    pub fn rooms(&self) -> Result<Vec<Room>, A::Error> {
+        self.socket.rooms()
+    }
+
+    /// Get the handshake of the socket.
+    pub fn handshake(&self) -> &'a Handshake {
+        &self.socket.handshake
+    }
+
+    /// Get a reference to a type previously inserted in the socket extensions.
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<Ref<'a, T>> {
+        self.socket.extensions.get::<T>()
+    }
+}
+
+impl<'a, A: Adapter> Clone for SocketView<'a, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<'a, A: Adapter> Copy for SocketView<'a, A> {}
+
+impl<'a, A: Adapter> Debug for SocketView<'a, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketView")
+            .field("ns", &self.ns())
+            .field("sid", &self.sid())
+            .finish()
+    }
+}
+
+/// A weak handle to a [`Socket`], obtained with [`Socket::downgrade`].
+///
+/// It mirrors [`std::sync::Weak`]: it doesn't keep the socket alive
+/// and must be upgraded to be used.
+pub struct WeakSocket<A: Adapter> {
+    sid: Sid,
+    socket: Weak<Socket<A>>,
+}
+
+impl<A: Adapter> WeakSocket<A> {
+    /// Get the id of the socket, even if it is disconnected.
+    pub fn sid(&self) -> Sid {
+        self.sid
+    }
+
+    /// Get the socket if it is still connected.
+    ///
+    /// It returns `None` once the socket is disconnected, even if it is still referenced elsewhere.
+    pub fn upgrade(&self) -> Option<Arc<Socket<A>>> {
+        self.socket
+            .upgrade()
+            .filter(|socket| socket.state() != SocketState::Disconnected)
+    }
+}
+
+impl<A: Adapter> Clone for WeakSocket<A> {
+    fn clone(&self) -> Self {
+        Self {
+            sid: self.sid,
+            socket: self.socket.clone(),
+        }
+    }
+}
+
+impl<A: Adapter> Debug for WeakSocket<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WeakSocket")
+            .field("sid", &self.sid)
+            .finish()
+    }
+}
+
+/// A batch of messages emitted together to a socket, see [`Socket::batch`].
+///
+/// The remaining messages are sent when the batch is dropped, the errors are then ignored.
+pub struct Batch<'a, A: Adapter> {
+    socket: &'a Socket<A>,
+    packets: Vec<(String, Vec<Vec<u8>>)>,
+}
+
+impl<'a, A: Adapter> Batch<'a, A> {
+    /// Add a message to the batch, it is sent when the batch is flushed.
+    pub fn emit(
+        &mut self,
+        event: impl Into<String>,
+        data: impl Serialize,
+    ) -> Result<(), SendError> {
+        let ns = self.socket.ns.path.clone();
+        let data = event_data(data)?;
+        let packet = Packet::event(ns, event_name(event)?, data);
+        self.packets.push(self.socket.encode(packet)?);
+        Ok(())
+    }
+
+    /// Send the messages of the batch, in the order they were emitted.
+    ///
+    /// Either all of them are queued, or, if the socket buffer is too small or closed, none of them is
+    /// and each message is returned in its own error, in the same order. The messages can then
+    /// be sent again with their [`Retryer`](crate::retryer::Retryer).
+    pub fn flush(&mut self) -> Result<(), Vec<SendError>> {
+        let packets = std::mem::take(&mut self.packets);
+        if packets.is_empty() {
+            return Ok(());
+        }
+        self.socket.send_raw_many(packets)
+    }
+}
+
+impl<'a, A: Adapter> Drop for Batch<'a, A> {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
 impl<A: Adapter> Debug for Socket<A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Socket")
@@ -451,6 +1712,8 @@ impl<A: Adapter> Socket<A> {
             ns,
             Handshake::new_dummy(),
             tx,
+            FlushHandle::default(),
+            TransportHandle::default(),
             Arc::new(SocketIoConfig::default()),
         )
     }
@@ -459,25 +1722,52 @@ impl<A: Adapter> Socket<A> {
 #[cfg(test)]
 mod tests {
     use crate::adapter::{Adapter, LocalAdapter};
-    use crate::errors::{RetryerError, SendError};
+    use crate::errors::{BroadcastError, RetryerError, SendError};
     use crate::handshake::Handshake;
-    use crate::{Namespace, Socket, SocketIoConfig};
+    use crate::ns::NsConfig;
+    use crate::packet::{BinaryPacket, Packet, PacketData};
+    use crate::socket::{DisconnectReason, SendOutcome, SocketState};
+    use crate::testing::connect_socket;
+    use crate::transform::PayloadTransform;
+    use crate::{AckError, Namespace, Socket, SocketError, SocketIoConfig};
     use engineioxide::sid_generator::Sid;
+    use engineioxide::socket::{FlushHandle, TransportHandle};
     use engineioxide::SendPacket;
     use futures::FutureExt;
+    use serde_json::{json, Value};
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
     use tokio::sync::mpsc::Receiver;
 
     impl<A: Adapter> Socket<A> {
         pub fn new_rx_dummy(sid: Sid, ns: Arc<Namespace<A>>) -> (Socket<A>, Receiver<SendPacket>) {
-            let (tx, rx) = tokio::sync::mpsc::channel(1);
+            Socket::new_rx_dummy_with(
+                sid,
+                ns,
+                Handshake::new_dummy(),
+                SocketIoConfig::default(),
+                1,
+            )
+        }
+
+        /// Like [`Socket::new_rx_dummy`] with the given handshake, config and buffer capacity
+        pub fn new_rx_dummy_with(
+            sid: Sid,
+            ns: Arc<Namespace<A>>,
+            handshake: Handshake,
+            config: SocketIoConfig,
+            capacity: usize,
+        ) -> (Socket<A>, Receiver<SendPacket>) {
+            let (tx, rx) = tokio::sync::mpsc::channel(capacity);
             (
                 Socket::new(
                     sid,
                     ns,
-                    Handshake::new_dummy(),
+                    handshake,
                     tx,
-                    Arc::new(SocketIoConfig::default()),
+                    FlushHandle::default(),
+                    TransportHandle::default(),
+                    Arc::new(config),
                 ),
                 rx,
             )
@@ -504,4 +1794,877 @@ mod tests {
         };
         retryer.retry().unwrap();
     }
+    #[test]
+    fn test_disconnect_reason_names() {
+        assert_eq!(
+            DisconnectReason::TransportClose.to_string(),
+            "transport close"
+        );
+        assert_eq!(
+            DisconnectReason::ClientNSDisconnect.as_str(),
+            "client namespace disconnect"
+        );
+        assert_eq!(
+            DisconnectReason::Kicked.as_str(),
+            DisconnectReason::ServerNSDisconnect.as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_on_disconnect_handlers() {
+        let sid: Sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        socket.join("room1").unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        for i in 0..2 {
+            let tx = tx.clone();
+            socket.on_disconnect(move |socket, reason| {
+                let tx = tx.clone();
+                async move {
+                    tx.send((i, reason, socket.rooms().unwrap())).await.unwrap();
+                }
+            });
+        }
+        for i in 0..2 {
+            let tx = tx.clone();
+            socket.on_disconnecting(move |socket, reason, rooms| {
+                let tx = tx.clone();
+                async move {
+                    // The rooms are still intact
+                    assert_eq!(socket.rooms().unwrap(), rooms);
+                    tx.send((i + 10, reason, rooms)).await.unwrap();
+                }
+            });
+        }
+        ns.disconnect(sid, DisconnectReason::ServerNSDisconnect)
+            .unwrap();
+
+        // The disconnecting handlers run first, then the socket leaves its rooms
+        for i in [10, 11] {
+            let (j, reason, rooms) = rx.recv().await.unwrap();
+            assert_eq!(i, j);
+            assert_eq!(reason, DisconnectReason::ServerNSDisconnect);
+            assert_eq!(rooms, ["room1"]);
+        }
+        for i in 0..2 {
+            let (j, reason, rooms) = rx.recv().await.unwrap();
+            assert_eq!(i, j);
+            assert_eq!(reason, DisconnectReason::ServerNSDisconnect);
+            assert!(rooms.is_empty());
+        }
+        assert_eq!(socket.state(), SocketState::Disconnected);
+    }
+    #[tokio::test]
+    async fn test_emit_with_outcome() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let (tx, mut outcome_rx) = tokio::sync::mpsc::channel(2);
+
+        sock.emit_with_outcome("lol", "foo", tx.clone()).unwrap();
+        // The socket buffer is full
+        sock.emit_with_outcome("lol", "bar", tx.clone()).unwrap();
+
+        let mut outcomes = vec![
+            outcome_rx.recv().await.unwrap(),
+            outcome_rx.recv().await.unwrap(),
+        ];
+        outcomes.sort_by_key(|o| *o as u8);
+        assert_eq!(outcomes, [SendOutcome::Sent, SendOutcome::Dropped]);
+        rx.recv().await.unwrap();
+
+        drop(rx);
+        sock.emit_with_outcome("lol", "baz", tx).unwrap();
+        assert_eq!(outcome_rx.recv().await.unwrap(), SendOutcome::Failed);
+    }
+    #[tokio::test]
+    async fn test_emit_invalid_event_name() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+
+        for event in ["", "connect", "disconnect"] {
+            assert!(matches!(
+                sock.emit(event, "foo"),
+                Err(SendError::InvalidPacket(SocketError::InvalidEventName))
+            ));
+            assert!(matches!(
+                sock.broadcast().emit(event, "foo"),
+                Err(BroadcastError::InvalidPacket(SocketError::InvalidEventName))
+            ));
+        }
+        assert!(rx.try_recv().is_err());
+
+        sock.emit("connected", "foo").unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["connected","foo"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+    }
+    #[tokio::test]
+    async fn test_socket_view() {
+        let sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        socket.join("room1").unwrap();
+        socket.extensions.insert(42usize);
+
+        let view = socket.as_view();
+        assert_eq!(view.sid(), socket.sid);
+        assert_eq!(view.ns(), "/");
+        assert_eq!(view.rooms().unwrap(), ["room1"]);
+        assert_eq!(*view.extension::<usize>().unwrap(), 42);
+        assert!(view.extension::<String>().is_none());
+    }
+    #[tokio::test]
+    async fn test_pending_acks() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+        assert!(sock.pending_acks().is_empty());
+
+        let sock1 = sock.clone();
+        let handle = tokio::spawn(async move { sock1.emit_with_ack::<Value>("test", "foo").await });
+        rx.recv().await.unwrap();
+
+        let acks = sock.pending_acks();
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].id, 1);
+
+        sock.clone()
+            .recv(PacketData::EventAck(json!("bar"), 1))
+            .unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(sock.pending_acks().is_empty());
+    }
+    #[tokio::test]
+    async fn test_send_raw_with_deadline() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        sock.send_raw("2[\"x\"]".into(), vec![], Some(deadline))
+            .unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Expiring(packet, d) => {
+                assert_eq!(d, deadline);
+                assert!(matches!(*packet, SendPacket::Message(msg) if msg == "2[\"x\"]"));
+            }
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // Packets with binary payloads are never dropped
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(2i64.into(), ns.clone());
+        sock.send_raw("51-[\"x\"]".into(), vec![vec![1]], Some(deadline))
+            .ok();
+        assert!(matches!(rx.recv().await.unwrap(), SendPacket::Message(_)));
+    }
+
+    #[tokio::test]
+    async fn test_emit_without_data() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+
+        sock.emit("x", ()).unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["x"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+        sock.emit("x", Value::Null).unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["x",null]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+    }
+    #[tokio::test]
+    async fn test_max_pending_acks() {
+        let config = NsConfig {
+            max_pending_acks: 1,
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let sock1 = sock.clone();
+        let handle = tokio::spawn(async move { sock1.emit_with_ack::<Value>("test", "foo").await });
+        rx.recv().await.unwrap();
+
+        match sock.emit_with_ack::<Value>("test", "bar").await {
+            Err(AckError::InternalError(SocketError::TooManyPendingAcks(sid))) => {
+                assert_eq!(sid, sock.sid)
+            }
+            e => panic!("should return a TooManyPendingAcks error: {:?}", e),
+        }
+
+        // Closing the socket drops the pending acks
+        sock.clone()
+            .close(DisconnectReason::TransportClose)
+            .unwrap();
+        assert!(matches!(handle.await.unwrap(), Err(AckError::Disconnected)));
+        assert!(sock.pending_acks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ack_timeout_removes_pending_ack() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, _rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns.clone());
+
+        let packet = Packet::event("/".to_string(), "test".to_string(), Value::Null);
+        let res = sock
+            .send_with_ack::<Value>(packet, Some(Duration::from_millis(10)))
+            .await;
+        assert!(matches!(res, Err(AckError::AckTimeoutError(_))));
+        assert!(sock.pending_acks().is_empty());
+    }
+    #[tokio::test]
+    async fn test_emit_with_ack_id() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let (id, ack) = sock.emit_with_ack_id::<Value>("test", "foo").unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(sock.pending_acks()[0].id, id);
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"21["test","foo"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        sock.clone()
+            .recv(PacketData::EventAck(json!(["bar"]), id))
+            .unwrap();
+        assert_eq!(ack.await.unwrap().data, json!(["bar"]));
+    }
+
+    #[tokio::test]
+    async fn test_emit_with_ack_id_dropped() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, _rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns.clone());
+
+        let (_, ack) = sock.emit_with_ack_id::<Value>("test", "foo").unwrap();
+        assert_eq!(sock.pending_acks().len(), 1);
+        // The future is never polled, the ack is discarded anyway
+        drop(ack);
+        assert!(sock.pending_acks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ack_elapsed() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, _rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let (id, ack) = sock.emit_with_ack_id::<Value>("test", "foo").unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        sock.clone()
+            .recv(PacketData::EventAck(json!(["bar"]), id))
+            .unwrap();
+        let elapsed = ack.await.unwrap().elapsed;
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < sock.config.ack_timeout);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_operators() {
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut sockets = Vec::new();
+        let mut rxs = Vec::new();
+        for i in 0..3i64 {
+            let (socket, rx) = connect_socket(&ns, i.into(), json!({}));
+            sockets.push(socket);
+            rxs.push(rx);
+        }
+        sockets[0].join("room1").unwrap();
+        sockets[1].join(["room1", "room2"]).unwrap();
+        sockets[2].join("room2").unwrap();
+        let mut received = || -> Vec<usize> {
+            rxs.iter_mut()
+                .map(|rx| std::iter::from_fn(|| rx.try_recv().ok()).count())
+                .collect()
+        };
+
+        // The sender is always excluded
+        assert_eq!(sockets[0].broadcast().emit("test", 1).unwrap(), 2);
+        assert_eq!(received(), [0, 1, 1]);
+        assert_eq!(sockets[0].to("room1").emit("test", 2).unwrap(), 1);
+        assert_eq!(received(), [0, 1, 0]);
+        assert_eq!(sockets[0].except("room2").emit("test", 3).unwrap(), 0);
+        assert_eq!(received(), [0, 0, 0]);
+        assert_eq!(sockets[1].local().to("room2").emit("test", 4).unwrap(), 1);
+        assert_eq!(received(), [0, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_emit_with_ack_timeout() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let ack = sock.emit_with_ack_timeout::<String>("test", "foo", Duration::from_secs(1));
+        let responder = async {
+            rx.recv().await.unwrap();
+            sock.clone()
+                .recv(PacketData::EventAck(json!("bar"), 1))
+                .unwrap();
+        };
+        let (ack, _) = tokio::join!(ack, responder);
+        assert_eq!(ack.unwrap().data, "bar");
+
+        let err = sock
+            .emit_with_ack_timeout::<String>("test", "foo", Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AckError::AckTimeoutError(_)));
+        assert!(sock.pending_acks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_emit_with_ack_or() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, _rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let ack = sock
+            .emit_with_ack_or("test", "foo", Duration::from_millis(10), false)
+            .await
+            .unwrap();
+        assert!(!ack.data);
+        assert!(ack.binary.is_empty());
+        assert_eq!(ack.sid, sock.sid);
+        assert!(sock.pending_acks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_emit_unserializable_data() {
+        let sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        socket.join("room1").unwrap();
+        let data = std::collections::HashMap::from([((1, 2), "value")]);
+
+        assert!(matches!(
+            socket.emit("test", &data),
+            Err(SendError::Serialize(_))
+        ));
+        assert!(matches!(
+            socket.within("room1").emit("test", &data),
+            Err(BroadcastError::Serialize(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let config = SocketIoConfig::default();
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy_with(1i64.into(), ns, Handshake::new_dummy(), config, 2);
+
+        sock.batch(|batch| {
+            batch.emit("a", 1).unwrap();
+            batch.emit("b", 2).unwrap();
+            // Nothing is sent until the batch is flushed
+            assert!(rx.try_recv().is_err());
+        })
+        .unwrap();
+        for expected in [r#"2["a",1]"#, r#"2["b",2]"#] {
+            match rx.try_recv().unwrap() {
+                SendPacket::Message(msg) => assert_eq!(msg, expected),
+                p => panic!("unexpected packet: {:?}", p),
+            }
+        }
+
+        // The buffer cannot hold the whole batch, none of the messages is sent
+        let errors = sock
+            .batch(|batch| {
+                for i in 0..3 {
+                    batch.emit("c", i).unwrap();
+                }
+            })
+            .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(rx.try_recv().is_err());
+
+        // The unsent messages are handed back in order
+        for (i, err) in errors.into_iter().enumerate() {
+            match err {
+                SendError::RetryerError(RetryerError::Remaining(retryer)) if i < 2 => {
+                    retryer.retry().unwrap()
+                }
+                SendError::RetryerError(RetryerError::Remaining(_)) => {}
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+        for expected in [r#"2["c",0]"#, r#"2["c",1]"#] {
+            match rx.try_recv().unwrap() {
+                SendPacket::Message(msg) => assert_eq!(msg, expected),
+                p => panic!("unexpected packet: {:?}", p),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_emit_many() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns);
+
+        let results = sock.emit_many([("a", 1), ("", 2)]);
+        assert!(results[0].is_ok());
+        // The empty event name is rejected
+        assert!(matches!(
+            results[1],
+            Err(SendError::InvalidPacket(SocketError::InvalidEventName))
+        ));
+
+        // The channel is full
+        let results = sock.emit_many([("b", 2)]);
+        assert!(matches!(results[0], Err(SendError::RetryerError(_))));
+        match rx.try_recv().unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["a",1]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // The channel has room for only one of the events, none of them is sent
+        let results = sock.emit_many([("b", 2), ("c", 3)]);
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Err(SendError::RetryerError(_)))));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let (sock, mut rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy_with(
+            1i64.into(),
+            ns,
+            Handshake::new_dummy(),
+            SocketIoConfig::default(),
+            4,
+        );
+        let buffer = || {
+            (0..3)
+                .map(|i| Packet::event("/".to_string(), "msg".to_string(), json!(i)))
+                .collect::<Vec<_>>()
+        };
+        let mut recv_all = || {
+            let mut msgs = Vec::new();
+            while let Ok(packet) = rx.try_recv() {
+                match packet {
+                    SendPacket::Message(msg) => msgs.push(msg),
+                    p => panic!("unexpected packet: {:?}", p),
+                }
+            }
+            msgs
+        };
+
+        // Full replay
+        assert_eq!(sock.replay(buffer(), 0, None).unwrap(), 3);
+        assert_eq!(
+            recv_all(),
+            [r#"2["msg",0]"#, r#"2["msg",1]"#, r#"2["msg",2]"#]
+        );
+
+        // The client already received the packets up to the offset 0
+        assert_eq!(sock.replay(buffer(), 0, Some(0)).unwrap(), 2);
+        assert_eq!(recv_all(), [r#"2["msg",1]"#, r#"2["msg",2]"#]);
+
+        // The client is up to date
+        assert_eq!(sock.replay(buffer(), 0, Some(2)).unwrap(), 0);
+        assert_eq!(sock.replay(buffer(), 0, Some(u64::MAX)).unwrap(), 0);
+        assert!(recv_all().is_empty());
+
+        // The buffer was trimmed, its first packet has the offset 10
+        assert_eq!(sock.replay(buffer(), 10, Some(10)).unwrap(), 2);
+        assert_eq!(recv_all(), [r#"2["msg",1]"#, r#"2["msg",2]"#]);
+        // The client missed trimmed packets, the whole buffer is sent
+        assert_eq!(sock.replay(buffer(), 10, Some(5)).unwrap(), 3);
+        assert_eq!(recv_all().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers() {
+        let config = NsConfig {
+            sequence_numbers: true,
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let socket = |handshake| -> (Socket<LocalAdapter>, _) {
+            Socket::new_rx_dummy_with(
+                1i64.into(),
+                ns.clone(),
+                handshake,
+                SocketIoConfig::default(),
+                4,
+            )
+        };
+        let recv_all = |rx: &mut Receiver<SendPacket>| {
+            let mut msgs = Vec::new();
+            while let Ok(packet) = rx.try_recv() {
+                match packet {
+                    SendPacket::Message(msg) => msgs.push(msg),
+                    p => panic!("unexpected packet: {:?}", p),
+                }
+            }
+            msgs
+        };
+
+        let (sock, mut rx) = socket(Handshake::new_dummy());
+        sock.emit("a", 1).unwrap();
+        sock.emit("b", [1, 2]).unwrap();
+        // The acknowledgements are not numbered
+        sock.send(Packet::ack("/".to_string(), json!("ok"), 1))
+            .unwrap();
+        assert_eq!(
+            recv_all(&mut rx),
+            [r#"2["a",1,0]"#, r#"2["b",1,2,1]"#, r#"31["ok"]"#]
+        );
+        assert_eq!(sock.next_sequence(), 2);
+
+        // The client reconnects after receiving the event 1, the numbering resumes from 2
+        let handshake = Handshake {
+            auth: json!({ "_seq": 1 }),
+            ..Handshake::new_dummy()
+        };
+        let (sock, mut rx) = socket(handshake);
+        let buffer = (0..3)
+            .map(|i| Packet::event("/".to_string(), "msg".to_string(), json!(i)))
+            .collect();
+        assert_eq!(sock.replay(buffer, 0, Some(1)).unwrap(), 1);
+        assert_eq!(recv_all(&mut rx), [r#"2["msg",2,2]"#]);
+
+        // A number that the client couldn't have received is ignored
+        let handshake = Handshake {
+            auth: json!({ "_seq": u64::MAX }),
+            ..Handshake::new_dummy()
+        };
+        let (sock, _rx) = socket(handshake);
+        assert_eq!(sock.next_sequence(), 0);
+
+        // The numbering stops at the largest number
+        let handshake = Handshake {
+            auth: json!({ "_seq": super::MAX_SEQUENCE - 1 }),
+            ..Handshake::new_dummy()
+        };
+        let (sock, mut rx) = socket(handshake);
+        sock.emit("a", 1).unwrap();
+        sock.emit("b", 2).unwrap();
+        assert_eq!(
+            recv_all(&mut rx),
+            [
+                r#"2["a",1,9007199254740991]"#,
+                r#"2["b",2,9007199254740991]"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inline_binary() {
+        let config = NsConfig {
+            inline_binary: true,
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let (sock, mut rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns);
+
+        let packet = Packet::bin_event("/".into(), "file".into(), json!([]), vec![vec![1, 2, 3]]);
+        sock.send(packet).unwrap();
+        // A single text packet is sent, without any binary frame
+        match rx.try_recv().unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["file","AQID"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_emit_with_ack_retry() {
+        let ns = Namespace::<LocalAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let mut sockets = Vec::new();
+        for i in 1..=3i64 {
+            let (socket, mut rx) = connect_socket(&ns, i.into(), json!({}));
+            socket.join("room1").unwrap();
+            let (attempts_tx, attempts_rx) = tokio::sync::oneshot::channel();
+            let acker = socket.clone();
+            tokio::spawn(async move {
+                let mut attempts = 0;
+                while let Ok(Some(packet)) =
+                    tokio::time::timeout(Duration::from_millis(100), rx.recv()).await
+                {
+                    attempts += 1;
+                    let msg = match packet {
+                        SendPacket::Message(msg) => msg,
+                        p => panic!("unexpected packet: {:?}", p),
+                    };
+                    // The 1st socket acks the first attempt, the 2nd one the second attempt
+                    // and the 3rd one never acks, it gets the first attempt and the 2 retries
+                    if i < 3 && attempts == i {
+                        let id = msg[1..msg.find('[').unwrap()].parse().unwrap();
+                        acker
+                            .clone()
+                            .recv(PacketData::EventAck(json!([]), id))
+                            .unwrap();
+                    }
+                }
+                attempts_tx.send(attempts).unwrap();
+            });
+            sockets.push(attempts_rx);
+        }
+
+        let sender = ns.get_socket(1i64.into()).unwrap();
+        let report = sender
+            .within("room1")
+            .timeout(Duration::from_millis(10))
+            .emit_with_ack_retry("alert", "fire", 2, Duration::from_millis(1))
+            .unwrap()
+            .await;
+        assert_eq!(report.acked.len(), 2);
+        assert!(report.acked.contains(&1i64.into()));
+        assert!(report.acked.contains(&2i64.into()));
+        assert_eq!(report.failed, [Sid::from(3i64)]);
+
+        let mut attempts = Vec::new();
+        for rx in sockets {
+            attempts.push(rx.await.unwrap());
+        }
+        assert_eq!(attempts, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_socket_id() {
+        let sid: Sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        let connect = Packet::connect_with_data("/".to_string(), sid, Default::default());
+        match connect.inner {
+            PacketData::Connect(data) => assert_eq!(data["sid"], socket.id()),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weak_socket() {
+        let sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        let weak = socket.downgrade();
+        assert_eq!(weak.sid(), sid);
+        assert!(weak.upgrade().is_some());
+
+        // The socket is still referenced here but it is disconnected
+        socket
+            .clone()
+            .close(DisconnectReason::TransportClose)
+            .unwrap();
+        while socket.state() != SocketState::Disconnected {
+            tokio::task::yield_now().await;
+        }
+        assert!(weak.upgrade().is_none());
+        drop(socket);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ack_timeout_event() {
+        let config = NsConfig {
+            ack_timeout_event: Some("ack_timeout".to_string()),
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy(1i64.into(), ns.clone());
+        let sock = Arc::new(sock);
+
+        let packet = Packet::event("/".to_string(), "test".to_string(), Value::Null);
+        let sock1 = sock.clone();
+        let handle = tokio::spawn(async move {
+            sock1
+                .send_with_ack::<Value>(packet, Some(Duration::from_millis(10)))
+                .await
+        });
+        rx.recv().await.unwrap();
+        assert!(matches!(
+            handle.await.unwrap(),
+            Err(AckError::AckTimeoutError(_))
+        ));
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(msg, r#"2["ack_timeout","test"]"#),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+        assert!(sock.pending_acks().is_empty());
+    }
+    #[tokio::test]
+    async fn test_event_normalizer() {
+        let config = NsConfig {
+            event_normalizer: Some(|e| e.to_lowercase()),
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let sock: Arc<Socket<LocalAdapter>> = Socket::new_dummy(1i64.into(), ns).into();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        sock.on("userJoin", move |_, data: Value, _, _| {
+            let tx = tx.clone();
+            async move {
+                tx.send(data).await.unwrap();
+            }
+        });
+
+        for event in ["userjoin", "USERJOIN"] {
+            sock.clone()
+                .recv(PacketData::Event(event.to_string(), json!(event), None))
+                .unwrap();
+            assert_eq!(rx.recv().await.unwrap(), json!(event));
+        }
+    }
+    #[tokio::test]
+    async fn test_forbidden_binary() {
+        let config = NsConfig {
+            allow_binary: false,
+            ..Default::default()
+        };
+        let ns = Namespace::with_config("/", Arc::new(|_| async move {}.boxed()), config);
+        let (sock, mut rx): (Socket<LocalAdapter>, _) = Socket::new_rx_dummy(1i64.into(), ns);
+        let sock = Arc::new(sock);
+
+        let (tx, mut handler_rx) = tokio::sync::mpsc::channel(1);
+        sock.on("msg", move |_, data: Value, _, _| {
+            let tx = tx.clone();
+            async move {
+                tx.send(data).await.unwrap();
+            }
+        });
+
+        let packet = BinaryPacket::incoming(json!(["data", { "_placeholder": true, "num": 0 }]));
+        sock.clone()
+            .recv(PacketData::BinaryEvent("msg".into(), packet, None))
+            .unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => assert_eq!(
+                msg,
+                r#"2["error","binary packets are not allowed in this namespace"]"#
+            ),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        // Text events are still dispatched
+        sock.clone()
+            .recv(PacketData::Event("msg".into(), json!("data"), None))
+            .unwrap();
+        assert_eq!(handler_rx.recv().await.unwrap(), json!("data"));
+        assert!(handler_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_payload_transform() {
+        struct Xor;
+        impl PayloadTransform for Xor {
+            fn encode(&self, data: &[u8]) -> Vec<u8> {
+                data.iter().map(|b| b ^ 1).collect()
+            }
+            fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+                Some(data.iter().map(|b| b ^ 1).collect())
+            }
+        }
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let config = SocketIoConfig::builder()
+            .payload_transform(Arc::new(Xor))
+            .build();
+        let (sock, mut rx): (Socket<LocalAdapter>, _) =
+            Socket::new_rx_dummy_with(1i64.into(), ns, Handshake::new_dummy(), config, 4);
+        let sock = Arc::new(sock);
+
+        sock.emit("msg", "hello").unwrap();
+        match rx.recv().await.unwrap() {
+            SendPacket::Message(msg) => {
+                assert_eq!(msg, r#"51-["msg",{"_placeholder":true,"num":0}]"#)
+            }
+            p => panic!("unexpected packet: {:?}", p),
+        }
+        let attachment = match rx.recv().await.unwrap() {
+            SendPacket::Binary(bin) => bin,
+            p => panic!("unexpected packet: {:?}", p),
+        };
+        assert_eq!(Xor.decode(&attachment).unwrap(), br#"["hello"]"#);
+
+        let (tx, mut handler_rx) = tokio::sync::mpsc::channel(1);
+        sock.on("msg", move |_, data: String, _, _| {
+            let tx = tx.clone();
+            async move {
+                tx.send(data).await.unwrap();
+            }
+        });
+        // An untransformed event is dropped
+        sock.clone()
+            .recv(PacketData::Event("msg".into(), json!(["plain"]), None))
+            .unwrap();
+        // The transformed event is dispatched by its event name
+        let packet = BinaryPacket::decoded(json!([]), vec![attachment]);
+        sock.clone()
+            .recv(PacketData::BinaryEvent("msg".into(), packet, None))
+            .unwrap();
+        assert_eq!(handler_rx.recv().await.unwrap(), "hello");
+        assert!(handler_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_own_room_from_handler() {
+        let sid = 1i64.into();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        socket.on("msg", move |socket, data: Value, _, _| {
+            let tx = tx.clone();
+            async move {
+                socket.join("room1").unwrap();
+                // The dummy socket cannot receive the packet, only the completion matters
+                socket.within("room1").emit("msg", data).ok();
+                socket.on("other", |_, _: Value, _, _| async move {});
+                tx.send(socket.rooms().unwrap()).await.unwrap();
+            }
+        });
+
+        socket
+            .clone()
+            .recv(PacketData::Event("msg".into(), json!("foo"), None))
+            .unwrap();
+        let rooms = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("the handler should not deadlock")
+            .unwrap();
+        assert_eq!(rooms, ["room1"]);
+    }
+    #[tokio::test]
+    async fn test_ack_disconnect_grace() {
+        let ns = Namespace::new("/", Arc::new(|_| async move {}.boxed()));
+        let config = SocketIoConfig::builder()
+            .ack_disconnect_grace(Duration::from_millis(50))
+            .build();
+        let (sock, mut rx) =
+            Socket::new_rx_dummy_with(1i64.into(), ns, Handshake::new_dummy(), config, 1);
+        let sock: Arc<Socket<LocalAdapter>> = sock.into();
+
+        let sock1 = sock.clone();
+        let handle = tokio::spawn(async move { sock1.emit_with_ack::<Value>("test", "foo").await });
+        rx.recv().await.unwrap();
+
+        sock.clone()
+            .close(DisconnectReason::TransportClose)
+            .unwrap();
+        // The ack is still pending during the grace period
+        assert_eq!(sock.pending_acks().len(), 1);
+
+        assert!(matches!(handle.await.unwrap(), Err(AckError::Disconnected)));
+        assert!(sock.pending_acks().is_empty());
+    }
 }
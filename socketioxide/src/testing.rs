@@ -0,0 +1,404 @@
+//! Helpers to unit test the handlers, enabled with the `testing` feature.
+//!
+//! The [`MockAdapter`] records the packets broadcast with their [`BroadcastOptions`] instead of sending them,
+//! so a test can check what a handler emitted and to which rooms, without any real client.
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{testing::{connect_socket, MockAdapter}, Namespace, PacketData};
+//! # use engineioxide::sid_generator::generate_sid;
+//! # use futures::FutureExt;
+//! # use std::sync::Arc;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let ns = Namespace::<MockAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+//! let (socket, _rx) = connect_socket(&ns, generate_sid(), serde_json::json!({}));
+//!
+//! // The code under test
+//! socket.to("bar").emit("foo", "hello").unwrap();
+//!
+//! let broadcasts = ns.adapter().broadcasts();
+//! assert_eq!(broadcasts.len(), 1);
+//! assert_eq!(broadcasts[0].opts.rooms, ["bar"]);
+//! assert!(matches!(&broadcasts[0].packet.inner, PacketData::Event(e, _, _) if e == "foo"));
+//! # }
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::{Arc, Mutex, RwLock, Weak},
+    task::Poll,
+};
+
+use engineioxide::{
+    sid_generator::Sid,
+    socket::{FlushHandle, TransportHandle},
+    SendPacket as EnginePacket,
+};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    adapter::{glob_match, paginate, Adapter, AdapterState, BroadcastOptions, Room, Session},
+    errors::{AckError, BroadcastError, Error},
+    handler::AckResponse,
+    handshake::Handshake,
+    ns::{Namespace, NsBuilder},
+    operators::RoomParam,
+    packet::Packet,
+    socket::{DisconnectReason, Socket},
+    SocketIoConfig,
+};
+
+/// A packet broadcast through the [`MockAdapter`]
+#[derive(Debug, Clone)]
+pub struct RecordedBroadcast {
+    /// The broadcast packet
+    pub packet: Packet,
+    /// The options of the broadcast, with its rooms and flags
+    pub opts: BroadcastOptions,
+    /// Whether the packet was broadcast with [`Adapter::broadcast_with_ack`]
+    pub with_ack: bool,
+}
+
+/// An adapter that records the broadcasts instead of sending them to the sockets.
+///
+/// The rooms are kept in memory like with the [`LocalAdapter`](crate::adapter::LocalAdapter),
+/// so the sockets can join and leave rooms and be selected with the [`BroadcastOptions`].
+/// It doesn't span several servers and doesn't recover the sessions.
+#[derive(Debug)]
+pub struct MockAdapter {
+    rooms: RwLock<HashMap<Room, HashSet<Sid>>>,
+    broadcasts: Mutex<Vec<RecordedBroadcast>>,
+    ns: Weak<Namespace<Self>>,
+}
+
+impl MockAdapter {
+    /// Get the packets broadcast so far, in the order of the calls.
+    pub fn broadcasts(&self) -> Vec<RecordedBroadcast> {
+        self.broadcasts.lock().unwrap().clone()
+    }
+
+    /// Take the packets broadcast so far, so the next calls to [`MockAdapter::broadcasts`]
+    /// only return the following ones.
+    pub fn take_broadcasts(&self) -> Vec<RecordedBroadcast> {
+        std::mem::take(&mut *self.broadcasts.lock().unwrap())
+    }
+
+    fn record(&self, packet: Packet, opts: BroadcastOptions, with_ack: bool) {
+        self.broadcasts.lock().unwrap().push(RecordedBroadcast {
+            packet,
+            opts,
+            with_ack,
+        });
+    }
+
+    /// Return the ids of the connected sockets that match the `opts`.
+    fn select(&self, opts: &BroadcastOptions) -> Vec<Sid> {
+        let Some(ns) = self.ns.upgrade() else {
+            return vec![];
+        };
+        let rooms = self.rooms.read().unwrap();
+        let in_rooms = |sid: &Sid, names: &[Room], patterns: &[String]| {
+            rooms.iter().any(|(room, sids)| {
+                sids.contains(sid)
+                    && (names.contains(room)
+                        || patterns.iter().any(|pattern| glob_match(pattern, room)))
+            })
+        };
+        let targeted = !opts.rooms.is_empty() || !opts.room_patterns.is_empty();
+        ns.get_sockets()
+            .into_iter()
+            .map(|socket| socket.sid)
+            .filter(|sid| {
+                let selected = if targeted {
+                    in_rooms(sid, &opts.rooms, &opts.room_patterns)
+                } else {
+                    opts.selects_all() || *sid == opts.sid
+                };
+                selected
+                    && !in_rooms(sid, &opts.except, &opts.except_patterns)
+                    && !opts.excludes_sender(*sid)
+            })
+            .collect()
+    }
+}
+
+impl Adapter for MockAdapter {
+    type Error = Infallible;
+
+    fn new(ns: Weak<Namespace<Self>>) -> Self {
+        Self {
+            rooms: RwLock::new(HashMap::new()),
+            broadcasts: Mutex::new(Vec::new()),
+            ns,
+        }
+    }
+
+    fn init(&self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn server_count(&self) -> Result<u16, Infallible> {
+        Ok(1)
+    }
+
+    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
+        let mut rooms_map = self.rooms.write().unwrap();
+        for room in rooms.into_room_iter() {
+            rooms_map.entry(room).or_default().insert(sid);
+        }
+        Ok(())
+    }
+
+    fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
+        let mut rooms_map = self.rooms.write().unwrap();
+        for room in rooms.into_room_iter() {
+            if let Some(sids) = rooms_map.get_mut(&room) {
+                sids.remove(&sid);
+                if sids.is_empty() {
+                    rooms_map.remove(&room);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn del_all(&self, sid: Sid) -> Result<(), Infallible> {
+        let mut rooms_map = self.rooms.write().unwrap();
+        rooms_map.retain(|_, sids| {
+            sids.remove(&sid);
+            !sids.is_empty()
+        });
+        Ok(())
+    }
+
+    /// Record the packet and return the number of sockets it would have been sent to.
+    fn broadcast(&self, packet: Packet, opts: BroadcastOptions) -> Result<usize, BroadcastError> {
+        let count = self.select(&opts).len();
+        self.record(packet, opts, false);
+        Ok(count)
+    }
+
+    /// Record the packet and return an empty stream, no socket acknowledges it.
+    fn broadcast_with_ack<V: DeserializeOwned>(
+        &self,
+        packet: Packet,
+        opts: BroadcastOptions,
+    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
+        self.record(packet, opts, true);
+        Ok(stream::poll_fn(|_| Poll::Ready(None)).boxed())
+    }
+
+    fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Infallible> {
+        let rooms_map = self.rooms.read().unwrap();
+        let sids: HashSet<Sid> = rooms
+            .into_room_iter()
+            .filter_map(|room| rooms_map.get(&room))
+            .flatten()
+            .copied()
+            .collect();
+        Ok(sids.into_iter().collect())
+    }
+
+    fn has_room(&self, room: &str) -> Result<bool, Infallible> {
+        Ok(self.rooms.read().unwrap().contains_key(room))
+    }
+
+    fn rooms(&self) -> Result<Vec<(Room, usize)>, Infallible> {
+        let rooms_map = self.rooms.read().unwrap();
+        Ok(rooms_map
+            .iter()
+            .map(|(room, sids)| (room.clone(), sids.len()))
+            .collect())
+    }
+
+    fn clear_room(&self, room: Room) -> Result<(), Infallible> {
+        self.rooms.write().unwrap().remove(&room);
+        Ok(())
+    }
+
+    fn rename_room(&self, from: Room, to: Room) -> Result<(), Infallible> {
+        let mut rooms_map = self.rooms.write().unwrap();
+        if from != to {
+            if let Some(sids) = rooms_map.remove(&from) {
+                rooms_map.entry(to).or_default().extend(sids);
+            }
+        }
+        Ok(())
+    }
+
+    fn export_state(&self) -> Result<AdapterState, Infallible> {
+        Ok(AdapterState {
+            rooms: self.rooms.read().unwrap().clone(),
+        })
+    }
+
+    fn import_state(&self, state: AdapterState) -> Result<(), Infallible> {
+        *self.rooms.write().unwrap() = state.rooms;
+        Ok(())
+    }
+
+    fn socket_rooms(&self, sid: Sid) -> Result<Vec<Room>, Infallible> {
+        let rooms_map = self.rooms.read().unwrap();
+        Ok(rooms_map
+            .iter()
+            .filter(|(_, sids)| sids.contains(&sid))
+            .map(|(room, _)| room.clone())
+            .collect())
+    }
+
+    fn fetch_sockets(&self, opts: BroadcastOptions) -> Result<Vec<Arc<Socket<Self>>>, Infallible> {
+        let Some(ns) = self.ns.upgrade() else {
+            return Ok(vec![]);
+        };
+        let sockets = self
+            .select(&opts)
+            .into_iter()
+            .filter_map(|sid| ns.get_socket(sid).ok())
+            .collect();
+        Ok(paginate(sockets, opts.page_bounds()))
+    }
+
+    fn add_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        for sid in self.select(&opts) {
+            self.add_all(sid, rooms.clone())?;
+        }
+        Ok(())
+    }
+
+    fn del_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        for sid in self.select(&opts) {
+            self.del(sid, rooms.clone())?;
+        }
+        Ok(())
+    }
+
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+        reason: DisconnectReason,
+    ) -> Result<(), BroadcastError> {
+        let Some(ns) = self.ns.upgrade() else {
+            return Ok(());
+        };
+        let errors: Vec<_> = self
+            .select(&opts)
+            .into_iter()
+            .filter_map(|sid| ns.disconnect(sid, reason).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.into())
+        }
+    }
+
+    fn server_side_emit(&self, _: Packet, _: BroadcastOptions) -> Result<u64, Infallible> {
+        Ok(0)
+    }
+
+    fn persist_session(&self, _: Sid, _: Sid) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn restore_session(&self, _: Sid) -> Result<Option<Session>, Infallible> {
+        Ok(None)
+    }
+}
+
+/// Build the namespace configured by a [`NsBuilder`] on its own, without any server.
+pub fn build_ns<A: Adapter>(builder: NsBuilder<A>) -> Result<Arc<Namespace<A>>, Error> {
+    let (path, handler) = builder.build()?.into_iter().next().unwrap();
+    Ok(Namespace::from_handler(path, handler))
+}
+
+/// Connect a socket without any transport to the namespace, with the given `auth` payload,
+/// and return it along with the channel of the packets sent to it.
+///
+/// The connect handler of the namespace is spawned, so it must be called within a tokio runtime.
+pub fn connect_socket<A: Adapter>(
+    ns: &Arc<Namespace<A>>,
+    sid: Sid,
+    auth: Value,
+) -> (Arc<Socket<A>>, mpsc::Receiver<EnginePacket>) {
+    let handshake = Handshake {
+        auth,
+        ..Handshake::new_dummy()
+    };
+    try_connect_socket(ns, sid, handshake, Arc::new(SocketIoConfig::default()))
+        .expect("the socket cannot connect to the namespace")
+}
+
+/// Connect a socket like [`connect_socket`], with the given handshake and server config,
+/// and return the error of the namespace if it refuses the socket.
+pub fn try_connect_socket<A: Adapter>(
+    ns: &Arc<Namespace<A>>,
+    sid: Sid,
+    handshake: Handshake,
+    config: Arc<SocketIoConfig>,
+) -> Result<(Arc<Socket<A>>, mpsc::Receiver<EnginePacket>), Error> {
+    let (tx, rx) = mpsc::channel(1024);
+    let socket = ns.clone().connect(
+        sid,
+        tx,
+        FlushHandle::default(),
+        TransportHandle::default(),
+        handshake,
+        config,
+    )?;
+    Ok((socket, rx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn test_mock_adapter() {
+        let ns = Namespace::<MockAdapter>::new("/", Arc::new(|_| async move {}.boxed()));
+        let (socket0, _rx0) = connect_socket(&ns, 0i64.into(), Value::Null);
+        let (socket1, _rx1) = connect_socket(&ns, 1i64.into(), Value::Null);
+        socket0.join(["bar", "baz"]).unwrap();
+        socket1.join("bar").unwrap();
+
+        assert_eq!(socket0.to("bar").emit("foo", 1).unwrap(), 1);
+        assert_eq!(socket0.within("bar").emit("foo", 2).unwrap(), 2);
+        assert_eq!(socket1.broadcast().except("baz").emit("foo", 3).unwrap(), 0);
+        let acks = socket0.to("bar").emit_with_ack::<Value>("foo", 4).unwrap();
+        drop(acks);
+
+        let broadcasts = ns.adapter().take_broadcasts();
+        assert_eq!(broadcasts.len(), 4);
+        assert_eq!(broadcasts[0].opts.rooms, ["bar"]);
+        assert!(broadcasts[3].with_ack);
+        assert!(ns.adapter().broadcasts().is_empty());
+
+        socket0.leave_all().unwrap();
+        assert_eq!(ns.adapter().rooms().unwrap(), [("bar".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_build_ns() {
+        let builder = Namespace::ns_builder_with_adapter::<MockAdapter>("chat")
+            .default_rooms(["lobby"])
+            .connect(|_| async move {});
+        let ns = build_ns(builder).unwrap();
+        assert_eq!(ns.path, "/chat");
+        let (socket, _rx) = connect_socket(&ns, 0i64.into(), Value::Null);
+        assert_eq!(socket.rooms().unwrap(), ["lobby"]);
+
+        let builder = Namespace::ns_builder_with_adapter::<MockAdapter>("chat");
+        assert!(matches!(build_ns(builder), Err(Error::InvalidNsConfig(_))));
+    }
+}
@@ -0,0 +1,39 @@
+//! Transformation of the event payloads at the server boundary, for example to encrypt them.
+//!
+//! A [`PayloadTransform`] is registered with [`SocketIoConfigBuilder::payload_transform`](crate::SocketIoConfigBuilder::payload_transform).
+//! It is applied to the JSON arguments and to the binary attachments of the events and acknowledgements,
+//! just before they are sent and just after they are received. The event names are kept in clear,
+//! so the events are still dispatched to their handlers.
+//!
+//! A transformed packet is sent as a binary packet: the transformed JSON arguments are its first attachment,
+//! followed by the transformed attachments. For example, the event `["message", "hello"]` is sent as
+//! `51-["message",{"_placeholder":true,"num":0}]` with the attachment `encode(b"[\"hello\"]")`.
+//! The clients must send their events and acknowledgements in the same format, other packets are dropped.
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{transform::PayloadTransform, SocketIoConfig};
+//! # use std::sync::Arc;
+//! /// A (very weak) xor cipher
+//! struct Xor(u8);
+//! impl PayloadTransform for Xor {
+//!     fn encode(&self, data: &[u8]) -> Vec<u8> {
+//!         data.iter().map(|b| b ^ self.0).collect()
+//!     }
+//!     fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+//!         Some(data.iter().map(|b| b ^ self.0).collect())
+//!     }
+//! }
+//! let config = SocketIoConfig::builder().payload_transform(Arc::new(Xor(42))).build();
+//! ```
+
+/// A transformation of the payloads, like an encryption.
+pub trait PayloadTransform: Send + Sync + 'static {
+    /// Transform the bytes of a payload before sending it.
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Restore the bytes of a received payload.
+    /// Returns `None` if the payload cannot be restored, for example if it fails an authentication check,
+    /// the packet is then dropped.
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>>;
+}